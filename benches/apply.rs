@@ -0,0 +1,66 @@
+//! Throughput of recording an event on accounts of growing size, comparing the previous
+//! clone-and-apply path of `Root::record_that` with the in-place `Aggregate::apply_mut`.
+//!
+//! ```shell
+//! cargo bench --bench apply
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payments_engine_rs::core::Aggregate;
+use payments_engine_rs::domain::{
+    Account, BankAccountRoot, Transaction, TransactionEvent, TransactionType,
+};
+use rust_decimal_macros::dec;
+
+fn account(transactions: u32) -> Account {
+    let deposit = |tx_id| Transaction {
+        status: Default::default(),
+        client_id: 1,
+        tx_id,
+        transaction_type: TransactionType::Deposit,
+        amount: Some(dec!(1)),
+    };
+
+    let mut root = BankAccountRoot::open(deposit(1)).expect("account opened");
+    for tx_id in 2..=transactions {
+        root.deposit(deposit(tx_id)).expect("deposit recorded");
+    }
+    Account::clone(&root)
+}
+
+fn record_event(c: &mut Criterion) {
+    let event = TransactionEvent::FeeWasCharged {
+        tx_id: 0,
+        fee_id: u32::MAX,
+        amount: dec!(0.0001),
+    };
+
+    let mut group = c.benchmark_group("record_event");
+    group.throughput(Throughput::Elements(1));
+    for transactions in [10, 1_000, 100_000] {
+        let account = account(transactions);
+
+        group.bench_with_input(
+            BenchmarkId::new("clone_apply", transactions),
+            &account,
+            |b, account| {
+                b.iter(|| Account::apply(Some(account.clone()), event.clone()).expect("applied"))
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("apply_mut", transactions),
+            &account,
+            |b, account| {
+                b.iter_batched_ref(
+                    || account.clone(),
+                    |account| account.apply_mut(event.clone()).expect("applied"),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, record_event);
+criterion_main!(benches);
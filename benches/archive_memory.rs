@@ -0,0 +1,67 @@
+//! Memory usage of a single long-lived account with transaction archiving.
+//!
+//! Feeds deposits through one account, archiving every transaction retained for more than
+//! `WINDOW` client transactions, and samples the retained transactions, the archived ones and the
+//! committed events along with the process resident set size. Archived transactions are stored
+//! in an [`InMemoryArchive`] and committed events are kept in memory, as they are by the engine,
+//! so the resident set size accounts for the whole run and not only the account state.
+//!
+//! Runs 100k transactions by default, pass a count for a longer run:
+//!
+//! ```shell
+//! cargo bench --bench archive_memory -- 10000000
+//! ```
+
+use payments_engine_rs::archive::{Archive, InMemoryArchive};
+use payments_engine_rs::domain::{BankAccountRoot, Transaction, TransactionType};
+use rust_decimal_macros::dec;
+
+const WINDOW: u64 = 32;
+const SAMPLES: u32 = 10;
+
+fn main() {
+    let transactions: u32 = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(100_000);
+
+    let deposit = |tx_id| Transaction {
+        status: Default::default(),
+        client_id: 1,
+        tx_id,
+        transaction_type: TransactionType::Deposit,
+        amount: Some(dec!(1)),
+    };
+
+    let mut root = BankAccountRoot::open(deposit(1)).expect("account opened");
+    let archive = InMemoryArchive::default();
+    let mut events = root.take_uncommitted_events();
+    let every = (transactions / SAMPLES).max(1);
+
+    println!("transactions,retained,archived,events,rss_kib");
+    for tx_id in 2..=transactions {
+        let archived = root
+            .archive_transactions(Some(WINDOW))
+            .expect("transactions archived");
+        archive.store(1, archived);
+        root.deposit(deposit(tx_id)).expect("deposit recorded");
+        events.extend(root.take_uncommitted_events());
+
+        if tx_id % every == 0 {
+            println!(
+                "{tx_id},{},{},{},{}",
+                root.retained_transactions(),
+                archive.len(),
+                events.len(),
+                resident_set_kib().map_or("n/a".to_owned(), |kib| kib.to_string())
+            );
+        }
+    }
+}
+
+/// The resident set size of the process, only available on Linux.
+fn resident_set_kib() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4)
+}
@@ -0,0 +1,116 @@
+//! Latency of loading and saving accounts through the event-sourced repository, and of handling
+//! a single transaction end to end through the service, for event streams of growing length.
+//!
+//! Saves and transactions are appended to a scratch [`Fork`] of the event store, so that every
+//! iteration runs against a stream of the same length.
+//!
+//! ```shell
+//! cargo bench --bench repository
+//! ```
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use payments_engine_rs::core::{Envelope, EventSourced, Fork, Getter, Handler, InMemory, Saver};
+use payments_engine_rs::domain::{
+    Account, BankAccountRoot, Transaction, TransactionEvent, TransactionType,
+};
+use payments_engine_rs::runtime::Service;
+use rust_decimal_macros::dec;
+use tokio::runtime::Runtime;
+
+const STREAM_LENGTHS: [u32; 3] = [10, 1_000, 10_000];
+
+fn deposit(tx_id: u32) -> Transaction {
+    Transaction {
+        status: Default::default(),
+        client_id: 1,
+        tx_id,
+        transaction_type: TransactionType::Deposit,
+        amount: Some(dec!(1)),
+    }
+}
+
+/// An event store holding the stream of an account opened with `events` deposits.
+fn event_store(runtime: &Runtime, events: u32) -> InMemory<u16, TransactionEvent> {
+    let event_store = InMemory::default();
+    let mut root = BankAccountRoot::open(deposit(1)).expect("account opened");
+    for tx_id in 2..=events {
+        root.deposit(deposit(tx_id)).expect("deposit recorded");
+    }
+    runtime
+        .block_on(EventSourced::<Account, _>::from(event_store.clone()).save(&mut root))
+        .expect("account saved");
+    event_store
+}
+
+fn get(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("tokio runtime");
+    let mut group = c.benchmark_group("repository_get");
+    group.throughput(Throughput::Elements(1));
+    for events in STREAM_LENGTHS {
+        let repository = EventSourced::<Account, _>::from(event_store(&runtime, events));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(events),
+            &repository,
+            |b, repository| {
+                b.to_async(&runtime)
+                    .iter(|| async { repository.get(&1).await.expect("account rehydrated") })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn save(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("tokio runtime");
+    let mut group = c.benchmark_group("repository_save");
+    group.throughput(Throughput::Elements(1));
+    for events in STREAM_LENGTHS {
+        let event_store = event_store(&runtime, events);
+        let mut root = BankAccountRoot::from(
+            runtime
+                .block_on(EventSourced::<Account, _>::from(event_store.clone()).get(&1))
+                .expect("account rehydrated"),
+        );
+        root.deposit(deposit(events + 1)).expect("deposit recorded");
+
+        group.bench_function(BenchmarkId::from_parameter(events), |b| {
+            b.to_async(&runtime).iter_batched(
+                || {
+                    let repository =
+                        EventSourced::<Account, _>::from(Fork::new(event_store.clone()));
+                    (repository, root.clone())
+                },
+                |(repository, mut root)| async move {
+                    repository.save(&mut root).await.expect("account saved")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn handle(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("tokio runtime");
+    let mut group = c.benchmark_group("service_handle");
+    group.throughput(Throughput::Elements(1));
+    for events in STREAM_LENGTHS {
+        let event_store = event_store(&runtime, events);
+        group.bench_function(BenchmarkId::from_parameter(events), |b| {
+            b.to_async(&runtime).iter_batched(
+                || Service::from(EventSourced::from(Fork::new(event_store.clone()))),
+                |service| async move {
+                    service
+                        .handle(Envelope::from(deposit(events + 1)))
+                        .await
+                        .expect("deposit handled")
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, get, save, handle);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+//! End-to-end throughput of the CLI processing generated transactions CSVs of 10k and 1M rows,
+//! from reading the file to printing the accounts.
+//!
+//! ```shell
+//! cargo bench --bench throughput
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use payments_engine_rs::generate::{Generator, Workload};
+
+const ROWS: [usize; 2] = [10_000, 1_000_000];
+
+/// Writes a generated workload of `rows` transactions, returning its path.
+fn workload(rows: usize) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("throughput-{rows}-{}.csv", std::process::id()));
+    let generator = Generator::new(Workload {
+        seed: 1,
+        clients: 1_000,
+        transactions: rows,
+        ..Workload::default()
+    })
+    .expect("valid workload");
+
+    let mut wtr = csv::Writer::from_path(&path).expect("workload file created");
+    for row in generator {
+        wtr.serialize(row).expect("row written");
+    }
+    wtr.flush().expect("workload written");
+    path
+}
+
+fn process(input: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_payments-engine-rs"))
+        .arg(input)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("engine started");
+    assert!(status.success(), "engine failed on {}", input.display());
+}
+
+fn process_csv(c: &mut Criterion) {
+    let mut group = c.benchmark_group("process_csv");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(30));
+    for rows in ROWS {
+        let input = workload(rows);
+        group.throughput(Throughput::Elements(rows as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(rows), &input, |b, input| {
+            b.iter(|| process(input))
+        });
+        std::fs::remove_file(input).expect("workload removed");
+    }
+    group.finish();
+}
+
+criterion_group!(benches, process_csv);
+criterion_main!(benches);
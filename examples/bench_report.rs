@@ -0,0 +1,107 @@
+//! Consolidates the latest criterion results under `target/criterion` into JSON Lines, one
+//! benchmark per line, so they can be appended to a history file and tracked over time.
+//!
+//! ```shell
+//! cargo bench --bench apply --bench repository --bench throughput
+//! cargo run --example bench_report -- $(git rev-parse --short HEAD) >> bench-history.jsonl
+//! ```
+//!
+//! The optional argument labels every line, e.g. with the benchmarked commit.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Deserialize)]
+struct Benchmark {
+    full_id: String,
+    group_id: String,
+    value_str: Option<String>,
+    throughput: Option<Throughput>,
+}
+
+#[derive(Deserialize)]
+enum Throughput {
+    Bytes(u64),
+    BytesDecimal(u64),
+    Elements(u64),
+}
+
+#[derive(Deserialize)]
+struct Estimates {
+    mean: Estimate,
+    median: Estimate,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+    standard_error: f64,
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> anyhow::Result<T> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Collects the `new/` result directories of every benchmark below `dir`.
+fn results(dir: &Path, found: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        if path.ends_with("new") && path.join("benchmark.json").is_file() {
+            found.push(path);
+        } else {
+            results(&path, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let label = std::env::args().nth(1);
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target/criterion");
+
+    let mut found = Vec::new();
+    results(&root, &mut found)
+        .with_context(|| format!("no benchmark results in {}", root.display()))?;
+    found.sort();
+
+    let mut out = io::stdout().lock();
+    for dir in found {
+        let benchmark: Benchmark = read(&dir.join("benchmark.json"))?;
+        let estimates: Estimates = read(&dir.join("estimates.json"))?;
+
+        // estimates are nanoseconds per iteration
+        let per_second = 1e9 / estimates.mean.point_estimate;
+        let (elements_per_second, bytes_per_second) = match benchmark.throughput {
+            Some(Throughput::Elements(n)) => (Some(n as f64 * per_second), None),
+            Some(Throughput::Bytes(n) | Throughput::BytesDecimal(n)) => {
+                (None, Some(n as f64 * per_second))
+            }
+            None => (None, None),
+        };
+
+        let line = json!({
+            "label": label,
+            "id": benchmark.full_id,
+            "group": benchmark.group_id,
+            "parameter": benchmark.value_str,
+            "mean_ns": estimates.mean.point_estimate,
+            "mean_std_err_ns": estimates.mean.standard_error,
+            "median_ns": estimates.median.point_estimate,
+            "elements_per_second": elements_per_second,
+            "bytes_per_second": bytes_per_second,
+        });
+        writeln!(out, "{line}")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,22 @@
+//! Raw bytes through the CSV reader and the service.
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use tokio::runtime::Runtime;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("tokio runtime")
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    runtime()
+        .block_on(payments_engine_rs::fuzz::ingest(data))
+        .expect("account streams recorded by the service rehydrate");
+});
@@ -0,0 +1,21 @@
+//! Arbitrary account events through the rehydration of an account.
+#![no_main]
+
+use std::sync::OnceLock;
+
+use libfuzzer_sys::fuzz_target;
+use payments_engine_rs::domain::TransactionEvent;
+use tokio::runtime::Runtime;
+
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("tokio runtime")
+    })
+}
+
+fuzz_target!(|events: Vec<TransactionEvent>| {
+    runtime().block_on(payments_engine_rs::fuzz::rehydrate(events));
+});
@@ -0,0 +1,11 @@
+#!/usr/bin/env sh
+# Builds the seed corpus of the `ingest` fuzz target from the transactions CSVs in etc/.
+# The `rehydrate` target builds its events from raw bytes and needs no seeds.
+set -eu
+
+cd "$(dirname "$0")/.."
+mkdir -p fuzz/corpus/ingest
+
+for csv in etc/*.csv; do
+    cp "$csv" "fuzz/corpus/ingest/$(basename "$csv")"
+done
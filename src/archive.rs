@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::domain::{ArchivedTransaction, TransactionType};
+
+/// Lookup index for the transactions archived out of account states.
+///
+/// Archiving keeps the account aggregates bounded to the retention window, while late disputes
+/// can still restore the transaction they reference from the index.
+pub trait Archive: Send + Sync {
+    /// Stores the transactions archived out of the given client account.
+    fn store(&self, client_id: u16, archived: Vec<ArchivedTransaction>);
+
+    /// Looks up an archived transaction of the given client account, preferring a client
+    /// transaction over a fee charged under the same id.
+    fn get(&self, client_id: u16, tx_id: u32) -> Option<ArchivedTransaction>;
+}
+
+/// Client id, transaction id and whether the transaction is a fee, since fee ids have an id
+/// space of their own.
+type ArchiveKey = (u16, u32, bool);
+
+/// An in-memory [`Archive`], keyed by client and transaction id.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryArchive {
+    index: Arc<RwLock<HashMap<ArchiveKey, ArchivedTransaction>>>,
+}
+
+impl InMemoryArchive {
+    /// Returns the number of archived transactions.
+    pub fn len(&self) -> usize {
+        self.index
+            .read()
+            .expect("acquire read lock on archive")
+            .len()
+    }
+
+    /// Whether no transaction has been archived yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Archive for InMemoryArchive {
+    fn store(&self, client_id: u16, archived: Vec<ArchivedTransaction>) {
+        let mut index = self.index.write().expect("acquire write lock on archive");
+        for entry in archived {
+            let is_fee = entry.transaction.transaction_type == TransactionType::Fee;
+            index.insert((client_id, entry.transaction.tx_id, is_fee), entry);
+        }
+    }
+
+    fn get(&self, client_id: u16, tx_id: u32) -> Option<ArchivedTransaction> {
+        let index = self.index.read().expect("acquire read lock on archive");
+        index
+            .get(&(client_id, tx_id, false))
+            .or_else(|| index.get(&(client_id, tx_id, true)))
+            .cloned()
+    }
+}
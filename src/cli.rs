@@ -1,61 +1,454 @@
-use crate::domain::Transaction;
-use clap::Parser;
+use crate::config::{
+    Backend, Config, CorruptStreamPolicy, InputFormat, Logger, LoggingConfig, OutputFormat,
+};
+use crate::domain::{Transaction, TransactionType};
+use crate::generate::{Mix, Workload, WorkloadError};
+use crate::mapping::{Column, ColumnMapping, Field, MappingError};
+use crate::telemetry::Tracer;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use opentelemetry::trace::TracerProvider as _;
+use rust_decimal::Decimal;
 use std::error::Error;
 use std::io;
 use std::io::IsTerminal;
-use std::path::PathBuf;
-use tracing::Subscriber;
-use tracing_subscriber::filter::Directive;
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::filter::{Directive, Targets};
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
 
 #[derive(Parser, Debug)]
-pub struct Args {
-    pub input: InputType,
+#[command(
+    subcommand_negates_reqs = true,
+    override_usage = "payments-engine-rs [OPTIONS] <INPUT>\n       payments-engine-rs [OPTIONS] <COMMAND>"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Arguments of the `process` command, which runs when no command is given
+    #[clap(flatten)]
+    pub(crate) process: Args,
+
+    /// TOML configuration file, overridden by environment variables and flags
+    #[arg(long, env = "PAYMENTS_CONFIG", global = true)]
+    pub(crate) config: Option<PathBuf>,
 
     #[clap(flatten)]
     pub(crate) instrumentation: Instrumentation,
 }
 
-#[derive(Clone, Default, Debug, clap::ValueEnum)]
-pub(crate) enum Logger {
-    #[default]
-    Compact,
-    Full,
-    Pretty,
-    Json,
-}
-
-impl std::fmt::Display for Logger {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let logger = match self {
-            Logger::Compact => "compact",
-            Logger::Full => "full",
-            Logger::Pretty => "pretty",
-            Logger::Json => "json",
+impl Cli {
+    /// Parses the command line, rejecting the flags of the `process` command given before any
+    /// other command than `config`, which would go unused.
+    pub(crate) fn parse_checked() -> Self {
+        let mut command = <Self as CommandFactory>::command();
+        let matches = command.get_matches_mut();
+        let cli = Self::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+        if let Some((name, _)) = matches.subcommand().filter(|(name, _)| *name != "config") {
+            let process = <Args as clap::Args>::augment_args(clap::Command::new("process"));
+            let given = process.get_arguments().find(|arg| {
+                matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+            });
+            if let Some(arg) = given {
+                let flag = match arg.get_long() {
+                    Some(long) => format!("--{long}"),
+                    None => format!("<{}>", arg.get_id().as_str().to_uppercase()),
+                };
+                command
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        format!("the argument '{flag}' cannot be used before the '{name}' command"),
+                    )
+                    .exit();
+            }
+        }
+
+        cli
+    }
+
+    /// Loads the configuration file and environment, overridden by the flags given.
+    pub(crate) fn config(&self) -> anyhow::Result<Config> {
+        let mut config = Config::load(self.config.as_ref())?;
+        match &self.command {
+            Some(Command::Process(args)) => args.apply_to(&mut config),
+            _ => self.process.apply_to(&mut config),
+        }
+        self.instrumentation.apply_to(&mut config.logging);
+
+        Ok(config)
+    }
+
+    /// Returns the command to run, defaulting to `process`.
+    pub(crate) fn command(self) -> Command {
+        self.command
+            .unwrap_or_else(|| Command::Process(Box::new(self.process)))
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Process transactions and print the resulting account balances
+    Process(Box<Args>),
+    /// Rebuild account balances from a persisted event log
+    Replay {
+        /// JSON Lines event log written by `process --event-log`
+        event_log: PathBuf,
+    },
+    /// Print the event stream of a client account, with the version of each event
+    Inspect {
+        /// JSON Lines event log written by `process --event-log`
+        event_log: PathBuf,
+        /// The client account to inspect
+        #[arg(long)]
+        client: u16,
+    },
+    /// Re-derive every account balance from a persisted event log and compare it against an accounts CSV
+    Verify {
+        /// JSON Lines event log written by `process --event-log`
+        event_log: PathBuf,
+        /// Accounts CSV, as printed by `process`
+        accounts: PathBuf,
+    },
+    /// Check the hash chains of a persisted event log, detecting altered, deleted or reordered events
+    VerifyChain {
+        /// JSON Lines event log written by `process --event-log`
+        event_log: PathBuf,
+        /// Checkpoint printed by `checkpoint`, which the hash chains must extend
+        #[arg(long, requires = "key_file")]
+        checkpoint: Option<PathBuf>,
+        /// File holding the key the checkpoint is signed with
+        #[arg(long, env = "PAYMENTS_CHECKPOINT_KEY_FILE")]
+        key_file: Option<PathBuf>,
+    },
+    /// Print a signed checkpoint of the head of the hash chains of a persisted event log, e.g. at the end of the day
+    Checkpoint {
+        /// JSON Lines event log written by `process --event-log`
+        event_log: PathBuf,
+        /// File holding the key the checkpoint is signed with
+        #[arg(long, env = "PAYMENTS_CHECKPOINT_KEY_FILE")]
+        key_file: PathBuf,
+    },
+    /// Inspect the engine configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Compare the account balances of two accounts files or event logs, exiting with 1 if they differ
+    Diff {
+        /// Accounts CSV or JSON, or a `.jsonl` event log replayed to account balances
+        left: PathBuf,
+        /// Accounts CSV or JSON, or a `.jsonl` event log replayed to account balances
+        right: PathBuf,
+        /// Consider balances equal when they differ by at most this amount
+        #[arg(long, default_value_t = Decimal::ZERO)]
+        tolerance: Decimal,
+        /// Round balances to this many decimal places before comparing them
+        #[arg(long)]
+        decimal_places: Option<u32>,
+    },
+    /// Generate a seeded, reproducible synthetic transactions workload
+    Generate(GenerateArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct GenerateArgs {
+    /// Seed of the workload, the same seed always generates the same transactions
+    #[arg(long, default_value_t = 0)]
+    pub(crate) seed: u64,
+    /// The number of client accounts
+    #[arg(long, default_value_t = 100)]
+    pub(crate) clients: u16,
+    /// The number of transactions
+    #[arg(long, default_value_t = 10_000)]
+    pub(crate) transactions: usize,
+    /// Weight of a deposit, withdrawal, authorize, capture or void in the transactions mix, e.g. `void=0`
+    #[arg(long = "mix", value_name = "TYPE=WEIGHT", value_parser = parse_pair::<TransactionType, u32>)]
+    pub(crate) mix: Vec<(TransactionType, u32)>,
+    /// Share of the transactions disputing an earlier deposit or withdrawal, or settling such a dispute
+    #[arg(long, default_value_t = 0.05)]
+    pub(crate) dispute_rate: f64,
+    /// Share of the settled disputes ending in a chargeback rather than a resolve
+    #[arg(long, default_value_t = 0.2)]
+    pub(crate) chargeback_rate: f64,
+    /// Share of deliberately invalid transactions, rejected by the engine
+    #[arg(long, default_value_t = 0.01)]
+    pub(crate) invalid_rate: f64,
+    /// Output format of the transactions
+    #[arg(long, value_enum, default_value_t)]
+    pub(crate) format: InputFormat,
+    /// File the transactions are written to, instead of stdout
+    #[arg(long)]
+    pub(crate) output: Option<PathBuf>,
+    /// Accounts CSV the expected account balances are written to, for `diff` against `process`
+    #[arg(long)]
+    pub(crate) expected: Option<PathBuf>,
+}
+
+impl GenerateArgs {
+    pub(crate) fn workload(&self) -> Result<Workload, WorkloadError> {
+        let mix = self
+            .mix
+            .iter()
+            .try_fold(Mix::default(), |mix, (transaction_type, weight)| {
+                mix.with(transaction_type.clone(), *weight)
+            })?;
+        Ok(Workload {
+            seed: self.seed,
+            clients: self.clients,
+            transactions: self.transactions,
+            mix,
+            dispute_rate: self.dispute_rate,
+            chargeback_rate: self.chargeback_rate,
+            invalid_rate: self.invalid_rate,
+        })
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum ConfigCommand {
+    /// Print the effective configuration, merged from defaults, the configuration file, environment and flags
+    Show,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    /// Transactions CSV file
+    #[arg(required = true)]
+    pub input: Option<InputType>,
+
+    /// Release authorization holds that stay open for this many later client transactions
+    #[arg(long)]
+    pub(crate) hold_expiry: Option<u64>,
+
+    /// TOML file with the fee schedule charged on accepted transactions
+    #[arg(long)]
+    pub(crate) fee_schedule: Option<PathBuf>,
+
+    /// TOML file with the credit limit of each client account
+    #[arg(long)]
+    pub(crate) credit_limits: Option<PathBuf>,
+
+    /// TOML file with the withdrawal limits of each client account
+    #[arg(long)]
+    pub(crate) withdrawal_policy: Option<PathBuf>,
+
+    /// TOML file with the dispute window and the maximum disputes per transaction
+    #[arg(long)]
+    pub(crate) dispute_rules: Option<PathBuf>,
+
+    /// Archive transactions out of the account state after this many later client transactions
+    #[arg(long)]
+    pub(crate) archive_after: Option<u64>,
+
+    /// Input format of the transactions [default: csv]
+    #[arg(long)]
+    pub(crate) input_format: Option<InputFormat>,
+
+    /// Output format of the accounts [default: csv]
+    #[arg(long)]
+    pub(crate) output_format: Option<OutputFormat>,
+
+    /// Print a summary of the run to stderr once it ends
+    #[arg(long)]
+    pub(crate) summary: bool,
+
+    /// Write a JSON summary of the run to this file once it ends
+    #[arg(long)]
+    pub(crate) summary_file: Option<PathBuf>,
+
+    /// Skip accounts with a corrupt event stream instead of aborting the run
+    #[arg(long, overrides_with = "no_quarantine")]
+    pub(crate) quarantine: bool,
+
+    /// Abort the run on an account with a corrupt event stream, whatever the configuration
+    #[arg(long, overrides_with = "quarantine")]
+    pub(crate) no_quarantine: bool,
+
+    /// Keep up to this many recently used accounts in memory in front of the event store
+    #[arg(long)]
+    pub(crate) cache_capacity: Option<NonZeroUsize>,
+
+    /// Write the recorded account events to this file as JSON Lines, for `replay`, `inspect` and `verify`
+    #[arg(long)]
+    pub(crate) event_log: Option<PathBuf>,
+
+    /// Continue from the events already recorded in the event log, appending the new ones to them
+    #[arg(long, requires = "event_log")]
+    pub(crate) resume: bool,
+
+    /// Run the transactions against a scratch copy of the event log and report their effects
+    /// instead of committing them
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+
+    /// Serve Prometheus metrics on this address while transactions are processed, e.g. `127.0.0.1:9000`
+    #[arg(long)]
+    pub(crate) metrics_listen: Option<SocketAddr>,
+
+    /// Write Prometheus metrics to this file once the run ends
+    #[arg(long)]
+    pub(crate) metrics_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub(crate) mapping: Mapping,
+}
+
+#[derive(clap::Args, Debug, Default)]
+pub(crate) struct Mapping {
+    /// TOML file mapping input columns and type literals onto transaction fields
+    #[arg(long = "mapping")]
+    pub(crate) file: Option<PathBuf>,
+    /// Treat the first input row as data instead of headers
+    #[arg(long)]
+    pub(crate) no_headers: bool,
+    /// Map a transaction field onto a source column name or zero-based index, e.g. `type=0`
+    #[arg(long = "column", value_name = "FIELD=COLUMN", value_parser = parse_pair::<Field, Column>)]
+    pub(crate) columns: Vec<(Field, Column)>,
+    /// Map a source literal onto a transaction type, e.g. `DEP=deposit`
+    #[arg(long = "type-literal", value_name = "LITERAL=TYPE", value_parser = parse_pair::<String, TransactionType>)]
+    pub(crate) type_literals: Vec<(String, TransactionType)>,
+}
+
+impl Args {
+    /// Overrides the configuration with the flags given.
+    pub(crate) fn apply_to(&self, config: &mut Config) {
+        let engine = &mut config.engine;
+        engine.hold_expiry = self.hold_expiry.or(engine.hold_expiry);
+        engine.fee_schedule = self.fee_schedule.clone().or(engine.fee_schedule.take());
+        engine.credit_limits = self.credit_limits.clone().or(engine.credit_limits.take());
+        engine.withdrawal_policy = self
+            .withdrawal_policy
+            .clone()
+            .or(engine.withdrawal_policy.take());
+        engine.dispute_rules = self.dispute_rules.clone().or(engine.dispute_rules.take());
+        engine.archive_after = self.archive_after.or(engine.archive_after);
+        engine.cache_capacity = self.cache_capacity.or(engine.cache_capacity);
+
+        if self.quarantine {
+            config.errors.corrupt_stream = CorruptStreamPolicy::Quarantine;
+        }
+        if self.no_quarantine {
+            config.errors.corrupt_stream = CorruptStreamPolicy::Abort;
+        }
+        if let Some(path) = &self.event_log {
+            config.event_store.backend = Backend::EventLog;
+            config.event_store.path = Some(path.clone());
+        }
+        config.event_store.resume |= self.resume;
+        if let Some(format) = self.input_format {
+            config.input.format = format;
+        }
+        if let Some(format) = self.output_format {
+            config.output.format = format;
+        }
+        config.output.summary |= self.summary;
+        config.output.summary_file = self
+            .summary_file
+            .clone()
+            .or(config.output.summary_file.take());
+        if let Some(path) = &self.mapping.file {
+            config.input.mapping = Some(path.clone());
+        }
+        config.metrics.listen = self.metrics_listen.or(config.metrics.listen);
+        config.metrics.file = self.metrics_file.clone().or(config.metrics.file.take());
+    }
+}
+
+impl Mapping {
+    /// Builds the effective [`ColumnMapping`], with CLI flags taking precedence over the mapping `file`.
+    pub(crate) fn column_mapping(
+        &self,
+        file: Option<&Path>,
+    ) -> Result<ColumnMapping, MappingError> {
+        let mut mapping = match file {
+            Some(path) => ColumnMapping::from_path(path)?,
+            None => ColumnMapping::default(),
         };
-        write!(f, "{}", logger)
+
+        if self.no_headers {
+            mapping.has_headers = false;
+        }
+        for (field, column) in &self.columns {
+            mapping.columns.set(*field, column.clone());
+        }
+        mapping.types.extend(self.type_literals.iter().cloned());
+
+        Ok(mapping)
     }
 }
 
+fn parse_pair<K, V>(s: &str) -> Result<(K, V), Box<dyn Error + Send + Sync + 'static>>
+where
+    K: std::str::FromStr,
+    K::Err: Error + Send + Sync + 'static,
+    V: std::str::FromStr,
+    V::Err: Error + Send + Sync + 'static,
+{
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `KEY=VALUE`, found `{s}`"))?;
+    Ok((key.parse()?, value.parse()?))
+}
+
 #[derive(clap::Args, Debug, Default)]
 pub(crate) struct Instrumentation {
     /// Enable debug logs, -vvv for trace
-    #[arg(short = 'v', env = "PAYMENTS_VERBOSITY", long, action = clap::ArgAction::Count, global = true)]
+    #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
     pub verbose: u8,
-    /// Which logger to use
-    #[arg(long, env = "PAYMENTS_LOGGER", default_value_t = Default::default(), global = true)]
-    pub(crate) logger: Logger,
+    /// Which logger to use [default: compact]
+    #[arg(long, global = true)]
+    pub(crate) logger: Option<Logger>,
     /// Tracing directives
     ///
     /// See https://docs.rs/tracing-subscriber/latest/tracing_subscriber/filter/struct.EnvFilter.html#directives
-    #[arg(long = "log-directive", global = true, env = "PAYMENTS_LOG_DIRECTIVES", value_delimiter = ',', num_args = 0..)]
+    #[arg(long = "log-directive", global = true, value_delimiter = ',', num_args = 0..)]
     pub(crate) log_directives: Vec<Directive>,
+    /// Also export the spans of every transaction to this OTLP/HTTP collector, e.g. `http://localhost:4318`
+    #[arg(long, global = true)]
+    pub(crate) otlp_endpoint: Option<String>,
 }
 
 impl Instrumentation {
+    /// Overrides the logging settings of the configuration with the flags given.
+    pub(crate) fn apply_to(&self, logging: &mut LoggingConfig) {
+        if self.verbose > 0 {
+            logging.verbosity = self.verbose;
+        }
+        if let Some(logger) = self.logger {
+            logging.logger = logger;
+        }
+        if let Some(endpoint) = &self.otlp_endpoint {
+            logging.otlp_endpoint = Some(endpoint.clone());
+        }
+        if !self.log_directives.is_empty() {
+            logging.directives = self
+                .log_directives
+                .iter()
+                .map(ToString::to_string)
+                .collect();
+        }
+    }
+
+    /// Builds the effective instrumentation from the logging settings of the configuration.
+    pub(crate) fn from_config(logging: &LoggingConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            verbose: logging.verbosity,
+            logger: Some(logging.logger),
+            log_directives: logging
+                .directives
+                .iter()
+                .map(|directive| directive.parse())
+                .collect::<Result<_, _>>()?,
+            otlp_endpoint: logging.otlp_endpoint.clone(),
+        })
+    }
+
     pub(crate) fn log_level(&self) -> String {
         match self.verbose {
             0 => "error",
@@ -65,22 +458,46 @@ impl Instrumentation {
         }
         .to_string()
     }
-    pub(crate) fn setup(&self) -> anyhow::Result<()> {
+    /// Installs the logger, and the OTLP exporter if an endpoint is set, which exports spans
+    /// until the returned [`Tracer`] is dropped.
+    pub(crate) fn setup(&self) -> anyhow::Result<Option<Tracer>> {
         let filter_layer = self.filter_layer()?;
+        let tracer = self
+            .otlp_endpoint
+            .as_deref()
+            .map(Tracer::otlp)
+            .transpose()?;
+        // spans are exported whatever the log verbosity
+        let otlp_layer = tracer.as_ref().map(|tracer| {
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer.provider().tracer(env!("CARGO_PKG_NAME")))
+                .with_filter(
+                    Targets::new()
+                        .with_target(env!("CARGO_PKG_NAME").replace('-', "_"), Level::DEBUG),
+                )
+        });
 
         let registry = tracing_subscriber::registry()
-            .with(filter_layer)
-            .with(tracing_error::ErrorLayer::default());
+            .with(tracing_error::ErrorLayer::default())
+            .with(otlp_layer);
 
         // `try_init` called inside `match` since `with` changes the type
-        match self.logger {
-            Logger::Compact => registry.with(self.fmt_layer_compact()).try_init()?,
-            Logger::Full => registry.with(self.fmt_layer_full()).try_init()?,
-            Logger::Pretty => registry.with(self.fmt_layer_pretty()).try_init()?,
-            Logger::Json => registry.with(self.fmt_layer_json()).try_init()?,
+        match self.logger.unwrap_or_default() {
+            Logger::Compact => registry
+                .with(self.fmt_layer_compact().with_filter(filter_layer))
+                .try_init()?,
+            Logger::Full => registry
+                .with(self.fmt_layer_full().with_filter(filter_layer))
+                .try_init()?,
+            Logger::Pretty => registry
+                .with(self.fmt_layer_pretty().with_filter(filter_layer))
+                .try_init()?,
+            Logger::Json => registry
+                .with(self.fmt_layer_json().with_filter(filter_layer))
+                .try_init()?,
         }
 
-        Ok(())
+        Ok(tracer)
     }
 
     pub(crate) fn filter_layer(&self) -> anyhow::Result<EnvFilter> {
@@ -175,6 +592,8 @@ pub enum ProcessingError {
     #[error(transparent)]
     DispatchError(#[from] flume::SendError<Transaction>),
     #[error(transparent)]
+    Mapping(#[from] MappingError),
+    #[error(transparent)]
     Io(#[from] io::Error),
 }
 
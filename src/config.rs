@@ -0,0 +1,513 @@
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::runtime::RejectionPolicy;
+
+/// Prefix of the environment variables overriding configuration keys,
+/// e.g. `PAYMENTS_RUNTIME__DISPATCH_CHANNEL_SIZE=1024` sets `dispatch_channel_size` of `[runtime]`.
+const ENV_PREFIX: &str = "PAYMENTS_";
+
+/// Environment variables of earlier versions, kept as aliases of the configuration keys they
+/// map onto. The `PAYMENTS_<SECTION>__<KEY>` variables take precedence over them.
+const ENV_ALIASES: &[(&str, &str, &str)] = &[
+    ("PAYMENTS_VERBOSITY", "logging", "verbosity"),
+    ("PAYMENTS_LOGGER", "logging", "logger"),
+    ("PAYMENTS_LOG_DIRECTIVES", "logging", "directives"),
+];
+
+/// Engine settings, layered from defaults, a TOML configuration file, environment variables
+/// and CLI flags, each layer overriding the previous ones.
+///
+/// ```toml
+/// [engine]
+/// hold_expiry = 10
+/// fee_schedule = "etc/fee_schedule.toml"
+///
+/// [output]
+/// format = "json"
+/// summary_file = "summary.json"
+///
+/// [event_store]
+/// backend = "event_log"
+/// path = "events.jsonl"
+/// resume = true
+///
+/// [runtime]
+/// dispatch_channel_size = 1024
+///
+/// [errors]
+/// corrupt_stream = "quarantine"
+///
+/// [logging]
+/// verbosity = 1
+/// logger = "json"
+///
+/// [metrics]
+/// listen = "127.0.0.1:9000"
+/// file = "metrics.prom"
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub engine: EngineConfig,
+    pub input: InputConfig,
+    pub output: OutputConfig,
+    pub event_store: EventStoreConfig,
+    pub runtime: RuntimeConfig,
+    pub errors: ErrorConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+}
+
+/// Account rules applied by the engine.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// The number of later client transactions after which authorization holds are released.
+    pub hold_expiry: Option<u64>,
+    /// TOML file with the fee schedule charged on accepted transactions.
+    pub fee_schedule: Option<PathBuf>,
+    /// TOML file with the credit limit of each client account.
+    pub credit_limits: Option<PathBuf>,
+    /// TOML file with the withdrawal limits of each client account.
+    pub withdrawal_policy: Option<PathBuf>,
+    /// TOML file with the dispute window and the maximum disputes per transaction.
+    pub dispute_rules: Option<PathBuf>,
+    /// The number of later client transactions after which transactions are archived.
+    pub archive_after: Option<u64>,
+    /// The number of recently used accounts kept in memory in front of the event store.
+    pub cache_capacity: Option<NonZeroUsize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InputConfig {
+    pub format: InputFormat,
+    /// TOML file mapping CSV columns and type literals onto transaction fields.
+    pub mapping: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// Transactions CSV, see the column mapping for other layouts.
+    #[default]
+    Csv,
+    /// One JSON transaction per line, with the same fields as the CSV headers.
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    pub format: OutputFormat,
+    /// Print a summary of the run to stderr once it ends.
+    pub summary: bool,
+    /// JSON file a summary of the run is written to once it ends.
+    pub summary_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Accounts CSV.
+    #[default]
+    Csv,
+    /// JSON array of accounts.
+    Json,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EventStoreConfig {
+    pub backend: Backend,
+    /// The event log file of the [`Backend::EventLog`] backend.
+    pub path: Option<PathBuf>,
+    /// Whether `process` continues from the events already recorded in the event log, appending
+    /// its own events to them rather than overwriting them.
+    pub resume: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// Account events only live for the duration of the run.
+    #[default]
+    Memory,
+    /// Account events are recorded to a JSON Lines event log, which dry runs fork.
+    EventLog,
+}
+
+impl EventStoreConfig {
+    /// Returns the event log file of the [`Backend::EventLog`] backend.
+    pub fn event_log(&self) -> Result<Option<&Path>, ConfigError> {
+        match (self.backend, &self.path) {
+            (Backend::Memory, _) if self.resume => Err(ConfigError::Invalid(
+                "resuming requires the event_log backend".to_owned(),
+            )),
+            (Backend::Memory, _) => Ok(None),
+            (Backend::EventLog, Some(path)) => Ok(Some(path)),
+            (Backend::EventLog, None) => Err(ConfigError::Invalid(
+                "the event_log backend requires an event_store.path".to_owned(),
+            )),
+        }
+    }
+}
+
+/// Capacities of the bounded channels transactions go through.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    /// The number of parsed transactions buffered ahead of the runtime.
+    pub ingest_channel_size: usize,
+    /// The number of transactions buffered between the connectors and the service.
+    pub dispatch_channel_size: usize,
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            ingest_channel_size: 128 * 1024,
+            dispatch_channel_size: 8192,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ErrorConfig {
+    pub corrupt_stream: CorruptStreamPolicy,
+    pub rejected_transaction: RejectionPolicy,
+}
+
+/// What to do with an account whose event stream can't be rehydrated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CorruptStreamPolicy {
+    /// Abort the run.
+    #[default]
+    Abort,
+    /// Skip the account and its transactions.
+    Quarantine,
+}
+
+/// Where the Prometheus metrics of a run are exported to, no metrics are recorded when unset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address of the local endpoint serving the metrics while transactions are processed.
+    pub listen: Option<SocketAddr>,
+    /// File the metrics are written to once the run ends.
+    pub file: Option<PathBuf>,
+}
+
+impl MetricsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.listen.is_some() || self.file.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `0` logs errors, `1` info, `2` debug and more trace.
+    pub verbosity: u8,
+    pub logger: Logger,
+    /// Tracing directives, overriding the verbosity.
+    pub directives: Vec<String>,
+    /// OTLP/HTTP collector the spans of every transaction are exported to.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Logger {
+    #[default]
+    Compact,
+    Full,
+    Pretty,
+    Json,
+}
+
+impl std::fmt::Display for Logger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let logger = match self {
+            Logger::Compact => "compact",
+            Logger::Full => "full",
+            Logger::Pretty => "pretty",
+            Logger::Json => "json",
+        };
+        write!(f, "{}", logger)
+    }
+}
+
+impl Config {
+    /// Loads the defaults overridden by the configuration file, if any, then by the environment.
+    pub fn load(path: Option<impl AsRef<Path>>) -> Result<Self, ConfigError> {
+        let file = match path {
+            Some(path) => Some(toml::from_str(&std::fs::read_to_string(path)?)?),
+            None => None,
+        };
+        Self::layered(file, std::env::vars())
+    }
+
+    fn layered(
+        file: Option<toml::Table>,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut config = toml::Table::try_from(Self::default())?;
+        if let Some(file) = file {
+            merge(&mut config, file);
+        }
+        let env = env_table(env, &config);
+        merge(&mut config, env);
+
+        Ok(config.try_into()?)
+    }
+}
+
+/// Collects the `PAYMENTS_<SECTION>__<KEY>` environment variables and their aliases into a
+/// table, leaving out the variables that don't name a configuration key.
+///
+/// Values are parsed as TOML, falling back on plain strings, or on comma-separated strings for
+/// the list keys of the `defaults` table.
+fn env_table(
+    env: impl IntoIterator<Item = (String, String)>,
+    defaults: &toml::Table,
+) -> toml::Table {
+    let mut aliased = Vec::new();
+    let mut keyed = Vec::new();
+    for (name, value) in env {
+        if let Some((_, section, key)) = ENV_ALIASES.iter().find(|(alias, ..)| *alias == name) {
+            aliased.push((section.to_string(), key.to_string(), value));
+        } else if let Some((section, key)) = name
+            .strip_prefix(ENV_PREFIX)
+            .and_then(|name| name.split_once("__"))
+        {
+            let (section, key) = (section.to_lowercase(), key.to_lowercase());
+            if section_keys(&section).contains(&key.as_str()) {
+                keyed.push((section, key, value));
+            }
+        }
+    }
+
+    let mut table = toml::Table::new();
+    for (section, key, value) in aliased.into_iter().chain(keyed) {
+        let is_list = defaults
+            .get(&section)
+            .and_then(|section| section.get(&key))
+            .is_some_and(toml::Value::is_array);
+        let value = match toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+        {
+            Some(value) if !is_list || value.is_array() => value,
+            _ if is_list => toml::Value::Array(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| toml::Value::String(item.to_owned()))
+                    .collect(),
+            ),
+            _ => toml::Value::String(value),
+        };
+
+        if let Some(section) = table
+            .entry(section)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+        {
+            section.insert(key, value);
+        }
+    }
+    table
+}
+
+/// The keys of a configuration section, as named by its `Deserialize` implementation.
+fn section_keys(section: &str) -> &'static [&'static str] {
+    match section {
+        "engine" => fields::<EngineConfig>(),
+        "input" => fields::<InputConfig>(),
+        "output" => fields::<OutputConfig>(),
+        "event_store" => fields::<EventStoreConfig>(),
+        "runtime" => fields::<RuntimeConfig>(),
+        "errors" => fields::<ErrorConfig>(),
+        "logging" => fields::<LoggingConfig>(),
+        "metrics" => fields::<MetricsConfig>(),
+        _ => &[],
+    }
+}
+
+/// The field names a derived `Deserialize` implementation of a struct asks for.
+fn fields<T: for<'de> Deserialize<'de>>() -> &'static [&'static str] {
+    struct Fields<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> serde::Deserializer<'de> for Fields<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: serde::de::Visitor<'de>>(
+            self,
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("only structs have fields"))
+        }
+
+        fn deserialize_struct<V: serde::de::Visitor<'de>>(
+            self,
+            _: &'static str,
+            fields: &'static [&'static str],
+            _: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("only the fields are read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+            option unit unit_struct newtype_struct seq tuple tuple_struct map enum identifier
+            ignored_any
+        }
+    }
+
+    let mut fields = &[][..];
+    let _ = T::deserialize(Fields(&mut fields));
+    fields
+}
+
+/// Deep merges the `overrides` table into the `base` table.
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("invalid configuration: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+    #[error("failed to encode configuration: {0}")]
+    Encode(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_overrides_the_configuration_file() {
+        let file = toml::from_str(
+            r#"
+            [engine]
+            hold_expiry = 3
+
+            [runtime]
+            dispatch_channel_size = 16
+
+            [errors]
+            corrupt_stream = "quarantine"
+            "#,
+        )
+        .expect("valid configuration file");
+        let env = [
+            ("PAYMENTS_RUNTIME__DISPATCH_CHANNEL_SIZE", "32"),
+            ("PAYMENTS_OUTPUT__FORMAT", "json"),
+            ("PAYMENTS_HOLD_EXPIRY", "5"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+
+        let config = Config::layered(Some(file), env).expect("valid configuration");
+
+        assert_eq!(Some(3), config.engine.hold_expiry);
+        assert_eq!(32, config.runtime.dispatch_channel_size);
+        assert_eq!(128 * 1024, config.runtime.ingest_channel_size);
+        assert_eq!(OutputFormat::Json, config.output.format);
+        assert_eq!(
+            CorruptStreamPolicy::Quarantine,
+            config.errors.corrupt_stream
+        );
+    }
+
+    #[test]
+    fn it_rejects_unknown_keys() {
+        let file = toml::from_str("[runtime]\nchannel_size = 32").expect("valid TOML");
+
+        assert!(matches!(
+            Config::layered(Some(file), []),
+            Err(ConfigError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn it_ignores_unknown_environment_variables() {
+        let env = [
+            ("PAYMENTS_RUNTIME__CHANNEL_SIZE", "32"),
+            ("PAYMENTS_UNRELATED__KEY", "value"),
+            ("PAYMENTS_RUNTIME__DISPATCH_CHANNEL_SIZE", "64"),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::layered(None, env).expect("valid configuration");
+
+        assert_eq!(64, config.runtime.dispatch_channel_size);
+    }
+
+    #[test]
+    fn it_reads_the_logging_aliases_and_comma_separated_lists() {
+        let env = [
+            ("PAYMENTS_VERBOSITY", "2"),
+            ("PAYMENTS_LOGGER", "json"),
+            (
+                "PAYMENTS_LOG_DIRECTIVES",
+                "payments_engine_rs=debug, tokio=warn",
+            ),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::layered(None, env).expect("valid configuration");
+
+        assert_eq!(2, config.logging.verbosity);
+        assert_eq!(Logger::Json, config.logging.logger);
+        assert_eq!(
+            vec!["payments_engine_rs=debug", "tokio=warn"],
+            config.logging.directives
+        );
+
+        // the sectioned variables take precedence, and lists can be TOML arrays too
+        let env = [
+            ("PAYMENTS_LOGGING__VERBOSITY", "1"),
+            ("PAYMENTS_VERBOSITY", "2"),
+            ("PAYMENTS_LOGGING__DIRECTIVES", r#"["warn"]"#),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::layered(None, env).expect("valid configuration");
+
+        assert_eq!(1, config.logging.verbosity);
+        assert_eq!(vec!["warn"], config.logging.directives);
+    }
+
+    #[test]
+    fn it_rejects_resuming_without_an_event_log() {
+        let env = [("PAYMENTS_EVENT_STORE__RESUME", "true")]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()));
+        let config = Config::layered(None, env).expect("valid configuration");
+
+        assert!(matches!(
+            config.event_store.event_log(),
+            Err(ConfigError::Invalid(_))
+        ));
+    }
+}
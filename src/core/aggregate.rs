@@ -33,6 +33,21 @@ pub trait Aggregate: Sized + Send + Sync + Clone {
     /// The method can return an error if the event to apply is unexpected
     /// given the current state of the Aggregate.
     fn apply(state: Option<Self>, event: Self::Event) -> Result<Self, Self::Error>;
+
+    /// Mutates the state of an existing Aggregate in place through a Domain Event.
+    ///
+    /// When an error is returned, the Aggregate state must be left as it was before
+    /// the call. The default implementation applies the event to a clone of the state
+    /// through [`Aggregate::apply`], implementations can override it to avoid the clone.
+    ///
+    /// # Errors
+    ///
+    /// The method can return an error if the event to apply is unexpected
+    /// given the current state of the Aggregate.
+    fn apply_mut(&mut self, event: Self::Event) -> Result<(), Self::Error> {
+        *self = Self::apply(Some(self.clone()), event)?;
+        Ok(())
+    }
 }
 
 /// Represents a piece of domain data that occurs in the system.
@@ -41,7 +56,6 @@ pub trait Message {
     fn name(&self) -> &'static str;
 }
 
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Envelope<T>
 where
@@ -112,6 +126,12 @@ where
         self.aggregate.aggregate_id()
     }
 
+    /// Returns the uncommitted, recorded Domain [Event]s of the [Root], without
+    /// resetting the internal list.
+    pub fn uncommitted_events(&self) -> &[Envelope<T::Event>] {
+        &self.recorded_events
+    }
+
     /// Returns the list of uncommitted, recorded Domain [Event]s from the [Root]
     /// and resets the internal list to its default value.
     pub fn take_uncommitted_events(&mut self) -> Vec<Envelope<T::Event>> {
@@ -139,7 +159,7 @@ where
     /// The method can return an error if the event to apply is unexpected
     /// given the current state of the Aggregate.
     pub fn record_that(&mut self, event: Envelope<T::Event>) -> Result<(), T::Error> {
-        self.aggregate = T::apply(Some(self.aggregate.clone()), event.message.clone())?;
+        self.aggregate.apply_mut(event.message.clone())?;
         self.recorded_events.push(event);
         self.version += 1;
 
@@ -155,8 +175,13 @@ pub enum RehydrateError<T, I> {
     ///
     /// This usually implies the Event Stream for the [Aggregate]
     /// contains corrupted or unexpected data.
-    #[error("failed to apply domain event while rehydrating aggregate: {0}")]
-    Domain(#[source] T),
+    #[error("failed to apply domain event {version} while rehydrating aggregate: {source}")]
+    Domain {
+        /// The version of the Domain Event that could not be applied.
+        version: Version,
+        #[source]
+        source: T,
+    },
 
     /// This error is returned by [Root::rehydrate_async] when the underlying
     /// [futures::TryStream] has returned an error.
@@ -187,12 +212,15 @@ where
         stream
             .map_err(RehydrateError::Inner)
             .try_fold(None, |ctx: Option<Root<T>>, event| async {
+                let version = ctx.as_ref().map_or(1, |ctx| ctx.version + 1);
                 let new_ctx_result = match ctx {
                     None => Root::<T>::rehydrate_from(event),
                     Some(ctx) => ctx.apply_rehydrated_event(event),
                 };
 
-                Ok(Some(new_ctx_result.map_err(RehydrateError::Domain)?))
+                Ok(Some(new_ctx_result.map_err(|source| {
+                    RehydrateError::Domain { version, source }
+                })?))
             })
             .await
     }
@@ -225,7 +253,7 @@ where
         mut self,
         event: Envelope<T::Event>,
     ) -> Result<Root<T>, T::Error> {
-        self.aggregate = T::apply(Some(self.aggregate), event.message)?;
+        self.aggregate.apply_mut(event.message)?;
         self.version += 1;
 
         Ok(self)
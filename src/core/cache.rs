@@ -0,0 +1,241 @@
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::core::repository::{GetError, Getter, SaveError, Saver, VersionGetter};
+use crate::core::{Aggregate, Root, Version};
+
+/// Hit and miss counters of a [Cached] repository.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CacheMetrics {
+    /// Number of [Aggregate Roots][Root] served from the cache.
+    pub hits: u64,
+    /// Number of [Aggregate Roots][Root] loaded from the decorated repository.
+    pub misses: u64,
+    /// Number of misses caused by a cached [Root] that was behind the data store.
+    pub stale: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+}
+
+/// Write-through cache decorator for a [Repository][crate::core::repository::Repository],
+/// keeping the most recently saved [Aggregate Roots][Root] in memory.
+///
+/// A cached [Root] is only served when its [Version] matches the one in the data store,
+/// so Domain Events appended by someone else are never missed. Cached Roots are handed out
+/// on [`Getter::get`] and taken back on [`Saver::save_owned`], so a Root that is never saved
+/// back, or only saved through [`Saver::save`], is simply loaded from the data store next time.
+#[derive(Debug, Clone)]
+pub struct Cached<T, R>
+where
+    T: Aggregate,
+    T::Id: Hash + Eq,
+{
+    inner: R,
+    entries: Arc<Mutex<LruCache<T::Id, Root<T>>>>,
+    counters: Arc<Counters>,
+}
+
+impl<T, R> Cached<T, R>
+where
+    T: Aggregate,
+    T::Id: Hash + Eq,
+{
+    /// Decorates the `inner` repository with a cache of at most `capacity` [Aggregate Roots][Root].
+    pub fn new(inner: R, capacity: NonZeroUsize) -> Self {
+        Self {
+            inner,
+            entries: Arc::new(Mutex::new(LruCache::new(capacity))),
+            counters: Arc::default(),
+        }
+    }
+
+    /// Returns the hit and miss counters of the cache so far.
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            stale: self.counters.stale.load(Ordering::Relaxed),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, LruCache<T::Id, Root<T>>> {
+        self.entries
+            .lock()
+            .expect("acquire lock on aggregate cache entries")
+    }
+}
+
+#[async_trait]
+impl<T, R> Getter<T> for Cached<T, R>
+where
+    T: Aggregate,
+    T::Id: Hash + Eq,
+    R: Getter<T> + VersionGetter<T>,
+{
+    async fn get(&self, id: &T::Id) -> Result<Root<T>, GetError> {
+        let cached = self.entries().pop(id);
+        if let Some(root) = cached {
+            if self.inner.version(id).await? == Some(root.version()) {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(root);
+            }
+            self.counters.stale.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        self.inner.get(id).await
+    }
+}
+
+#[async_trait]
+impl<T, R> VersionGetter<T> for Cached<T, R>
+where
+    T: Aggregate,
+    T::Id: Hash + Eq,
+    R: VersionGetter<T>,
+{
+    async fn version(&self, id: &T::Id) -> Result<Option<Version>, GetError> {
+        self.inner.version(id).await
+    }
+}
+
+#[async_trait]
+impl<T, R> Saver<T> for Cached<T, R>
+where
+    T: Aggregate,
+    T::Id: Hash + Eq + Clone,
+    R: Saver<T>,
+{
+    async fn save(&self, root: &mut Root<T>) -> Result<(), SaveError> {
+        // the caller keeps the Root, so any cached one is outdated
+        self.entries().pop(root.aggregate_id());
+        self.inner.save(root).await
+    }
+
+    async fn save_owned(&self, mut root: Root<T>) -> Result<(), SaveError>
+    where
+        T: 'async_trait,
+    {
+        let id = root.aggregate_id().clone();
+        match self.inner.save(&mut root).await {
+            Ok(()) => {
+                self.entries().put(id, root);
+                Ok(())
+            }
+            Err(err) => {
+                self.entries().pop(&id);
+                Err(err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::{Appender, Check, Envelope, EventSourced, InMemory};
+    use crate::domain::{Account, BankAccountRoot, Transaction, TransactionEvent, TransactionType};
+
+    fn deposit(client_id: u16, tx_id: u32) -> Transaction {
+        Transaction {
+            status: Default::default(),
+            client_id,
+            tx_id,
+            transaction_type: TransactionType::Deposit,
+            amount: Some(dec!(1)),
+        }
+    }
+
+    #[tokio::test]
+    async fn it_serves_saved_roots_until_the_stream_moves_on() {
+        let event_store = InMemory::<u16, TransactionEvent>::default();
+        let repository = Cached::new(
+            EventSourced::<Account, _>::from(event_store.clone()),
+            NonZeroUsize::new(1).expect("non-zero capacity"),
+        );
+
+        let root = BankAccountRoot::open(deposit(1, 1)).expect("account opened");
+        repository.save_owned(root.into()).await.expect("saved");
+        let mut root = BankAccountRoot::from(repository.get(&1).await.expect("cached"));
+        root.deposit(deposit(1, 2)).expect("deposit");
+        repository.save_owned(root.into()).await.expect("saved");
+        assert_eq!(2, repository.get(&1).await.expect("cached").version());
+        assert_eq!(
+            CacheMetrics {
+                hits: 2,
+                misses: 0,
+                stale: 0,
+            },
+            repository.metrics()
+        );
+
+        // an external append makes the cached root stale
+        let root = BankAccountRoot::from(repository.get(&1).await.expect("reloaded"));
+        repository.save_owned(root.into()).await.expect("saved");
+        event_store
+            .append(
+                1,
+                Check::MustBe(2),
+                vec![Envelope::from(TransactionEvent::FeeWasCharged {
+                    tx_id: 3,
+                    fee_id: u32::MAX,
+                    amount: dec!(1),
+                })],
+            )
+            .await
+            .expect("appended");
+        assert_eq!(3, repository.get(&1).await.expect("reloaded").version());
+        assert_eq!(1, repository.metrics().stale);
+
+        // the least recently saved root is evicted
+        let other = BankAccountRoot::open(deposit(2, 4)).expect("account opened");
+        repository.save_owned(other.into()).await.expect("saved");
+        let root = BankAccountRoot::from(repository.get(&1).await.expect("loaded"));
+        repository.save_owned(root.into()).await.expect("saved");
+        let _ = repository.get(&2).await.expect("loaded");
+        assert_eq!(
+            CacheMetrics {
+                hits: 2,
+                misses: 4,
+                stale: 1,
+            },
+            repository.metrics()
+        );
+    }
+
+    #[tokio::test]
+    async fn it_drops_roots_saved_by_reference() {
+        let repository = Cached::new(
+            EventSourced::<Account, _>::from(InMemory::<u16, TransactionEvent>::default()),
+            NonZeroUsize::new(1).expect("non-zero capacity"),
+        );
+
+        let root = BankAccountRoot::open(deposit(1, 1)).expect("account opened");
+        repository.save_owned(root.into()).await.expect("saved");
+        let mut root = BankAccountRoot::from(repository.get(&1).await.expect("cached"));
+        root.deposit(deposit(1, 2)).expect("deposit");
+        repository.save(&mut root).await.expect("saved");
+        assert_eq!(2, repository.get(&1).await.expect("loaded").version());
+        assert_eq!(
+            CacheMetrics {
+                hits: 1,
+                misses: 1,
+                stale: 0,
+            },
+            repository.metrics()
+        );
+    }
+}
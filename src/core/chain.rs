@@ -0,0 +1,683 @@
+//! Tamper-evident hash chains over the [Persisted] Domain Events of an Event Store.
+//!
+//! Every event is linked to the previous event of its Event Stream, and to the previous event
+//! appended to the store whatever its stream, by hashing its content along with their hashes.
+//! Altering, deleting or reordering a past event breaks every link after it, and a signed
+//! [Checkpoint] of the heads of the chains catches the removal of the latest events.
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest as _, Sha256};
+use tokio::sync::Mutex;
+
+use crate::core::store::{
+    AppendError, Appender, Check, ConflictError, Importer, Persisted, Store, Stream, Streamer,
+    Version, VersionSelect,
+};
+use crate::core::{Envelope, Message};
+
+/// A SHA-256 hash, written as hex.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Digest([u8; 32]);
+
+impl Digest {
+    /// Hashes the content of an event: its stream, version and Domain Event, encoded as JSON.
+    pub fn of<Id, Evt>(persisted: &Persisted<Id, Evt>) -> Result<Self, serde_json::Error>
+    where
+        Id: Serialize,
+        Evt: Message + Serialize,
+    {
+        let mut hasher = HashWriter(Sha256::new());
+        serde_json::to_writer(
+            &mut hasher,
+            &(&persisted.stream_id, persisted.version, &persisted.event),
+        )?;
+        Ok(Self(hasher.0.finalize().into()))
+    }
+
+    /// Chains the `content` hash of an event to the hash of the previous event.
+    fn chain(&self, content: &Digest) -> Self {
+        Self(
+            Sha256::new()
+                .chain_update(self.0)
+                .chain_update(content.0)
+                .finalize()
+                .into(),
+        )
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({self})")
+    }
+}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(encoded, &mut bytes).map_err(serde::de::Error::custom)?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Feeds the JSON encoding of an event straight into the hasher.
+struct HashWriter(Sha256);
+
+impl io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The links of a [Persisted] event into the hash chains of its Event Store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    /// The position of the event among all the events appended to the store, from 1.
+    pub sequence: u64,
+    /// Hash of the event content chained to the `stream` hash of the previous event of its stream.
+    pub stream: Digest,
+    /// Hash of the event content chained to the `global` hash of the previous event of the store.
+    pub global: Digest,
+}
+
+/// The last event of the global hash chain, `0` and a zero hash for an empty store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    pub sequence: u64,
+    pub hash: Digest,
+}
+
+/// The heads of the hash chains of an Event Store, which [Appender][crate::core::Appender]
+/// implementations advance with every event they append.
+#[derive(Debug, Clone)]
+pub struct Chain<Id> {
+    streams: HashMap<Id, Digest>,
+    head: Head,
+}
+
+impl<Id> Default for Chain<Id> {
+    fn default() -> Self {
+        Self {
+            streams: HashMap::default(),
+            head: Head::default(),
+        }
+    }
+}
+
+impl<Id> Chain<Id>
+where
+    Id: Clone + Eq + Hash,
+{
+    /// Links the next event of the stream `id`, whose content hashes to `content`.
+    pub fn link(&mut self, id: &Id, content: &Digest) -> Link {
+        let link = self.links(id, std::slice::from_ref(content))[0];
+        self.advance(id, &link);
+        link
+    }
+
+    /// Links the next events of the stream `id`, whose contents hash to `contents`, without
+    /// moving the heads past them.
+    pub fn links(&self, id: &Id, contents: &[Digest]) -> Vec<Link> {
+        let mut stream = self.streams.get(id).copied().unwrap_or_default();
+        let mut head = self.head;
+        contents
+            .iter()
+            .map(|content| {
+                let link = Link {
+                    sequence: head.sequence + 1,
+                    stream: stream.chain(content),
+                    global: head.hash.chain(content),
+                };
+                stream = link.stream;
+                head = Head {
+                    sequence: link.sequence,
+                    hash: link.global,
+                };
+                link
+            })
+            .collect()
+    }
+
+    /// Moves the heads past an event linked already, e.g. loaded from a persistent store.
+    pub fn advance(&mut self, id: &Id, link: &Link) {
+        self.streams.insert(id.clone(), link.stream);
+        if link.sequence > self.head.sequence {
+            self.head = Head {
+                sequence: link.sequence,
+                hash: link.global,
+            };
+        }
+    }
+
+    pub fn head(&self) -> Head {
+        self.head
+    }
+}
+
+/// Decorator for an Event [Store] that links every appended Domain Event into the hash chains
+/// of the store, whatever the decorated [Importer] keeps them in.
+///
+/// Appends are serialized, so that the global chain follows the order the events are stored
+/// in. Events [imported][Importer::import] through the decorator keep their links and move
+/// the heads past them.
+#[derive(Debug, Clone)]
+pub struct Chained<S, Id> {
+    store: S,
+    chain: Arc<Mutex<Chain<Id>>>,
+}
+
+impl<S, Id> Chained<S, Id> {
+    /// Decorates the empty `store`, whose events are then chained from the start.
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            chain: Arc::new(Mutex::new(Chain::default())),
+        }
+    }
+}
+
+impl<S, Id> Default for Chained<S, Id>
+where
+    S: Default,
+{
+    fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl<S, Id, Evt> Streamer<Id, Evt> for Chained<S, Id>
+where
+    S: Streamer<Id, Evt>,
+    Id: Send + Sync,
+    Evt: Message + Send + Sync,
+{
+    type Error = S::Error;
+
+    fn stream(&self, id: &Id, select: VersionSelect) -> Stream<'_, Id, Evt, Self::Error> {
+        self.store.stream(id, select)
+    }
+
+    fn stream_version<'a>(&'a self, id: &Id) -> BoxFuture<'a, Result<Option<Version>, Self::Error>>
+    where
+        Id: 'a,
+        Evt: 'a,
+    {
+        self.store.stream_version(id)
+    }
+}
+
+#[async_trait]
+impl<S, Id, Evt> Appender<Id, Evt> for Chained<S, Id>
+where
+    S: Store<Id, Evt> + Importer<Id, Evt>,
+    S::Error: std::error::Error + Send + Sync + 'static,
+    Id: Clone + Eq + Hash + Serialize + Send + Sync,
+    Evt: Message + Serialize + Send + Sync + 'static,
+{
+    async fn append(
+        &self,
+        id: Id,
+        version_check: Check,
+        events: Vec<Envelope<Evt>>,
+    ) -> Result<Version, AppendError> {
+        let mut chain = self.chain.lock().await;
+
+        let last_event_stream_version = self
+            .store
+            .stream_version(&id)
+            .await
+            .map_err(anyhow::Error::from)?
+            .unwrap_or_default();
+        if let Check::MustBe(expected) = version_check {
+            if last_event_stream_version != expected {
+                return Err(AppendError::Conflict(ConflictError {
+                    expected,
+                    actual: last_event_stream_version,
+                }));
+            }
+        }
+
+        let mut persisted_events: Vec<Persisted<Id, Evt>> = events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| Persisted {
+                stream_id: id.clone(),
+                version: last_event_stream_version + (i as Version) + 1,
+                event,
+                link: None,
+            })
+            .collect();
+        let new_last_event_stream_version = persisted_events
+            .last()
+            .map(|evt| evt.version)
+            .unwrap_or(last_event_stream_version);
+
+        // the heads only move past the events once the store holds them
+        let contents = persisted_events
+            .iter()
+            .map(Digest::of)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(anyhow::Error::from)?;
+        let links = chain.links(&id, &contents);
+        for (persisted, link) in persisted_events.iter_mut().zip(&links) {
+            persisted.link = Some(*link);
+        }
+        self.store.import(persisted_events).await?;
+        for link in &links {
+            chain.advance(&id, link);
+        }
+
+        Ok(new_last_event_stream_version)
+    }
+}
+
+#[async_trait]
+impl<S, Id, Evt> Importer<Id, Evt> for Chained<S, Id>
+where
+    S: Importer<Id, Evt>,
+    Id: Clone + Eq + Hash + Send + Sync,
+    Evt: Message + Send + Sync + 'static,
+{
+    async fn import(&self, events: Vec<Persisted<Id, Evt>>) -> Result<(), AppendError> {
+        let mut chain = self.chain.lock().await;
+        let links: Vec<_> = events
+            .iter()
+            .filter_map(|persisted| Some((persisted.stream_id.clone(), persisted.link?)))
+            .collect();
+        self.store.import(events).await?;
+        for (id, link) in &links {
+            chain.advance(id, link);
+        }
+
+        Ok(())
+    }
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The [Head] of the global hash chain at some point, e.g. the end of the day, signed with
+/// HMAC-SHA256 so that it can't be forged without the key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(flatten)]
+    pub head: Head,
+    pub signature: Digest,
+}
+
+impl Checkpoint {
+    pub fn sign(head: Head, key: &[u8]) -> Self {
+        let signature = Self::mac(&head, key).finalize().into_bytes();
+        Self {
+            head,
+            signature: Digest(signature.into()),
+        }
+    }
+
+    /// Checks the signature of the checkpoint with `key`, in constant time.
+    pub fn is_signed_with(&self, key: &[u8]) -> bool {
+        Self::mac(&self.head, key)
+            .verify_slice(&self.signature.0)
+            .is_ok()
+    }
+
+    fn mac(head: &Head, key: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+        mac.update(&head.sequence.to_be_bytes());
+        mac.update(&head.hash.0);
+        mac
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ChainError {
+    #[error("event {version} of stream {stream} is not chained")]
+    Unchained { stream: String, version: Version },
+    #[error("failed to hash event {version} of stream {stream}: {source}")]
+    Encode {
+        stream: String,
+        version: Version,
+        source: serde_json::Error,
+    },
+    #[error("event {expected} of stream {stream} is missing, found event {found} in its place")]
+    StreamGap {
+        stream: String,
+        expected: Version,
+        found: Version,
+    },
+    #[error("event {version} of stream {stream} doesn't match its stream chain, it or an earlier event of the stream was altered")]
+    StreamMismatch { stream: String, version: Version },
+    #[error("event {expected} of the global chain is missing, found event {found} in its place")]
+    SequenceGap { expected: u64, found: u64 },
+    #[error(
+        "event {sequence} of the global chain doesn't match it, it or an earlier event was altered"
+    )]
+    GlobalMismatch { sequence: u64 },
+    #[error("the checkpoint isn't signed with this key")]
+    Signature,
+    #[error("the chain ends at event {length}, before the checkpoint at event {sequence}, events were deleted")]
+    Truncated { length: u64, sequence: u64 },
+    #[error("event {sequence} doesn't match the checkpoint, the chain was rewritten")]
+    CheckpointMismatch { sequence: u64 },
+}
+
+/// The hash chains of an Event Store, as checked by [verify].
+#[derive(Debug, Clone, Default)]
+pub struct Verified {
+    /// The `global` hash of every event, in sequence.
+    globals: Vec<Digest>,
+}
+
+impl Verified {
+    /// The number of events verified.
+    pub fn len(&self) -> u64 {
+        self.globals.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.globals.is_empty()
+    }
+
+    pub fn head(&self) -> Head {
+        Head {
+            sequence: self.len(),
+            hash: self.globals.last().copied().unwrap_or_default(),
+        }
+    }
+
+    /// Checks that the chains extend the one the `checkpoint`, signed with `key`, was taken of.
+    pub fn check(&self, checkpoint: &Checkpoint, key: &[u8]) -> Result<(), ChainError> {
+        if !checkpoint.is_signed_with(key) {
+            return Err(ChainError::Signature);
+        }
+        let sequence = checkpoint.head.sequence;
+        let hash = match sequence {
+            0 => Digest::default(),
+            _ => *self
+                .globals
+                .get(sequence as usize - 1)
+                .ok_or(ChainError::Truncated {
+                    length: self.len(),
+                    sequence,
+                })?,
+        };
+        if hash != checkpoint.head.hash {
+            return Err(ChainError::CheckpointMismatch { sequence });
+        }
+        Ok(())
+    }
+}
+
+/// Recomputes the hash chains of the events of a store, given in any order, and checks every
+/// event against its [Link].
+pub fn verify<Id, Evt>(
+    events: impl IntoIterator<Item = Persisted<Id, Evt>>,
+) -> Result<Verified, ChainError>
+where
+    Id: Clone + Eq + Hash + Ord + fmt::Display + Serialize,
+    Evt: Message + Serialize,
+{
+    let mut chained = Vec::new();
+    for persisted in events {
+        let stream = || persisted.stream_id.to_string();
+        let link = persisted.link.ok_or_else(|| ChainError::Unchained {
+            stream: stream(),
+            version: persisted.version,
+        })?;
+        let content = Digest::of(&persisted).map_err(|source| ChainError::Encode {
+            stream: stream(),
+            version: persisted.version,
+            source,
+        })?;
+        chained.push((persisted.stream_id, persisted.version, content, link));
+    }
+
+    // every stream chain, in version order
+    chained.sort_by(|(a, a_version, ..), (b, b_version, ..)| (a, a_version).cmp(&(b, b_version)));
+    let mut previous: Option<(&Id, Version, Digest)> = None;
+    for (id, version, content, link) in &chained {
+        let (expected, hash) = match previous {
+            Some((stream, last, hash)) if stream == id => (last + 1, hash),
+            _ => (1, Digest::default()),
+        };
+        if *version != expected {
+            return Err(ChainError::StreamGap {
+                stream: id.to_string(),
+                expected,
+                found: *version,
+            });
+        }
+        if hash.chain(content) != link.stream {
+            return Err(ChainError::StreamMismatch {
+                stream: id.to_string(),
+                version: *version,
+            });
+        }
+        previous = Some((id, *version, link.stream));
+    }
+
+    // the global chain, in sequence
+    chained.sort_by_key(|(.., link)| link.sequence);
+    let mut verified = Verified::default();
+    for (.., content, link) in &chained {
+        let head = verified.head();
+        if link.sequence != head.sequence + 1 {
+            return Err(ChainError::SequenceGap {
+                expected: head.sequence + 1,
+                found: link.sequence,
+            });
+        }
+        if head.hash.chain(content) != link.global {
+            return Err(ChainError::GlobalMismatch {
+                sequence: link.sequence,
+            });
+        }
+        verified.globals.push(link.global);
+    }
+
+    Ok(verified)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::{Appender, Check, Envelope, Importer, InMemory, Streamer, VersionSelect};
+    use crate::domain::TransactionEvent;
+
+    const KEY: &[u8] = b"end of day";
+
+    fn fee(tx_id: u32) -> Envelope<TransactionEvent> {
+        Envelope::from(TransactionEvent::FeeWasCharged {
+            tx_id,
+            fee_id: u32::MAX - tx_id,
+            amount: dec!(1),
+        })
+    }
+
+    /// Two interleaved streams of three events each.
+    async fn chained_events() -> Vec<Persisted<u16, TransactionEvent>> {
+        let event_store = Chained::new(InMemory::<u16, TransactionEvent>::default());
+        for (version, tx_id) in (0..3).zip(1..) {
+            for id in [1, 2] {
+                event_store
+                    .append(id, Check::MustBe(version), vec![fee(tx_id)])
+                    .await
+                    .expect("append should not fail");
+            }
+        }
+
+        let mut events = Vec::new();
+        for id in [1, 2] {
+            let stream: Vec<_> = event_store
+                .stream(&id, VersionSelect::All)
+                .try_collect()
+                .await
+                .unwrap_or_else(|never| match never {});
+            events.extend(stream);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn appended_events_verify() {
+        let events = chained_events().await;
+        let head = events
+            .iter()
+            .filter_map(|persisted| persisted.link)
+            .max_by_key(|link| link.sequence)
+            .expect("events are linked");
+
+        let verified = verify(events).expect("untouched events verify");
+
+        assert_eq!(6, verified.len());
+        assert_eq!(
+            Head {
+                sequence: 6,
+                hash: head.global
+            },
+            verified.head()
+        );
+    }
+
+    #[tokio::test]
+    async fn appended_events_are_chained_after_imported_ones() {
+        let event_store = Chained::new(InMemory::<u16, TransactionEvent>::default());
+        event_store
+            .import(chained_events().await)
+            .await
+            .expect("linked events imported");
+        event_store
+            .append(2, Check::MustBe(3), vec![fee(4)])
+            .await
+            .expect("append should not fail");
+
+        let mut events = Vec::new();
+        for id in [1, 2] {
+            let stream: Vec<_> = event_store
+                .stream(&id, VersionSelect::All)
+                .try_collect()
+                .await
+                .unwrap_or_else(|never| match never {});
+            events.extend(stream);
+        }
+        assert_eq!(7, verify(events).expect("the chains go on").len());
+    }
+
+    #[tokio::test]
+    async fn it_detects_an_altered_event() {
+        let mut events = chained_events().await;
+        events[1].event = fee(42);
+
+        let err = verify(events).expect_err("altered event");
+        assert!(matches!(err, ChainError::StreamMismatch { version: 2, .. }));
+    }
+
+    #[tokio::test]
+    async fn it_detects_a_deleted_event() {
+        let mut events = chained_events().await;
+        events.remove(1);
+
+        let err = verify(events).expect_err("deleted event");
+        assert!(matches!(
+            err,
+            ChainError::StreamGap {
+                expected: 2,
+                found: 3,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_detects_reordered_events() {
+        let mut events = chained_events().await;
+        // the events of stream 1 swapped along with their links, stream 2 left as is
+        let (first, second) = (events[0].clone(), events[1].clone());
+        events[0] = Persisted {
+            version: 1,
+            ..second
+        };
+        events[1] = Persisted {
+            version: 2,
+            ..first
+        };
+
+        let err = verify(events).expect_err("reordered events");
+        assert!(matches!(err, ChainError::StreamMismatch { version: 1, .. }));
+    }
+
+    #[tokio::test]
+    async fn it_detects_a_rewritten_sequence() {
+        let mut events = chained_events().await;
+        let last = events
+            .iter_mut()
+            .filter_map(|persisted| persisted.link.as_mut())
+            .max_by_key(|link| link.sequence)
+            .expect("events are linked");
+        last.sequence += 1;
+
+        let err = verify(events).expect_err("gap in the global chain");
+        assert!(matches!(
+            err,
+            ChainError::SequenceGap {
+                expected: 6,
+                found: 7
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn checkpoints_detect_deleted_latest_events() {
+        let mut events = chained_events().await;
+        let checkpoint =
+            Checkpoint::sign(verify(events.clone()).expect("events verify").head(), KEY);
+
+        let verified = verify(events.clone()).expect("events verify");
+        verified
+            .check(&checkpoint, KEY)
+            .expect("the chain matches its checkpoint");
+        assert!(matches!(
+            verified.check(&checkpoint, b"another key"),
+            Err(ChainError::Signature)
+        ));
+
+        // the latest event is the last one of stream 2, the chains left are intact
+        events.pop();
+        let truncated = verify(events).expect("the chains left verify");
+        assert!(matches!(
+            truncated.check(&checkpoint, KEY),
+            Err(ChainError::Truncated {
+                length: 5,
+                sequence: 6
+            })
+        ));
+    }
+}
@@ -44,10 +44,21 @@ where
 #[cfg(any(test, feature = "test"))]
 #[doc(hidden)]
 pub mod __scenario {
-    use crate::core::store::{Appender, Check, Persisted};
-    use crate::core::{Envelope, EventStoreExt, Handler, InMemory, Message, Tracking};
-    use std::fmt::Debug;
+    use std::any::Any;
+    use std::fmt::{Debug, Display};
     use std::hash::Hash;
+    use std::path::Path;
+
+    use futures::future::{BoxFuture, FutureExt};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    use crate::core::repository::Getter;
+    use crate::core::store::{Appender, Check, Persisted};
+    use crate::core::{
+        Aggregate, Envelope, EventSourced, EventStoreExt, Handler, InMemory, Message, Root,
+        Tracking,
+    };
 
     /// A test scenario that can be used to test a [Command][command::Envelope] [Handler][command::Handler]
     /// using a [given-then-when canvas](https://www.agilealliance.org/glossary/gwt/) approach.
@@ -57,11 +68,52 @@ pub mod __scenario {
         /// Sets the precondition state of the system for the [Scenario], which
         /// is expressed by a list of Domain [Event][event::Envelope]s in an Event-sourced system.
         #[must_use]
-        pub fn given<Id, Evt>(self, events: Vec<Persisted<Id, Evt>>) -> ScenarioGiven<Id, Evt>
+        pub fn given<Id, Evt, Cmd>(
+            self,
+            events: Vec<Persisted<Id, Evt>>,
+        ) -> ScenarioGiven<Id, Evt, Cmd>
+        where
+            Evt: Message,
+            Cmd: Message,
+        {
+            ScenarioGiven {
+                given: events,
+                given_commands: Vec::default(),
+            }
+        }
+
+        /// Sets the precondition state of the system for the [Scenario] from a CSV fixture,
+        /// whose commands are handled before the ones under test, ignoring their results.
+        ///
+        /// # Panics
+        ///
+        /// The method panics if the fixture can't be read or deserialized into commands.
+        #[must_use]
+        pub fn given_csv<Id, Evt, Cmd>(self, path: impl AsRef<Path>) -> ScenarioGiven<Id, Evt, Cmd>
         where
             Evt: Message,
+            Cmd: Message + DeserializeOwned,
         {
-            ScenarioGiven { given: events }
+            let path = path.as_ref();
+            let given_commands = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .flexible(true)
+                .from_path(path)
+                .unwrap_or_else(|err| {
+                    panic!("fixture {} should be readable: {err}", path.display())
+                })
+                .into_deserialize::<Cmd>()
+                .map(|command| {
+                    command.map(Envelope::from).unwrap_or_else(|err| {
+                        panic!("fixture {} should contain commands: {err}", path.display())
+                    })
+                })
+                .collect();
+
+            ScenarioGiven {
+                given: Vec::default(),
+                given_commands,
+            }
         }
 
         /// Specifies the [Command][command::Envelope] to test in the [Scenario], in the peculiar case
@@ -74,36 +126,48 @@ pub mod __scenario {
         {
             ScenarioWhen {
                 given: Vec::default(),
-                when: command,
+                given_commands: Vec::default(),
+                when: vec![When::Succeeds(command)],
             }
         }
     }
 
     #[doc(hidden)]
-    pub struct ScenarioGiven<Id, Evt>
+    pub struct ScenarioGiven<Id, Evt, Cmd>
     where
         Evt: Message,
+        Cmd: Message,
     {
         given: Vec<Persisted<Id, Evt>>,
+        given_commands: Vec<Envelope<Cmd>>,
     }
 
-    impl<Id, Evt> ScenarioGiven<Id, Evt>
+    impl<Id, Evt, Cmd> ScenarioGiven<Id, Evt, Cmd>
     where
         Evt: Message,
+        Cmd: Message,
     {
         /// Specifies the [Command][command::Envelope] to test in the [Scenario].
         #[must_use]
-        pub fn when<Cmd>(self, command: Envelope<Cmd>) -> ScenarioWhen<Id, Evt, Cmd>
-        where
-            Cmd: Message,
-        {
+        pub fn when(self, command: Envelope<Cmd>) -> ScenarioWhen<Id, Evt, Cmd> {
             ScenarioWhen {
                 given: self.given,
-                when: command,
+                given_commands: self.given_commands,
+                when: vec![When::Succeeds(command)],
             }
         }
     }
 
+    /// A [Command][command::Envelope] under test, along with whether it may fail when it isn't
+    /// the last one.
+    enum When<Cmd>
+    where
+        Cmd: Message,
+    {
+        Succeeds(Envelope<Cmd>),
+        MayFail(Envelope<Cmd>),
+    }
+
     #[doc(hidden)]
     pub struct ScenarioWhen<Id, Evt, Cmd>
     where
@@ -111,7 +175,8 @@ pub mod __scenario {
         Cmd: Message,
     {
         given: Vec<Persisted<Id, Evt>>,
-        when: Envelope<Cmd>,
+        given_commands: Vec<Envelope<Cmd>>,
+        when: Vec<When<Cmd>>,
     }
 
     impl<Id, Evt, Cmd> ScenarioWhen<Id, Evt, Cmd>
@@ -119,51 +184,172 @@ pub mod __scenario {
         Evt: Message,
         Cmd: Message,
     {
+        /// Specifies another [Command][command::Envelope] to test in the [Scenario],
+        /// handled after the previous ones.
+        ///
+        /// The previous commands have to succeed, unless they were specified with
+        /// [`when_may_fail`][ScenarioWhen::when_may_fail].
+        #[must_use]
+        pub fn when(mut self, command: Envelope<Cmd>) -> Self {
+            self.when.push(When::Succeeds(command));
+            self
+        }
+
+        /// Specifies another [Command][command::Envelope] to test in the [Scenario],
+        /// handled after the previous ones, whose error doesn't fail the [Scenario] when
+        /// more commands follow it.
+        #[must_use]
+        pub fn when_may_fail(mut self, command: Envelope<Cmd>) -> Self {
+            self.when.push(When::MayFail(command));
+            self
+        }
+
         /// Sets the expectation on the result of the [Scenario] to be positive
-        /// and produce a specified list of Domain [Event]s.
+        /// and produce a specified list of Domain [Event]s, across all the commands.
         #[must_use]
-        pub fn then(self, events: Vec<Persisted<Id, Evt>>) -> ScenarioThen<Id, Evt, Cmd> {
-            ScenarioThen {
-                given: self.given,
-                when: self.when,
-                case: ScenarioThenCase::Produces(events),
-            }
+        pub fn then<Err>(self, events: Vec<Persisted<Id, Evt>>) -> ScenarioThen<Id, Evt, Cmd, Err> {
+            self.expect(ScenarioThenCase::Produces(events))
         }
 
-        /// Sets the expectation on the result of the [Scenario] to return an error.
+        /// Sets the expectation on the result of the [Scenario] to return an error
+        /// for the last command.
         #[must_use]
-        pub fn then_fails(self) -> ScenarioThen<Id, Evt, Cmd> {
+        pub fn then_fails<Err>(self) -> ScenarioThen<Id, Evt, Cmd, Err> {
+            self.expect(ScenarioThenCase::Fails(Box::new(|_| true)))
+        }
+
+        /// Sets the expectation on the result of the [Scenario] to return an error
+        /// for the last command, matching the `predicate`.
+        ///
+        /// `Err` is the error type of the [Handler][command::Handler] under test.
+        #[must_use]
+        pub fn then_fails_with<Err>(
+            self,
+            predicate: impl Fn(&Err) -> bool + Send + 'static,
+        ) -> ScenarioThen<Id, Evt, Cmd, Err> {
+            self.expect(ScenarioThenCase::Fails(Box::new(predicate)))
+        }
+
+        /// Sets the expectation on the result of the [Scenario] to return the `expected` error
+        /// for the last command, either as the [Handler][command::Handler] error or
+        /// anywhere in the chain of an [anyhow::Error].
+        #[must_use]
+        pub fn then_fails_with_error<Err, E>(self, expected: E) -> ScenarioThen<Id, Evt, Cmd, Err>
+        where
+            Err: 'static,
+            E: std::error::Error + PartialEq + Send + Sync + 'static,
+        {
+            self.expect(ScenarioThenCase::Fails(Box::new(move |err: &Err| {
+                let err: &dyn Any = err;
+                match (err.downcast_ref::<E>(), err.downcast_ref::<anyhow::Error>()) {
+                    (Some(err), _) => *err == expected,
+                    (None, Some(err)) => err
+                        .chain()
+                        .filter_map(|err| err.downcast_ref::<E>())
+                        .any(|err| *err == expected),
+                    (None, None) => false,
+                }
+            })))
+        }
+
+        /// Sets the expectation on the state of the aggregate `id` after the commands,
+        /// as rehydrated from the event store, regardless of the commands results.
+        #[must_use]
+        pub fn then_state<T, Err>(
+            self,
+            id: Id,
+            assertion: impl FnOnce(&Root<T>) + Send + 'static,
+        ) -> ScenarioThen<Id, Evt, Cmd, Err>
+        where
+            T: Aggregate<Id = Id, Event = Evt> + Send + Sync + 'static,
+            T::Error: std::error::Error + Send + Sync + 'static,
+            Id: Clone + Eq + Hash + Display + Serialize + Send + Sync + 'static,
+            Evt: Clone + Serialize + Send + Sync + 'static,
+        {
+            self.expect(ScenarioThenCase::Any).then_state(id, assertion)
+        }
+
+        fn expect<Err>(
+            self,
+            case: ScenarioThenCase<Id, Evt, Err>,
+        ) -> ScenarioThen<Id, Evt, Cmd, Err> {
             ScenarioThen {
                 given: self.given,
+                given_commands: self.given_commands,
                 when: self.when,
-                case: ScenarioThenCase::Fails,
+                case,
+                states: Vec::default(),
             }
         }
     }
 
-    enum ScenarioThenCase<Id, Evt>
+    type ErrorPredicate<Err> = Box<dyn Fn(&Err) -> bool + Send>;
+
+    type StateAssertion<Id, Evt> =
+        Box<dyn FnOnce(InMemory<Id, Evt>) -> BoxFuture<'static, ()> + Send>;
+
+    enum ScenarioThenCase<Id, Evt, Err>
     where
         Evt: Message,
     {
         Produces(Vec<Persisted<Id, Evt>>),
-        Fails,
+        Fails(ErrorPredicate<Err>),
+        /// Only the aggregate states are asserted.
+        Any,
     }
 
+    /// A [Scenario] with its expectations, for a [Handler][command::Handler] whose error
+    /// type is `Err`.
     #[doc(hidden)]
-    pub struct ScenarioThen<Id, Evt, Cmd>
+    pub struct ScenarioThen<Id, Evt, Cmd, Err>
     where
         Evt: Message,
         Cmd: Message,
     {
         given: Vec<Persisted<Id, Evt>>,
-        when: Envelope<Cmd>,
-        case: ScenarioThenCase<Id, Evt>,
+        given_commands: Vec<Envelope<Cmd>>,
+        when: Vec<When<Cmd>>,
+        case: ScenarioThenCase<Id, Evt, Err>,
+        states: Vec<StateAssertion<Id, Evt>>,
     }
 
-    impl<Id, Evt, Cmd> ScenarioThen<Id, Evt, Cmd>
+    impl<Id, Evt, Cmd, Err> ScenarioThen<Id, Evt, Cmd, Err>
     where
-        Id: Clone + Eq + Hash + Send + Sync + Debug,
-        Evt: Message + Clone + PartialEq + Send + Sync + Debug,
+        Evt: Message,
+        Cmd: Message,
+    {
+        /// Adds an expectation on the state of the aggregate `id` after the commands,
+        /// as rehydrated from the event store.
+        #[must_use]
+        pub fn then_state<T>(
+            mut self,
+            id: Id,
+            assertion: impl FnOnce(&Root<T>) + Send + 'static,
+        ) -> Self
+        where
+            T: Aggregate<Id = Id, Event = Evt> + Send + Sync + 'static,
+            T::Error: std::error::Error + Send + Sync + 'static,
+            Id: Clone + Eq + Hash + Display + Serialize + Send + Sync + 'static,
+            Evt: Clone + Serialize + Send + Sync + 'static,
+        {
+            self.states.push(Box::new(move |event_store| {
+                async move {
+                    let root = EventSourced::<T, _>::from(event_store)
+                        .get(&id)
+                        .await
+                        .unwrap_or_else(|err| panic!("aggregate {id} should rehydrate: {err}"));
+                    assertion(&root);
+                }
+                .boxed()
+            }));
+            self
+        }
+    }
+
+    impl<Id, Evt, Cmd, Err> ScenarioThen<Id, Evt, Cmd, Err>
+    where
+        Id: Clone + Eq + Hash + Serialize + Send + Sync + Debug,
+        Evt: Message + Clone + PartialEq + Serialize + Send + Sync + Debug,
         Cmd: Message,
     {
         /// Executes the whole [Scenario] by constructing a Command [Handler][command::Handler]
@@ -171,14 +357,15 @@ pub mod __scenario {
         ///
         /// # Panics
         ///
-        /// The method panics if the assertion fails.
+        /// The method panics if the assertion fails, or if a command other than the last one
+        /// fails without having been specified with [`when_may_fail`][ScenarioWhen::when_may_fail].
         pub async fn assert_on<F, H>(self, handler_factory: F)
         where
             F: Fn(Tracking<InMemory<Id, Evt>, Id, Evt>) -> H,
-            H: Handler<Cmd>,
+            H: Handler<Cmd, Error = Err>,
+            Err: Debug,
         {
             let event_store = InMemory::<Id, Evt>::default();
-            let tracking_event_store = event_store.clone().with_recorded_events_tracking();
 
             for event in self.given {
                 event_store
@@ -191,16 +378,47 @@ pub mod __scenario {
                     .expect("domain event in 'given' should be inserted in the event store");
             }
 
+            // the events recorded by the given commands are part of the precondition, not tracked
+            if !self.given_commands.is_empty() {
+                let handler = handler_factory(event_store.clone().with_recorded_events_tracking());
+                for command in self.given_commands {
+                    let _ = handler.handle(command).await;
+                }
+            }
+
+            let tracking_event_store = event_store.clone().with_recorded_events_tracking();
             let handler = handler_factory(tracking_event_store.clone());
-            let result = handler.handle(self.when).await;
+            let mut result = Ok(());
+            let commands = self.when.len();
+            for (i, command) in self.when.into_iter().enumerate() {
+                let (command, may_fail) = match command {
+                    When::Succeeds(command) => (command, false),
+                    When::MayFail(command) => (command, true),
+                };
+                result = handler.handle(command).await;
+                // only the result of the last command is up to the expectation
+                if i + 1 < commands && !may_fail {
+                    if let Err(err) = &result {
+                        panic!("command {} should have succeeded: {err:?}", i + 1);
+                    }
+                }
+            }
 
             match self.case {
                 ScenarioThenCase::Produces(events) => {
                     let recorded_events = tracking_event_store.recorded_events();
                     assert_eq!(events, recorded_events);
                 }
-                ScenarioThenCase::Fails => assert!(result.is_err()),
+                ScenarioThenCase::Fails(predicate) => match result {
+                    Ok(()) => panic!("the last command should have failed"),
+                    Err(err) => assert!(predicate(&err), "unexpected error: {err:?}"),
+                },
+                ScenarioThenCase::Any => {}
             };
+
+            for assertion in self.states {
+                assertion(event_store.clone()).await;
+            }
         }
     }
 }
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use futures::future::{ready, BoxFuture, FutureExt};
+use futures::stream::{iter, StreamExt};
+
+use crate::core::store::{
+    AppendError, Appender, Check, ConflictError, Stream, Streamer, Version, VersionSelect,
+};
+use crate::core::{Envelope, Message, Persisted};
+
+/// Scratch fork of an Event [Store][crate::core::store::Store], used to evaluate commands
+/// without committing their Domain Events.
+///
+/// Event Streams are read from the forked store first and continue with the Domain Events
+/// appended to the fork, which are kept in memory only. The forked store is never written to.
+/// Scratch events are never committed, so they are not linked into its hash chains.
+#[derive(Debug, Clone)]
+pub struct Fork<S, Id, Evt>
+where
+    Evt: Message,
+{
+    base: S,
+    #[allow(clippy::type_complexity)]
+    scratch: Arc<RwLock<HashMap<Id, Vec<Persisted<Id, Evt>>>>>,
+}
+
+impl<S, Id, Evt> Fork<S, Id, Evt>
+where
+    Evt: Message,
+{
+    /// Forks the `base` Event Store.
+    pub fn new(base: S) -> Self {
+        Self {
+            base,
+            scratch: Arc::default(),
+        }
+    }
+}
+
+impl<S, Id, Evt> Fork<S, Id, Evt>
+where
+    Id: Eq + Hash,
+    Evt: Message,
+{
+    fn scratch_version(&self, id: &Id) -> Option<Version> {
+        self.scratch
+            .read()
+            .expect("acquire read lock on forked event streams")
+            .get(id)
+            .and_then(|events| events.last())
+            .map(|event| event.version)
+    }
+}
+
+impl<S, Id, Evt> Streamer<Id, Evt> for Fork<S, Id, Evt>
+where
+    S: Streamer<Id, Evt>,
+    Id: Clone + Eq + Hash + Send + Sync,
+    Evt: Message + Clone + Send + Sync,
+{
+    type Error = S::Error;
+
+    fn stream(&self, id: &Id, select: VersionSelect) -> Stream<'_, Id, Evt, Self::Error> {
+        let scratch = self
+            .scratch
+            .read()
+            .expect("acquire read lock on forked event streams")
+            .get(id)
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(move |evt| match select {
+                VersionSelect::All => true,
+                VersionSelect::From(v) => evt.version >= v,
+            });
+
+        self.base
+            .stream(id, select)
+            .chain(iter(scratch).map(Ok))
+            .boxed()
+    }
+
+    fn stream_version<'a>(&'a self, id: &Id) -> BoxFuture<'a, Result<Option<Version>, Self::Error>>
+    where
+        Id: 'a,
+        Evt: 'a,
+    {
+        match self.scratch_version(id) {
+            Some(version) => ready(Ok(Some(version))).boxed(),
+            None => self.base.stream_version(id),
+        }
+    }
+}
+
+#[async_trait]
+impl<S, Id, Evt> Appender<Id, Evt> for Fork<S, Id, Evt>
+where
+    S: Streamer<Id, Evt>,
+    S::Error: std::error::Error + 'static,
+    Id: Clone + Eq + Hash + Send + Sync,
+    Evt: Message + Clone + Send + Sync,
+{
+    async fn append(
+        &self,
+        id: Id,
+        version_check: Check,
+        events: Vec<Envelope<Evt>>,
+    ) -> Result<Version, AppendError> {
+        let base_version = self
+            .base
+            .stream_version(&id)
+            .await
+            .map_err(anyhow::Error::from)?
+            .unwrap_or_default();
+
+        let mut scratch = self
+            .scratch
+            .write()
+            .expect("acquire write lock on forked event streams");
+
+        let last_event_stream_version = scratch
+            .get(&id)
+            .and_then(|events| events.last())
+            .map_or(base_version, |event| event.version);
+
+        if let Check::MustBe(expected) = version_check {
+            if last_event_stream_version != expected {
+                return Err(AppendError::Conflict(ConflictError {
+                    expected,
+                    actual: last_event_stream_version,
+                }));
+            }
+        }
+
+        let mut persisted_events: Vec<Persisted<Id, Evt>> = events
+            .into_iter()
+            .enumerate()
+            .map(|(i, event)| Persisted {
+                stream_id: id.clone(),
+                version: last_event_stream_version + (i as u64) + 1,
+                event,
+                link: None,
+            })
+            .collect();
+
+        let new_last_event_stream_version = persisted_events
+            .last()
+            .map_or(last_event_stream_version, |evt| evt.version);
+
+        scratch.entry(id).or_default().append(&mut persisted_events);
+
+        Ok(new_last_event_stream_version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::InMemory;
+    use crate::domain::TransactionEvent;
+
+    fn fee(tx_id: u32) -> Envelope<TransactionEvent> {
+        Envelope::from(TransactionEvent::FeeWasCharged {
+            tx_id,
+            fee_id: u32::MAX - tx_id,
+            amount: dec!(1),
+        })
+    }
+
+    #[tokio::test]
+    async fn it_continues_streams_without_touching_the_forked_store() {
+        let event_store = InMemory::<u16, TransactionEvent>::default();
+        event_store
+            .append(1, Check::MustBe(0), vec![fee(1), fee(2)])
+            .await
+            .expect("appended");
+
+        let fork = Fork::new(event_store.clone());
+        assert_eq!(
+            3,
+            fork.append(1, Check::MustBe(2), vec![fee(3)])
+                .await
+                .expect("appended to the fork")
+        );
+        assert!(matches!(
+            fork.append(1, Check::MustBe(2), vec![fee(4)]).await,
+            Err(AppendError::Conflict(ConflictError {
+                expected: 2,
+                actual: 3
+            }))
+        ));
+
+        let forked: Vec<_> = fork
+            .stream(&1, VersionSelect::All)
+            .map(|persisted| persisted.expect("infallible").version)
+            .collect()
+            .await;
+        assert_eq!(vec![1, 2, 3], forked);
+        assert_eq!(Ok(Some(3)), fork.stream_version(&1).await);
+        assert_eq!(Ok(Some(2)), event_store.stream_version(&1).await);
+    }
+}
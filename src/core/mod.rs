@@ -1,13 +1,21 @@
 mod aggregate;
+mod cache;
+mod chain;
 mod command;
+mod fork;
 pub(crate) mod repository;
 mod store;
 
-pub use aggregate::{Aggregate, Envelope, Message, Root};
+pub use aggregate::{Aggregate, Envelope, Message, RehydrateError, Root};
+pub use cache::{CacheMetrics, Cached};
+pub use chain::{verify, Chain, ChainError, Chained, Checkpoint, Digest, Head, Link, Verified};
 pub use command::Handler;
-pub use repository::{EventSourced, GetError};
+pub use fork::Fork;
+pub use repository::{EventSourced, GetError, Getter, SaveError, Saver, VersionGetter};
 pub use store::InMemory;
 pub use store::Persisted;
+pub use store::Version;
+pub use store::{AppendError, Appender, Check, Importer, Streamer, VersionSelect};
 
 #[cfg(any(test, feature = "test"))]
 pub use command::__scenario::{Scenario, ScenarioGiven, ScenarioThen, ScenarioWhen};
@@ -1,12 +1,15 @@
 use std::fmt::Debug;
 use std::marker::PhantomData;
+use std::time::Instant;
 
 use crate::core::store::{
     AppendError, Check, ConflictError, Store, Streamer, Version, VersionSelect,
 };
-use crate::core::{Aggregate, Root};
+use crate::core::{Aggregate, RehydrateError, Root};
+use crate::telemetry;
 use async_trait::async_trait;
 use futures::TryStreamExt;
+use tracing::Instrument;
 
 /// All possible errors returned by [`Getter::get`].
 #[derive(Debug, thiserror::Error)]
@@ -14,6 +17,17 @@ pub enum GetError {
     /// Error returned when the [Aggregate Root][aggregate::Root] could not be found in the data store.
     #[error("failed to get aggregate root: not found")]
     NotFound,
+    /// Error returned when a Domain Event of the stream could not be applied to the
+    /// [Aggregate Root][aggregate::Root], usually because the stream is corrupted.
+    #[error("failed to rehydrate stream {stream_id} at version {version}: {source}")]
+    Rehydrate {
+        /// The id of the corrupted stream.
+        stream_id: String,
+        /// The version of the Domain Event that could not be applied.
+        version: Version,
+        #[source]
+        source: anyhow::Error,
+    },
     /// Error returned when the [Getter] implementation has encountered an error.
     #[error("failed to get aggregate root, an error occurred: {0}")]
     Internal(#[from] anyhow::Error),
@@ -31,6 +45,18 @@ where
     async fn get(&self, id: &T::Id) -> Result<Root<T>, GetError>;
 }
 
+/// Trait used to look up the current [Version] of an [aggregate::Root] in a data store,
+/// without loading it.
+#[async_trait]
+pub trait VersionGetter<T>: Send + Sync
+where
+    T: Aggregate,
+{
+    /// Returns the current [Version] of the [aggregate::Root] referenced by its unique identifier,
+    /// or `None` if it could not be found.
+    async fn version(&self, id: &T::Id) -> Result<Option<Version>, GetError>;
+}
+
 /// All possible errors returned by [`Saver::save`].
 #[derive(Debug, thiserror::Error)]
 pub enum SaveError {
@@ -51,6 +77,17 @@ where
 {
     /// Saves a new version of an [aggregate::Root] instance to the data store.
     async fn save(&self, root: &mut Root<T>) -> Result<(), SaveError>;
+
+    /// Saves a new version of an [aggregate::Root] instance the caller is done with.
+    ///
+    /// The default implementation saves it through [`Saver::save`] and drops it, implementations
+    /// keeping saved Roots around should override it to take them over without a clone.
+    async fn save_owned(&self, mut root: Root<T>) -> Result<(), SaveError>
+    where
+        T: 'async_trait,
+    {
+        self.save(&mut root).await
+    }
 }
 
 /// A Repository is an object that allows to load and save
@@ -100,7 +137,7 @@ where
 impl<T, S> Getter<T> for EventSourced<T, S>
 where
     T: Aggregate,
-    T::Id: Clone,
+    T::Id: Clone + std::fmt::Display,
     T::Error: std::error::Error + Send + Sync + 'static,
     S: Store<T::Id, T::Event>,
     <S as Streamer<T::Id, T::Event>>::Error: std::error::Error + Send + Sync + 'static,
@@ -112,11 +149,37 @@ where
             .map_ok(|persisted| persisted.event);
 
         let ctx = Root::<T>::rehydrate_async(stream)
+            .instrument(tracing::debug_span!("stream", aggregate = T::type_name(), id = %id))
             .await
-            .map_err(anyhow::Error::from)
-            .map_err(GetError::Internal)?;
+            .map_err(|err| match err {
+                RehydrateError::Domain { version, source } => GetError::Rehydrate {
+                    stream_id: id.to_string(),
+                    version,
+                    source: anyhow::Error::from(source),
+                },
+                err => GetError::Internal(anyhow::Error::from(err)),
+            })?;
 
-        ctx.ok_or(GetError::NotFound)
+        let root = ctx.ok_or(GetError::NotFound)?;
+        metrics::histogram!(telemetry::REHYDRATED_EVENTS, "aggregate" => T::type_name())
+            .record(root.version() as f64);
+        Ok(root)
+    }
+}
+
+#[async_trait]
+impl<T, S> VersionGetter<T> for EventSourced<T, S>
+where
+    T: Aggregate,
+    S: Store<T::Id, T::Event>,
+    <S as Streamer<T::Id, T::Event>>::Error: std::error::Error + Send + Sync + 'static,
+{
+    async fn version(&self, id: &T::Id) -> Result<Option<Version>, GetError> {
+        self.store
+            .stream_version(id)
+            .await
+            .map_err(anyhow::Error::from)
+            .map_err(GetError::Internal)
     }
 }
 
@@ -135,19 +198,28 @@ where
             return Ok(());
         }
 
-        let current_event_stream_version = root.version() - (events_to_commit.len() as Version);
+        let events_count = events_to_commit.len();
+        let current_event_stream_version = root.version() - (events_count as Version);
 
+        let started = Instant::now();
         self.store
             .append(
                 aggregate_id.clone(),
                 Check::MustBe(current_event_stream_version),
                 events_to_commit,
             )
+            .instrument(tracing::debug_span!(
+                "append",
+                aggregate = T::type_name(),
+                events = events_count
+            ))
             .await
             .map_err(|err| match err {
                 AppendError::Internal(err) => SaveError::Internal(err),
                 AppendError::Conflict(err) => SaveError::Conflict(err),
             })?;
+        metrics::histogram!(telemetry::APPEND_DURATION, "aggregate" => T::type_name())
+            .record(started.elapsed());
 
         Ok(())
     }
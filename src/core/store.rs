@@ -4,11 +4,13 @@ use std::hash::Hash;
 use std::sync::{Arc, RwLock};
 
 use async_trait::async_trait;
+use futures::future::{ready, BoxFuture, FutureExt};
 use futures::stream::BoxStream;
-use futures::stream::{iter, StreamExt};
+use futures::stream::{iter, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
 
 use crate::core::aggregate::{Envelope, Message};
+use crate::core::chain::Link;
 
 pub type Version = u64;
 
@@ -49,6 +51,10 @@ where
 
     /// The actual Domain Event carried by this envelope.
     pub event: Envelope<Evt>,
+
+    /// The links of the Event into the hash chains of the Event Store, if it keeps them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<Link>,
 }
 
 /// Specifies the slice of the Event Stream to select when calling [`Store::stream`].
@@ -77,7 +83,28 @@ where
 
     /// Opens an Event Stream, effectively streaming all Domain Events
     /// of an Event Stream back in the application.
-    fn stream(&self, id: &StreamId, select: VersionSelect) -> Stream<StreamId, Event, Self::Error>;
+    fn stream(
+        &self,
+        id: &StreamId,
+        select: VersionSelect,
+    ) -> Stream<'_, StreamId, Event, Self::Error>;
+
+    /// Returns the current [Version] of an Event Stream, or `None` if the stream is empty.
+    ///
+    /// The default implementation goes through the whole Event Stream, implementations
+    /// should override it when the last [Version] can be looked up directly.
+    fn stream_version<'a>(
+        &'a self,
+        id: &StreamId,
+    ) -> BoxFuture<'a, Result<Option<Version>, Self::Error>>
+    where
+        StreamId: 'a,
+        Event: 'a,
+    {
+        self.stream(id, VersionSelect::All)
+            .try_fold(None, |_, persisted| ready(Ok(Some(persisted.version))))
+            .boxed()
+    }
 }
 
 /// All possible error types returned by [`Appender::append`].
@@ -111,6 +138,20 @@ where
     ) -> Result<Version, AppendError>;
 }
 
+#[async_trait]
+/// Interface used to insert Domain Events persisted already in an Event Store, as they are,
+/// e.g. loaded from a persistent store or [linked][crate::core::Chained] by a decorator.
+pub trait Importer<StreamId, Event>: Send + Sync
+where
+    StreamId: Send + Sync,
+    Event: Message + Send + Sync,
+{
+    /// Inserts the persisted Domain Events, keeping their versions and links.
+    ///
+    /// Every Domain Event must be the next one of its Event Stream, otherwise none is inserted.
+    async fn import(&self, events: Vec<Persisted<StreamId, Event>>) -> Result<(), AppendError>;
+}
+
 /// An [Event][event::Envelope] Store, used to store Domain Events in Event Streams -- a stream
 /// of Domain Events -- and retrieve them.
 ///
@@ -152,6 +193,8 @@ where
 
 /// In-memory implementation of [Store] trait,
 /// backed by a thread-safe [`std::collections::HashMap`].
+///
+/// Appended Domain Events are not linked, see [Chained][crate::core::Chained] for that.
 #[derive(Debug, Clone)]
 pub struct InMemory<Id, Evt>
 where
@@ -178,7 +221,7 @@ where
 {
     type Error = Infallible;
 
-    fn stream(&self, id: &Id, select: VersionSelect) -> Stream<Id, Evt, Self::Error> {
+    fn stream(&self, id: &Id, select: VersionSelect) -> Stream<'_, Id, Evt, Self::Error> {
         let backend = self
             .backend
             .read()
@@ -197,6 +240,68 @@ where
 
         iter(events).map(Ok).boxed()
     }
+
+    fn stream_version<'a>(&'a self, id: &Id) -> BoxFuture<'a, Result<Option<Version>, Self::Error>>
+    where
+        Id: 'a,
+        Evt: 'a,
+    {
+        let version = self
+            .backend
+            .read()
+            .expect("acquire read lock on event store backend")
+            .event_streams
+            .get(id)
+            .and_then(|events| events.last())
+            .map(|event| event.version);
+
+        ready(Ok(version)).boxed()
+    }
+}
+
+#[async_trait]
+impl<Id, Evt> Importer<Id, Evt> for InMemory<Id, Evt>
+where
+    Id: Clone + Eq + Hash + Send + Sync,
+    Evt: Message + Send + Sync,
+{
+    async fn import(&self, events: Vec<Persisted<Id, Evt>>) -> Result<(), AppendError> {
+        let mut backend = self
+            .backend
+            .write()
+            .expect("acquire write lock on event store backend");
+
+        for (i, persisted) in events.iter().enumerate() {
+            let last_event_stream_version = events[..i]
+                .iter()
+                .rev()
+                .find(|previous| previous.stream_id == persisted.stream_id)
+                .or_else(|| {
+                    backend
+                        .event_streams
+                        .get(&persisted.stream_id)
+                        .and_then(|events| events.last())
+                })
+                .map(|event| event.version)
+                .unwrap_or_default();
+            if persisted.version != last_event_stream_version + 1 {
+                return Err(AppendError::Conflict(ConflictError {
+                    expected: persisted.version - 1,
+                    actual: last_event_stream_version,
+                }));
+            }
+        }
+
+        for persisted in events {
+            backend
+                .event_streams
+                .entry(persisted.stream_id.clone())
+                .or_default()
+                .push(persisted);
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -239,6 +344,7 @@ where
                 stream_id: id.clone(),
                 version: last_event_stream_version + (i as u64) + 1,
                 event,
+                link: None,
             })
             .collect();
 
@@ -319,9 +425,20 @@ pub mod __tracking {
             &self,
             id: &StreamId,
             select: VersionSelect,
-        ) -> Stream<StreamId, Event, Self::Error> {
+        ) -> Stream<'_, StreamId, Event, Self::Error> {
             self.store.stream(id, select)
         }
+
+        fn stream_version<'a>(
+            &'a self,
+            id: &StreamId,
+        ) -> futures::future::BoxFuture<'a, Result<Option<Version>, Self::Error>>
+        where
+            StreamId: 'a,
+            Event: 'a,
+        {
+            self.store.stream_version(id)
+        }
     }
 
     #[async_trait]
@@ -352,6 +469,7 @@ pub mod __tracking {
                     stream_id: id.clone(),
                     version: previous_version + (i as Version) + 1,
                     event,
+                    link: None,
                 })
                 .collect();
 
@@ -399,7 +517,7 @@ mod test {
 
     const STREAM_ID: &str = "stream:test";
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
     pub(crate) struct StringMessage(pub(crate) &'static str);
 
     impl Message for StringMessage {
@@ -436,6 +554,7 @@ mod test {
                 stream_id: STREAM_ID,
                 version: (i as Version) + 1,
                 event,
+                link: None,
             })
             .collect::<Vec<_>>();
 
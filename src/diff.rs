@@ -0,0 +1,194 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use csv::Trim;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::core::GetError;
+use crate::domain::AccountSnapShot;
+use crate::event_log::{EventLog, EventLogError};
+
+/// Account snapshots keyed by client id.
+pub type Accounts = BTreeMap<u16, AccountSnapShot>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error("unsupported accounts file {0}, expected a .csv, .json or .jsonl event log")]
+    UnsupportedFile(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Csv(#[from] csv::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    EventLog(#[from] EventLogError),
+    #[error(transparent)]
+    Replay(#[from] GetError),
+}
+
+/// Reads the account snapshots of an accounts CSV, as printed by `process`, or of a JSON array.
+pub fn read_accounts(path: impl AsRef<Path>) -> Result<Accounts, DiffError> {
+    let path = path.as_ref();
+    let snapshots: Vec<AccountSnapShot> = match extension(path) {
+        Some("csv") => csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .from_path(path)?
+            .into_deserialize()
+            .collect::<Result<_, _>>()?,
+        Some("json") => {
+            serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?
+        }
+        _ => return Err(DiffError::UnsupportedFile(path.display().to_string())),
+    };
+
+    Ok(snapshots
+        .into_iter()
+        .map(|snapshot| (snapshot.client(), snapshot))
+        .collect())
+}
+
+/// Loads the account snapshots of an accounts file, or replays them from a `.jsonl` event log.
+pub async fn load(path: impl AsRef<Path>) -> Result<Accounts, DiffError> {
+    let path = path.as_ref();
+    match extension(path) {
+        Some("jsonl") => Ok(EventLog::from_path(path).await?.snapshots().await?),
+        _ => read_accounts(path),
+    }
+}
+
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+/// How far apart two balances can be and still compare equal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tolerance {
+    /// The largest absolute difference between two equal balances.
+    pub absolute: Decimal,
+    /// The number of decimal places balances are rounded to before being compared.
+    pub decimal_places: Option<u32>,
+}
+
+impl Tolerance {
+    fn matches(&self, left: Decimal, right: Decimal) -> bool {
+        let (left, right) = match self.decimal_places {
+            Some(dp) => (left.round_dp(dp), right.round_dp(dp)),
+            None => (left, right),
+        };
+        (left - right).abs() <= self.absolute
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    /// The client is only in the right accounts.
+    Added,
+    /// The client is only in the left accounts.
+    Removed,
+    /// A field of the client account differs.
+    Changed,
+}
+
+/// A difference between two sets of account snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Difference {
+    pub client: u16,
+    pub change: Change,
+    /// The differing field, for [`Change::Changed`].
+    pub field: Option<&'static str>,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+impl Difference {
+    fn changed(
+        client: u16,
+        field: &'static str,
+        left: impl ToString,
+        right: impl ToString,
+    ) -> Self {
+        Self {
+            client,
+            change: Change::Changed,
+            field: Some(field),
+            left: Some(left.to_string()),
+            right: Some(right.to_string()),
+        }
+    }
+}
+
+/// Compares two sets of account snapshots, field by field, in client order.
+pub fn diff(left: &Accounts, right: &Accounts, tolerance: &Tolerance) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    for client in left.keys().chain(right.keys()).collect::<BTreeSet<_>>() {
+        let client = *client;
+        let (left, right) = match (left.get(&client), right.get(&client)) {
+            (Some(left), Some(right)) => (left, right),
+            (left, _) => {
+                differences.push(Difference {
+                    client,
+                    change: match left {
+                        Some(_) => Change::Removed,
+                        None => Change::Added,
+                    },
+                    field: None,
+                    left: None,
+                    right: None,
+                });
+                continue;
+            }
+        };
+
+        for (field, l, r) in [
+            ("available", left.available(), right.available()),
+            ("held", left.held(), right.held()),
+            ("reserved", left.reserved(), right.reserved()),
+            ("total", left.total(), right.total()),
+        ] {
+            if !tolerance.matches(l, r) {
+                differences.push(Difference::changed(client, field, l, r));
+            }
+        }
+        if left.locked() != right.locked() {
+            differences.push(Difference::changed(
+                client,
+                "locked",
+                left.locked(),
+                right.locked(),
+            ));
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn tolerance_absorbs_precision_differences() {
+        let exact = Tolerance::default();
+        assert!(exact.matches(dec!(1.50), dec!(1.5)));
+        assert!(!exact.matches(dec!(1.5), dec!(1.5001)));
+
+        let absolute = Tolerance {
+            absolute: dec!(0.001),
+            decimal_places: None,
+        };
+        assert!(absolute.matches(dec!(1.5), dec!(1.5001)));
+        assert!(!absolute.matches(dec!(1.5), dec!(1.502)));
+
+        let rounded = Tolerance {
+            absolute: Decimal::ZERO,
+            decimal_places: Some(2),
+        };
+        assert!(rounded.matches(dec!(1.5), dec!(1.5049)));
+        assert!(!rounded.matches(dec!(1.5), dec!(1.5051)));
+    }
+}
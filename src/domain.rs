@@ -1,13 +1,15 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::core::{Aggregate, Message, Root};
+use crate::core::{Aggregate, Message, Root, Version};
+use crate::fees::FeeSchedule;
+use crate::limits::DisputeRules;
 
 /// Transaction type enum
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
@@ -15,6 +17,37 @@ pub enum TransactionType {
     Dispute,
     Resolve,
     Chargeback,
+    Authorize,
+    Capture,
+    Void,
+    /// A fee charged by the engine, which input rows can't carry.
+    Fee,
+}
+
+impl TransactionType {
+    /// The lowercase name of the type, as in the input files.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Authorize => "authorize",
+            TransactionType::Capture => "capture",
+            TransactionType::Void => "void",
+            TransactionType::Fee => "fee",
+        }
+    }
+}
+
+impl std::str::FromStr for TransactionType {
+    type Err = serde::de::value::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use serde::de::IntoDeserializer;
+        Self::deserialize(s.into_deserializer())
+    }
 }
 
 #[derive(Default, Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
@@ -42,7 +75,10 @@ pub struct Transaction {
     #[serde(rename = "type")]
     pub transaction_type: TransactionType,
     /// The [`Transaction`] amount.
-    /// It will be enforced only for [`TransactionType::Deposit`] and [`TransactionType::Withdrawal`]
+    /// It will be enforced only for [`TransactionType::Deposit`], [`TransactionType::Withdrawal`]
+    /// and [`TransactionType::Authorize`]. A [`TransactionType::Capture`] without an amount
+    /// captures the whole remaining hold. A dispute, resolve or chargeback carrying an amount only
+    /// applies to that part of the disputed transaction.
     pub amount: Option<Decimal>,
 }
 
@@ -54,19 +90,28 @@ impl Message for Transaction {
 
 impl Transaction {
     pub fn can_be_disputed(&self) -> bool {
-        self.status == Status::Ok
-            && (self.transaction_type == TransactionType::Withdrawal
-                || self.transaction_type == TransactionType::Deposit)
+        matches!(self.status, Status::Ok | Status::Resolved) && self.is_disputable()
+    }
+
+    /// Whether the [`Transaction`] reached a [`Status`] that can't be reopened by a new dispute.
+    pub fn is_final(&self) -> bool {
+        matches!(self.status, Status::ChargedBack | Status::Declined)
     }
 
     pub fn can_complete_dispute(&self) -> bool {
-        self.status == Status::Disputed
-            && (self.transaction_type == TransactionType::Withdrawal
-                || self.transaction_type == TransactionType::Deposit)
+        self.status == Status::Disputed && self.is_disputable()
+    }
+
+    /// Whether the [`TransactionType`] can be disputed at all: deposits, withdrawals and fees.
+    fn is_disputable(&self) -> bool {
+        matches!(
+            self.transaction_type,
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Fee
+        )
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionEvent {
     WasOpened {
         tx_id: u32,
@@ -93,6 +138,114 @@ pub enum TransactionEvent {
         tx_id: u32,
         amount: Decimal,
     },
+    AuthorizationWasRecorded {
+        amount: Decimal,
+        transaction: Transaction,
+    },
+    CaptureWasRecorded {
+        tx_id: u32,
+        amount: Decimal,
+    },
+    VoidWasRecorded {
+        tx_id: u32,
+        amount: Decimal,
+    },
+    AuthorizationWasExpired {
+        tx_id: u32,
+        amount: Decimal,
+    },
+    FeeWasCharged {
+        /// The transaction the fee is charged for.
+        tx_id: u32,
+        /// The id the fee is recorded under, so that it can be disputed on its own.
+        fee_id: u32,
+        amount: Decimal,
+    },
+    FeeWasCollected {
+        account_holder_id: u16,
+        client_id: u16,
+        fee_id: u32,
+        amount: Decimal,
+    },
+    CreditLimitWasSet {
+        limit: Decimal,
+    },
+    WithdrawalLimitsWereSet {
+        limits: WithdrawalLimits,
+    },
+    DisputeWasDeclined {
+        tx_id: u32,
+        reason: DeclineReason,
+    },
+    TransactionsWereArchived {
+        tx_ids: Vec<u32>,
+    },
+    TransactionWasRestored {
+        archived: ArchivedTransaction,
+    },
+}
+
+impl TransactionEvent {
+    /// Whether the event records a transaction of the account holder, as opposed to fees,
+    /// limits, declined disputes and archiving.
+    pub fn is_transaction(&self) -> bool {
+        matches!(
+            self,
+            TransactionEvent::WasOpened { .. }
+                | TransactionEvent::DepositWasRecorded { .. }
+                | TransactionEvent::WithdrawalWasRecorded { .. }
+                | TransactionEvent::DisputeWasRecorded { .. }
+                | TransactionEvent::ResolveWasRecorded { .. }
+                | TransactionEvent::ChargebackWasRecorded { .. }
+                | TransactionEvent::AuthorizationWasRecorded { .. }
+                | TransactionEvent::CaptureWasRecorded { .. }
+                | TransactionEvent::VoidWasRecorded { .. }
+        )
+    }
+
+    /// The amount the event moved for the client transaction `tx_id`, if it records one.
+    fn recorded_amount(&self, tx_id: u32) -> Option<Decimal> {
+        match self {
+            TransactionEvent::WasOpened {
+                transaction,
+                tx_id: opened,
+                ..
+            } if *opened == tx_id => transaction.amount,
+            TransactionEvent::DepositWasRecorded {
+                amount,
+                transaction,
+            }
+            | TransactionEvent::WithdrawalWasRecorded {
+                amount,
+                transaction,
+            }
+            | TransactionEvent::AuthorizationWasRecorded {
+                amount,
+                transaction,
+            } if transaction.tx_id == tx_id => Some(*amount),
+            TransactionEvent::DisputeWasRecorded { tx_id: id, amount }
+            | TransactionEvent::ResolveWasRecorded { tx_id: id, amount }
+            | TransactionEvent::ChargebackWasRecorded { tx_id: id, amount }
+            | TransactionEvent::CaptureWasRecorded { tx_id: id, amount }
+            | TransactionEvent::VoidWasRecorded { tx_id: id, amount }
+                if *id == tx_id =>
+            {
+                Some(*amount)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The [`DisputeRules`] violation a dispute was declined for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeclineReason {
+    /// The disputed transaction is older than the dispute window.
+    Expired,
+    /// The disputed transaction has already been disputed the maximum number of times.
+    LimitReached,
+    /// The disputed transaction is in a final [`Status`].
+    Finalized,
 }
 
 impl Message for TransactionEvent {
@@ -104,34 +257,108 @@ impl Message for TransactionEvent {
             TransactionEvent::DisputeWasRecorded { .. } => "Dispute",
             TransactionEvent::ResolveWasRecorded { .. } => "Resolve",
             TransactionEvent::ChargebackWasRecorded { .. } => "Chargeback",
+            TransactionEvent::AuthorizationWasRecorded { .. } => "Authorization",
+            TransactionEvent::CaptureWasRecorded { .. } => "Capture",
+            TransactionEvent::VoidWasRecorded { .. } => "Void",
+            TransactionEvent::AuthorizationWasExpired { .. } => "AuthorizationExpired",
+            TransactionEvent::FeeWasCharged { .. } => "FeeCharged",
+            TransactionEvent::FeeWasCollected { .. } => "FeeCollected",
+            TransactionEvent::CreditLimitWasSet { .. } => "CreditLimitSet",
+            TransactionEvent::WithdrawalLimitsWereSet { .. } => "WithdrawalLimitsSet",
+            TransactionEvent::DisputeWasDeclined { .. } => "DisputeDeclined",
+            TransactionEvent::TransactionsWereArchived { .. } => "TransactionsArchived",
+            TransactionEvent::TransactionWasRestored { .. } => "TransactionRestored",
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
-pub enum BankAccountError {
-    #[error("Account has not been opened yet")]
-    NotOpenedYet,
-    #[error("Account has already been opened")]
-    AlreadyOpened,
-    #[error("Transaction with id {0} has negative amount")]
-    NegativeTransactionAttempted(u32),
-    #[error("No money to deposit has been specified")]
-    NoMoneyDeposited,
-    #[error("Insufficient available funds")]
-    InsufficientFunds,
-    #[error("Transfer transaction was destined to a different recipient: {0}")]
-    WrongTransactionRecipient(u32),
-    #[error("Duplicate transaction attempted: {0}")]
-    DuplicateTransactionRecipient(u32),
-    #[error("Invalid transaction dispute")]
-    InvalidTransactionDispute,
-    #[error("Invalid transaction chargeback")]
-    InvalidTransactionChargeBack,
-    #[error("Insufficient held funds")]
-    InsufficientHeldFunds,
-    #[error("Tried to apply transaction with id {tx} to a locked account {id}")]
-    LockedAccount { id: u16, tx: u32 },
+/// Declares an enum along with a `name` method returning the name of its variant, so that the
+/// names can't drift from the variants.
+macro_rules! named_variants {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $(
+                $(#[$variant_meta:meta])*
+                $variant:ident $(($($tuple:ty),* $(,)?))? $({ $($field:ident: $field_ty:ty),* $(,)? })?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $(
+                $(#[$variant_meta])*
+                $variant $(($($tuple),*))? $({ $($field: $field_ty),* })?
+            ),*
+        }
+
+        impl $name {
+            /// The name of the variant, e.g. to label metrics.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $($name::$variant { .. } => stringify!($variant)),*
+                }
+            }
+        }
+    };
+}
+
+named_variants! {
+    #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+    pub enum BankAccountError {
+        #[error("Account has not been opened yet")]
+        NotOpenedYet,
+        #[error("Account has already been opened")]
+        AlreadyOpened,
+        #[error("Transaction with id {0} has negative amount")]
+        NegativeTransactionAttempted(u32),
+        #[error("No money to deposit has been specified")]
+        NoMoneyDeposited,
+        #[error("Insufficient available funds")]
+        InsufficientFunds,
+        #[error("Event references unknown transaction: {0}")]
+        UnknownTransaction(u32),
+        #[error("Transfer transaction was destined to a different recipient: {0}")]
+        WrongTransactionRecipient(u32),
+        #[error("Duplicate transaction attempted: {0}")]
+        DuplicateTransactionRecipient(u32),
+        #[error("Invalid transaction dispute")]
+        InvalidTransactionDispute,
+        #[error("Invalid transaction chargeback")]
+        InvalidTransactionChargeBack,
+        #[error("Insufficient held funds")]
+        InsufficientHeldFunds,
+        #[error("Tried to apply transaction with id {tx} to a locked account {id}")]
+        LockedAccount { id: u16, tx: u32 },
+        #[error("No open authorization hold for transaction: {0}")]
+        UnknownAuthorization(u32),
+        #[error("Capture exceeds the remaining authorization hold for transaction: {0}")]
+        CaptureExceedsAuthorization(u32),
+        #[error("Credit limit cannot be negative")]
+        NegativeCreditLimit,
+        #[error("Withdrawal {0} exceeds the maximum withdrawal amount")]
+        WithdrawalAmountExceeded(u32),
+        #[error("Withdrawal {0} exceeds the maximum withdrawal total for the window")]
+        WithdrawalTotalExceeded(u32),
+        #[error("Withdrawal {0} exceeds the maximum number of withdrawals for the window")]
+        WithdrawalCountExceeded(u32),
+        #[error("Transaction {0} is too old to be disputed")]
+        DisputeWindowExpired(u32),
+        #[error("Transaction {0} has reached the maximum number of disputes")]
+        DisputeLimitReached(u32),
+        #[error("Transaction {0} is in a final state and can't be disputed again")]
+        DisputeFinalized(u32),
+        #[error("Dispute exceeds the undisputed amount of transaction: {0}")]
+        DisputeExceedsTransaction(u32),
+        #[error("Resolve exceeds the disputed amount of transaction: {0}")]
+        ResolveExceedsDispute(u32),
+        #[error("Chargeback exceeds the disputed amount of transaction: {0}")]
+        ChargebackExceedsDispute(u32),
+        #[error("Fee {0} can only be charged by the engine")]
+        UnexpectedFee(u32),
+        #[error("Client {0} is reserved for the house revenue account")]
+        HouseAccount(u16),
+    }
 }
 
 /// Balance for the account
@@ -141,6 +368,8 @@ pub struct Balance {
     pub available: Decimal,
     /// The total funds that are held for dispute. This should be equal to total - available amounts
     pub held: Decimal,
+    /// The total funds that are reserved by open authorization holds, pending capture or void.
+    pub reserved: Decimal,
 }
 
 impl Balance {
@@ -148,6 +377,98 @@ impl Balance {
         Balance {
             available,
             held: Decimal::default(),
+            reserved: Decimal::default(),
+        }
+    }
+}
+
+/// Risk controls evaluated on every withdrawal of an account.
+///
+/// The window is measured in transactions of the account holder, since transactions carry no
+/// timestamp, see [`TransactionEvent::is_transaction`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WithdrawalLimits {
+    /// The maximum amount of a single withdrawal.
+    pub max_amount: Option<Decimal>,
+    /// The number of transactions the rolling window spans, the withdrawal included, window limits
+    /// are ignored without one.
+    pub window: Option<Version>,
+    /// The maximum total withdrawn within the window.
+    pub max_window_total: Option<Decimal>,
+    /// The maximum number of withdrawals among the transactions within the window.
+    pub max_window_count: Option<usize>,
+}
+
+/// Funds reserved by an authorization, until captured, voided or expired.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Hold {
+    /// The amount of the authorization that has not been captured yet.
+    pub remaining: Decimal,
+    /// The number of transactions of the account once the authorization was recorded, used for
+    /// expiry.
+    pub authorized_at: Version,
+}
+
+/// Dispute lifecycle of a disputable transaction.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Lifecycle {
+    /// The number of transactions of the account once the transaction was recorded, used for the
+    /// dispute window.
+    pub recorded_at: Version,
+    /// The number of disputes recorded for the transaction.
+    pub disputes: u32,
+    /// The disputed amount that has not been resolved or charged back yet.
+    pub disputed: Decimal,
+}
+
+impl Lifecycle {
+    fn new(recorded_at: Version) -> Self {
+        Self {
+            recorded_at,
+            disputes: 0,
+            disputed: Decimal::ZERO,
+        }
+    }
+}
+
+/// A disputable transaction moved out of the account state, along with its dispute lifecycle.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(from = "ArchivedRecord", into = "ArchivedRecord")]
+pub struct ArchivedTransaction {
+    pub transaction: Transaction,
+    pub lifecycle: Lifecycle,
+}
+
+/// Serialized form of an [`ArchivedTransaction`], keeping the [`Status`] the [`Transaction`] skips.
+#[derive(Serialize, Deserialize)]
+struct ArchivedRecord {
+    transaction: Transaction,
+    status: Status,
+    lifecycle: Lifecycle,
+}
+
+impl From<ArchivedRecord> for ArchivedTransaction {
+    fn from(record: ArchivedRecord) -> Self {
+        let ArchivedRecord {
+            mut transaction,
+            status,
+            lifecycle,
+        } = record;
+        transaction.status = status;
+        Self {
+            transaction,
+            lifecycle,
+        }
+    }
+}
+
+impl From<ArchivedTransaction> for ArchivedRecord {
+    fn from(archived: ArchivedTransaction) -> Self {
+        Self {
+            status: archived.transaction.status.clone(),
+            transaction: archived.transaction,
+            lifecycle: archived.lifecycle,
         }
     }
 }
@@ -157,18 +478,238 @@ pub struct AccountSnapShot {
     client: u16,
     available: Decimal,
     held: Decimal,
+    /// Missing from the accounts files of engine versions without authorization holds.
+    #[serde(default)]
+    reserved: Decimal,
     total: Decimal,
     locked: bool,
 }
 
-#[derive(Debug, Clone)]
+impl AccountSnapShot {
+    /// Snapshots the balances of an account, rounded to four decimal places.
+    pub fn new(
+        client: u16,
+        available: Decimal,
+        held: Decimal,
+        reserved: Decimal,
+        locked: bool,
+    ) -> Self {
+        Self {
+            client,
+            available: available.round_dp(4),
+            held: held.round_dp(4),
+            reserved: reserved.round_dp(4),
+            total: (available + held + reserved).round_dp(4),
+            locked,
+        }
+    }
+
+    /// The client id of the snapshotted account.
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    pub fn reserved(&self) -> Decimal {
+        self.reserved
+    }
+
+    pub fn total(&self) -> Decimal {
+        self.total
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+}
+
+/// Balances of an account before and after a batch of transactions.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct BalanceChange {
+    client: u16,
+    available_before: Decimal,
+    available_after: Decimal,
+    held_before: Decimal,
+    held_after: Decimal,
+    reserved_before: Decimal,
+    reserved_after: Decimal,
+    total_before: Decimal,
+    total_after: Decimal,
+    locked_before: bool,
+    locked_after: bool,
+}
+
+impl BalanceChange {
+    /// Compares two snapshots of an account, a missing snapshot standing for an account not opened yet.
+    pub fn new(
+        client: u16,
+        before: Option<&AccountSnapShot>,
+        after: Option<&AccountSnapShot>,
+    ) -> Self {
+        let empty = AccountSnapShot {
+            client,
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            reserved: Decimal::ZERO,
+            total: Decimal::ZERO,
+            locked: false,
+        };
+        let before = before.unwrap_or(&empty);
+        let after = after.unwrap_or(&empty);
+
+        Self {
+            client,
+            available_before: before.available,
+            available_after: after.available,
+            held_before: before.held,
+            held_after: after.held,
+            reserved_before: before.reserved,
+            reserved_after: after.reserved,
+            total_before: before.total,
+            total_after: after.total,
+            locked_before: before.locked,
+            locked_after: after.locked,
+        }
+    }
+}
+
+/// Key of a transaction held in the account state, keeping the ids of the fees charged by the
+/// engine apart from the client transaction ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum TxKey {
+    Client(u32),
+    Fee(u32),
+}
+
+impl TxKey {
+    fn of(transaction: &Transaction) -> Self {
+        match transaction.transaction_type {
+            TransactionType::Fee => Self::Fee(transaction.tx_id),
+            _ => Self::Client(transaction.tx_id),
+        }
+    }
+
+    fn tx_id(self) -> u32 {
+        match self {
+            Self::Client(tx_id) | Self::Fee(tx_id) => tx_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     id: u16,
     balance: Balance,
-    pending_transactions: HashMap<u32, Transaction>,
+    pending_transactions: HashMap<TxKey, Transaction>,
+    lifecycles: HashMap<TxKey, Lifecycle>,
+    /// The deposits, withdrawals, fees and authorizations in the order they entered the account
+    /// state, along with the transaction count at that point, used for archiving.
+    retention: VecDeque<(Version, TxKey)>,
+    holds: HashMap<u32, Hold>,
+    /// The id the next fee is recorded under, counting down from [`u32::MAX`] in an id space of
+    /// its own.
+    next_fee_id: u32,
+    /// How far below zero the available funds are allowed to go.
+    credit_limit: Decimal,
+    withdrawal_limits: WithdrawalLimits,
+    /// The transaction count and amount of the withdrawals within the withdrawal limits window.
+    recent_withdrawals: VecDeque<(Version, Decimal)>,
+    /// The number of account holder transactions applied to the account so far, see
+    /// [`TransactionEvent::is_transaction`].
+    transactions: Version,
     locked: bool,
 }
 
+impl Account {
+    pub fn snapshot(&self) -> AccountSnapShot {
+        AccountSnapShot::new(
+            self.id,
+            self.balance.available,
+            self.balance.held,
+            self.balance.reserved,
+            self.locked,
+        )
+    }
+
+    /// Returns the given oldest retained transactions along with their lifecycles, as an archive
+    /// index stores them, matching the ids in retention order like
+    /// [`TransactionEvent::TransactionsWereArchived`] does.
+    pub fn archived(&self, tx_ids: &[u32]) -> Vec<ArchivedTransaction> {
+        let keys: Vec<_> = self
+            .retention
+            .iter()
+            .zip(tx_ids)
+            .take_while(|((_, key), tx_id)| key.tx_id() == **tx_id)
+            .map(|((_, key), _)| *key)
+            .collect();
+        self.archived_keys(&keys)
+    }
+
+    fn archived_keys(&self, keys: &[TxKey]) -> Vec<ArchivedTransaction> {
+        keys.iter()
+            .filter_map(|key| {
+                Some(ArchivedTransaction {
+                    transaction: self.pending_transactions.get(key)?.clone(),
+                    lifecycle: self.lifecycles.get(key)?.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The transaction a dispute, resolve or chargeback row referencing `tx_id` applies to: the
+    /// client transaction with that id, or else the fee charged under it.
+    fn key(&self, tx_id: u32) -> TxKey {
+        let client = TxKey::Client(tx_id);
+        let fee = TxKey::Fee(tx_id);
+        if !self.pending_transactions.contains_key(&client)
+            && self.pending_transactions.contains_key(&fee)
+        {
+            return fee;
+        }
+        client
+    }
+
+    /// The funds that can be spent, including the unused credit limit.
+    fn spendable(&self) -> Decimal {
+        self.balance.available + self.credit_limit
+    }
+
+    /// Looks up a disputed deposit, withdrawal or fee, failing with `invalid` for other transactions.
+    fn disputable_mut(
+        &mut self,
+        tx_id: u32,
+        invalid: BankAccountError,
+    ) -> Result<&mut Transaction, BankAccountError> {
+        let key = self.key(tx_id);
+        match self.pending_transactions.get_mut(&key) {
+            Some(tx) if tx.is_disputable() => Ok(tx),
+            Some(_) => Err(invalid),
+            None => Err(BankAccountError::UnknownTransaction(tx_id)),
+        }
+    }
+
+    /// Drops the recent withdrawals that fall out of the window ending at the `transactions` count.
+    fn prune_recent_withdrawals(&mut self, transactions: Version) {
+        let Some(window) = self.withdrawal_limits.window else {
+            self.recent_withdrawals.clear();
+            return;
+        };
+        while let Some((recorded_at, _)) = self.recent_withdrawals.front() {
+            if transactions - recorded_at < window {
+                break;
+            }
+            self.recent_withdrawals.pop_front();
+        }
+    }
+}
+
 impl Aggregate for Account {
     type Id = u16;
     type Event = TransactionEvent;
@@ -199,97 +740,243 @@ impl Aggregate for Account {
                     Ok(Account {
                         id: account_holder_id,
                         balance: Balance::new(amount),
-                        pending_transactions: HashMap::from([(tx_id, transaction)]),
+                        pending_transactions: HashMap::from([(TxKey::Client(tx_id), transaction)]),
+                        lifecycles: HashMap::from([(TxKey::Client(tx_id), Lifecycle::new(1))]),
+                        retention: VecDeque::from([(1, TxKey::Client(tx_id))]),
+                        holds: HashMap::new(),
+                        next_fee_id: u32::MAX,
+                        credit_limit: Decimal::ZERO,
+                        withdrawal_limits: WithdrawalLimits::default(),
+                        recent_withdrawals: VecDeque::new(),
+                        transactions: 1,
                         locked: false,
                     })
                 }
+                TransactionEvent::FeeWasCollected {
+                    account_holder_id,
+                    amount,
+                    ..
+                } => Ok(Account {
+                    id: account_holder_id,
+                    balance: Balance::new(amount),
+                    pending_transactions: HashMap::new(),
+                    lifecycles: HashMap::new(),
+                    retention: VecDeque::new(),
+                    holds: HashMap::new(),
+                    next_fee_id: u32::MAX,
+                    credit_limit: Decimal::ZERO,
+                    withdrawal_limits: WithdrawalLimits::default(),
+                    recent_withdrawals: VecDeque::new(),
+                    transactions: 0,
+                    locked: false,
+                }),
                 _ => Err(BankAccountError::NotOpenedYet),
             },
-            Some(mut account) => match event {
-                TransactionEvent::WasOpened { .. } => Err(BankAccountError::AlreadyOpened),
-                TransactionEvent::DepositWasRecorded {
-                    amount,
-                    transaction,
-                } => {
-                    account.balance.available += amount;
-                    account
-                        .pending_transactions
-                        .insert(transaction.tx_id, transaction);
-                    Ok(account)
+            Some(mut account) => {
+                account.apply_mut(event)?;
+                Ok(account)
+            }
+        }
+    }
+
+    /// Applies the event in place, validating it before mutating anything so that a rejected
+    /// event leaves the account untouched.
+    fn apply_mut(&mut self, event: Self::Event) -> Result<(), Self::Error> {
+        let transactions = self.transactions + Version::from(event.is_transaction());
+        match event {
+            TransactionEvent::WasOpened { .. } => return Err(BankAccountError::AlreadyOpened),
+            TransactionEvent::DepositWasRecorded {
+                amount,
+                transaction,
+            } => {
+                self.balance.available += amount;
+                let key = TxKey::Client(transaction.tx_id);
+                self.lifecycles.insert(key, Lifecycle::new(transactions));
+                self.retention.push_back((transactions, key));
+                self.pending_transactions.insert(key, transaction);
+            }
+            TransactionEvent::WithdrawalWasRecorded {
+                amount,
+                transaction,
+            } => {
+                self.balance.available -= amount;
+                let key = TxKey::Client(transaction.tx_id);
+                self.lifecycles.insert(key, Lifecycle::new(transactions));
+                self.retention.push_back((transactions, key));
+                self.pending_transactions.insert(key, transaction);
+                self.recent_withdrawals.push_back((transactions, amount));
+                self.prune_recent_withdrawals(transactions);
+            }
+            TransactionEvent::DisputeWasRecorded { tx_id, amount } => {
+                let tx = self.disputable_mut(tx_id, BankAccountError::InvalidTransactionDispute)?;
+                tx.status = Status::Disputed;
+                // the disputed amount is held, even if that overdraws the account
+                self.balance.available -= amount;
+                self.balance.held += amount;
+                if let Some(lifecycle) = self.lifecycles.get_mut(&self.key(tx_id)) {
+                    lifecycle.disputes += 1;
+                    lifecycle.disputed += amount;
                 }
-                TransactionEvent::WithdrawalWasRecorded {
-                    amount,
-                    transaction,
-                } => {
-                    account.balance.available -= amount;
-                    account
-                        .pending_transactions
-                        .insert(transaction.tx_id, transaction);
-                    Ok(account)
+            }
+            TransactionEvent::ResolveWasRecorded { tx_id, amount } => {
+                self.disputable_mut(tx_id, BankAccountError::WrongTransactionRecipient(tx_id))?;
+                let key = self.key(tx_id);
+                let outstanding = self
+                    .lifecycles
+                    .get(&key)
+                    .map_or(Decimal::ZERO, |lifecycle| lifecycle.disputed - amount);
+                if outstanding < Decimal::ZERO {
+                    return Err(BankAccountError::ResolveExceedsDispute(tx_id));
                 }
-                TransactionEvent::DisputeWasRecorded { tx_id, amount } => {
-                    match account
-                        .pending_transactions
-                        .entry(tx_id)
-                        .and_modify(|tx| tx.status = Status::Disputed)
-                    {
-                        Entry::Occupied(t) => match t.get().transaction_type {
-                            TransactionType::Deposit | TransactionType::Withdrawal => {
-                                if account.balance.available >= amount {
-                                    account.balance.available -= amount;
-                                }
-                                account.balance.held += amount;
-                                Ok(account)
-                            }
-                            _ => Err(BankAccountError::InvalidTransactionDispute),
-                        },
-                        Entry::Vacant(_) => {
-                            unreachable!("this should never happen")
-                        }
-                    }
+                if self.balance.held < amount {
+                    return Err(BankAccountError::InsufficientHeldFunds);
                 }
-                TransactionEvent::ResolveWasRecorded { tx_id, amount } => {
-                    match account
-                        .pending_transactions
-                        .entry(tx_id)
-                        .and_modify(|tx| tx.status = Status::Ok)
-                    {
-                        Entry::Occupied(t) => match t.get().transaction_type {
-                            TransactionType::Deposit | TransactionType::Withdrawal
-                                if account.balance.held >= amount =>
-                            {
-                                account.balance.held -= amount;
-                                account.balance.available += amount;
-                                Ok(account)
-                            }
-                            _ => Err(BankAccountError::InsufficientHeldFunds),
-                        },
-                        Entry::Vacant(_) => unreachable!(),
-                    }
+                // a partial resolve leaves the rest of the dispute open
+                if let (true, Some(tx)) = (
+                    outstanding.is_zero(),
+                    self.pending_transactions.get_mut(&key),
+                ) {
+                    tx.status = Status::Resolved;
                 }
-                TransactionEvent::ChargebackWasRecorded { tx_id, amount } => {
-                    match account
-                        .pending_transactions
-                        .entry(tx_id)
-                        .and_modify(|tx| tx.status = Status::ChargedBack)
-                    {
-                        Entry::Occupied(t) => match t.get().transaction_type {
-                            TransactionType::Deposit | TransactionType::Withdrawal
-                                if account.balance.held >= amount =>
-                            {
-                                account.balance.held -= amount;
-                                account.locked = true;
-                                Ok(account)
-                            }
-                            _ => Err(BankAccountError::InvalidTransactionChargeBack),
-                        },
-                        Entry::Vacant(_) => {
-                            unreachable!()
-                        }
+                if let Some(lifecycle) = self.lifecycles.get_mut(&key) {
+                    lifecycle.disputed = outstanding;
+                }
+                self.balance.held -= amount;
+                self.balance.available += amount;
+            }
+            TransactionEvent::ChargebackWasRecorded { tx_id, amount } => {
+                // the part of the dispute that isn't charged back is released
+                let key = self.key(tx_id);
+                let outstanding = self
+                    .lifecycles
+                    .get(&key)
+                    .map_or(amount, |lifecycle| lifecycle.disputed);
+                if self.balance.held < outstanding || outstanding < amount {
+                    return Err(BankAccountError::InvalidTransactionChargeBack);
+                }
+                let tx =
+                    self.disputable_mut(tx_id, BankAccountError::InvalidTransactionChargeBack)?;
+                tx.status = Status::ChargedBack;
+                if let Some(lifecycle) = self.lifecycles.get_mut(&key) {
+                    lifecycle.disputed = Decimal::ZERO;
+                }
+                self.balance.held -= outstanding;
+                self.balance.available += outstanding - amount;
+                self.locked = true;
+            }
+            TransactionEvent::AuthorizationWasRecorded {
+                amount,
+                transaction,
+            } => {
+                self.balance.available -= amount;
+                self.balance.reserved += amount;
+                self.holds.insert(
+                    transaction.tx_id,
+                    Hold {
+                        remaining: amount,
+                        authorized_at: transactions,
+                    },
+                );
+                // retained like deposits, so that archiving bounds them too
+                let key = TxKey::Client(transaction.tx_id);
+                self.lifecycles.insert(key, Lifecycle::new(transactions));
+                self.retention.push_back((transactions, key));
+                self.pending_transactions.insert(key, transaction);
+            }
+            TransactionEvent::CaptureWasRecorded { tx_id, amount } => {
+                let Entry::Occupied(mut hold) = self.holds.entry(tx_id) else {
+                    return Err(BankAccountError::UnknownAuthorization(tx_id));
+                };
+                if hold.get().remaining < amount {
+                    return Err(BankAccountError::CaptureExceedsAuthorization(tx_id));
+                }
+                hold.get_mut().remaining -= amount;
+                if hold.get().remaining.is_zero() {
+                    hold.remove();
+                }
+                self.balance.reserved -= amount;
+            }
+            TransactionEvent::VoidWasRecorded { tx_id, amount }
+            | TransactionEvent::AuthorizationWasExpired { tx_id, amount } => {
+                if self
+                    .holds
+                    .get(&tx_id)
+                    .is_none_or(|hold| hold.remaining != amount)
+                {
+                    return Err(BankAccountError::UnknownAuthorization(tx_id));
+                }
+                self.holds.remove(&tx_id);
+                self.balance.reserved -= amount;
+                self.balance.available += amount;
+            }
+            TransactionEvent::FeeWasCharged { fee_id, amount, .. } => {
+                let key = TxKey::Fee(fee_id);
+                if self.pending_transactions.contains_key(&key) {
+                    return Err(BankAccountError::DuplicateTransactionRecipient(fee_id));
+                }
+                self.balance.available -= amount;
+                self.next_fee_id = fee_id.saturating_sub(1);
+                self.lifecycles.insert(key, Lifecycle::new(transactions));
+                self.retention.push_back((transactions, key));
+                self.pending_transactions.insert(
+                    key,
+                    Transaction {
+                        status: Status::Ok,
+                        client_id: self.id,
+                        tx_id: fee_id,
+                        transaction_type: TransactionType::Fee,
+                        amount: Some(amount),
+                    },
+                );
+            }
+            TransactionEvent::FeeWasCollected { amount, .. } => {
+                self.balance.available += amount;
+            }
+            TransactionEvent::CreditLimitWasSet { limit } => {
+                self.credit_limit = limit;
+            }
+            TransactionEvent::DisputeWasDeclined { tx_id, reason } => {
+                // only the attempt is declined while part of the transaction is still disputed,
+                // so that the held funds can still be resolved or charged back
+                if let (DeclineReason::Expired | DeclineReason::LimitReached, Some(tx)) =
+                    (reason, self.pending_transactions.get_mut(&self.key(tx_id)))
+                {
+                    if tx.status != Status::Disputed {
+                        tx.status = Status::Declined;
                     }
                 }
-            },
+            }
+            TransactionEvent::TransactionsWereArchived { tx_ids } => {
+                // archiving always drains the oldest retained transactions, in order
+                if let Some((_, tx_id)) = tx_ids.iter().enumerate().find(|(position, tx_id)| {
+                    self.retention
+                        .get(*position)
+                        .map(|(_, retained)| retained.tx_id())
+                        != Some(**tx_id)
+                }) {
+                    return Err(BankAccountError::UnknownTransaction(*tx_id));
+                }
+                for (_, key) in self.retention.drain(..tx_ids.len()) {
+                    self.pending_transactions.remove(&key);
+                    self.lifecycles.remove(&key);
+                }
+            }
+            TransactionEvent::TransactionWasRestored { archived } => {
+                let key = TxKey::of(&archived.transaction);
+                if self.pending_transactions.contains_key(&key) {
+                    return Err(BankAccountError::DuplicateTransactionRecipient(key.tx_id()));
+                }
+                self.lifecycles.insert(key, archived.lifecycle);
+                self.pending_transactions.insert(key, archived.transaction);
+                self.retention.push_back((transactions, key));
+            }
+            TransactionEvent::WithdrawalLimitsWereSet { limits } => {
+                self.withdrawal_limits = limits;
+                self.prune_recent_withdrawals(transactions);
+            }
         }
+        self.transactions = transactions;
+        Ok(())
     }
 }
 
@@ -345,7 +1032,10 @@ impl BankAccountRoot {
                 transaction.tx_id,
             ));
         }
-        if self.pending_transactions.get(&transaction.tx_id).is_some() {
+        if self
+            .pending_transactions
+            .contains_key(&TxKey::Client(transaction.tx_id))
+        {
             return Err(BankAccountError::DuplicateTransactionRecipient(
                 transaction.tx_id,
             ));
@@ -375,16 +1065,21 @@ impl BankAccountRoot {
             ));
         }
 
-        if self.balance.available < amount {
+        if self.spendable() < amount {
             return Err(BankAccountError::InsufficientFunds);
         }
 
-        if self.pending_transactions.get(&transaction.tx_id).is_some() {
+        if self
+            .pending_transactions
+            .contains_key(&TxKey::Client(transaction.tx_id))
+        {
             return Err(BankAccountError::DuplicateTransactionRecipient(
                 transaction.tx_id,
             ));
         }
 
+        self.check_withdrawal_limits(transaction.tx_id, amount)?;
+
         self.record_that(
             TransactionEvent::WithdrawalWasRecorded {
                 amount,
@@ -394,20 +1089,90 @@ impl BankAccountRoot {
         )
     }
 
-    pub fn dispute(&mut self, transaction: Transaction) -> Result<(), BankAccountError> {
+    fn check_withdrawal_limits(&self, tx_id: u32, amount: Decimal) -> Result<(), BankAccountError> {
+        let limits = &self.withdrawal_limits;
+        if limits
+            .max_amount
+            .is_some_and(|max_amount| amount > max_amount)
+        {
+            return Err(BankAccountError::WithdrawalAmountExceeded(tx_id));
+        }
+
+        let Some(window) = limits.window else {
+            return Ok(());
+        };
+        // the withdrawal would be the next transaction
+        let transactions = self.transactions + 1;
+        let in_window: Vec<_> = self
+            .recent_withdrawals
+            .iter()
+            .filter(|(recorded_at, _)| transactions - recorded_at < window)
+            .map(|(_, amount)| *amount)
+            .collect();
+
+        if limits
+            .max_window_total
+            .is_some_and(|max_total| in_window.iter().sum::<Decimal>() + amount > max_total)
+        {
+            return Err(BankAccountError::WithdrawalTotalExceeded(tx_id));
+        }
+        if limits
+            .max_window_count
+            .is_some_and(|max_count| in_window.len() + 1 > max_count)
+        {
+            return Err(BankAccountError::WithdrawalCountExceeded(tx_id));
+        }
+        Ok(())
+    }
+
+    /// Disputes a deposit or withdrawal, subject to the given [`DisputeRules`].
+    ///
+    /// A dispute violating the rules is recorded as declined before the matching error is returned.
+    pub fn dispute(
+        &mut self,
+        transaction: Transaction,
+        rules: &DisputeRules,
+    ) -> Result<(), BankAccountError> {
         if self.locked {
             return Err(BankAccountError::LockedAccount {
                 id: transaction.client_id,
                 tx: transaction.tx_id,
             });
         }
-        match self.pending_transactions.get(&transaction.tx_id) {
-            Some(disputed_tx) if disputed_tx.can_be_disputed() => {
-                let disputed = disputed_tx.clone();
+        if let Some(reason) = self.dispute_violation(transaction.tx_id, rules) {
+            self.record_that(
+                TransactionEvent::DisputeWasDeclined {
+                    tx_id: transaction.tx_id,
+                    reason,
+                }
+                .into(),
+            )?;
+            return Err(match reason {
+                DeclineReason::Expired => BankAccountError::DisputeWindowExpired(transaction.tx_id),
+                DeclineReason::LimitReached => {
+                    BankAccountError::DisputeLimitReached(transaction.tx_id)
+                }
+                DeclineReason::Finalized => BankAccountError::DisputeFinalized(transaction.tx_id),
+            });
+        }
+        match self.pending_transactions.get(&self.key(transaction.tx_id)) {
+            Some(disputed_tx)
+                if disputed_tx.can_be_disputed() || disputed_tx.can_complete_dispute() =>
+            {
+                let undisputed = disputed_tx
+                    .amount
+                    .ok_or(BankAccountError::NoMoneyDeposited)?
+                    - self.disputed_amount(transaction.tx_id);
+                let amount = Self::partial_amount(&transaction, undisputed)?;
+                if amount.is_zero() || amount > undisputed {
+                    return Err(BankAccountError::DisputeExceedsTransaction(
+                        transaction.tx_id,
+                    ));
+                }
                 self.record_that(
                     TransactionEvent::DisputeWasRecorded {
-                        tx_id: disputed.tx_id,
-                        amount: disputed.amount.ok_or(BankAccountError::NoMoneyDeposited)?,
+                        tx_id: transaction.tx_id,
+                        amount,
                     }
                     .into(),
                 )
@@ -418,6 +1183,52 @@ impl BankAccountRoot {
         }
     }
 
+    /// The disputed amount of a transaction that has not been resolved or charged back yet.
+    fn disputed_amount(&self, tx_id: u32) -> Decimal {
+        self.lifecycles
+            .get(&self.key(tx_id))
+            .map(|lifecycle| lifecycle.disputed)
+            .unwrap_or_default()
+    }
+
+    /// The amount of a dispute, resolve or chargeback row, defaulting to `full` when omitted.
+    fn partial_amount(
+        transaction: &Transaction,
+        full: Decimal,
+    ) -> Result<Decimal, BankAccountError> {
+        let amount = transaction.amount.unwrap_or(full);
+        if amount < Decimal::ZERO {
+            return Err(BankAccountError::NegativeTransactionAttempted(
+                transaction.tx_id,
+            ));
+        }
+        Ok(amount)
+    }
+
+    fn dispute_violation(&self, tx_id: u32, rules: &DisputeRules) -> Option<DeclineReason> {
+        // a resolved transaction can only be disputed again when a dispute limit bounds it
+        let key = self.key(tx_id);
+        if self.pending_transactions.get(&key).is_some_and(|tx| {
+            tx.is_final() || (tx.status == Status::Resolved && rules.max_disputes.is_none())
+        }) {
+            return Some(DeclineReason::Finalized);
+        }
+        let lifecycle = self.lifecycles.get(&key)?;
+        if rules
+            .max_age
+            .is_some_and(|max_age| self.transactions - lifecycle.recorded_at > max_age)
+        {
+            return Some(DeclineReason::Expired);
+        }
+        if rules
+            .max_disputes
+            .is_some_and(|max_disputes| lifecycle.disputes >= max_disputes)
+        {
+            return Some(DeclineReason::LimitReached);
+        }
+        None
+    }
+
     pub fn resolve(&mut self, transaction: Transaction) -> Result<(), BankAccountError> {
         if self.locked {
             return Err(BankAccountError::LockedAccount {
@@ -425,13 +1236,17 @@ impl BankAccountRoot {
                 tx: transaction.tx_id,
             });
         }
-        match self.pending_transactions.get(&transaction.tx_id) {
+        match self.pending_transactions.get(&self.key(transaction.tx_id)) {
             Some(disputed_tx) if disputed_tx.can_complete_dispute() => {
-                let disputed = disputed_tx.clone();
+                let disputed = self.disputed_amount(transaction.tx_id);
+                let amount = Self::partial_amount(&transaction, disputed)?;
+                if amount.is_zero() || amount > disputed {
+                    return Err(BankAccountError::ResolveExceedsDispute(transaction.tx_id));
+                }
                 self.record_that(
                     TransactionEvent::ResolveWasRecorded {
-                        amount: disputed.amount.ok_or(BankAccountError::NoMoneyDeposited)?,
-                        tx_id: disputed.tx_id,
+                        amount,
+                        tx_id: transaction.tx_id,
                     }
                     .into(),
                 )
@@ -449,13 +1264,19 @@ impl BankAccountRoot {
                 tx: transaction.tx_id,
             });
         }
-        match self.pending_transactions.get(&transaction.tx_id) {
+        match self.pending_transactions.get(&self.key(transaction.tx_id)) {
             Some(disputed_tx) if disputed_tx.can_complete_dispute() => {
-                let disputed = disputed_tx.clone();
+                let disputed = self.disputed_amount(transaction.tx_id);
+                let amount = Self::partial_amount(&transaction, disputed)?;
+                if amount.is_zero() || amount > disputed {
+                    return Err(BankAccountError::ChargebackExceedsDispute(
+                        transaction.tx_id,
+                    ));
+                }
                 self.record_that(
                     TransactionEvent::ChargebackWasRecorded {
-                        amount: disputed.amount.ok_or(BankAccountError::NoMoneyDeposited)?,
-                        tx_id: disputed.tx_id,
+                        amount,
+                        tx_id: transaction.tx_id,
                     }
                     .into(),
                 )
@@ -466,14 +1287,287 @@ impl BankAccountRoot {
         }
     }
 
-    pub fn snapshot(&self) -> AccountSnapShot {
-        AccountSnapShot {
-            client: self.id,
-            available: self.balance.available.round_dp(4),
-            held: self.balance.held.round_dp(4),
-            total: (self.balance.available + self.balance.held).round_dp(4),
-            locked: self.locked,
+    pub fn authorize(&mut self, transaction: Transaction) -> Result<(), BankAccountError> {
+        if self.locked {
+            return Err(BankAccountError::LockedAccount {
+                id: transaction.client_id,
+                tx: transaction.tx_id,
+            });
+        }
+        let amount = transaction
+            .amount
+            .ok_or(BankAccountError::NoMoneyDeposited)?;
+        if amount < Decimal::ZERO {
+            return Err(BankAccountError::NegativeTransactionAttempted(
+                transaction.tx_id,
+            ));
+        }
+
+        if self.spendable() < amount {
+            return Err(BankAccountError::InsufficientFunds);
+        }
+
+        if self
+            .pending_transactions
+            .contains_key(&TxKey::Client(transaction.tx_id))
+        {
+            return Err(BankAccountError::DuplicateTransactionRecipient(
+                transaction.tx_id,
+            ));
+        }
+
+        self.record_that(
+            TransactionEvent::AuthorizationWasRecorded {
+                amount,
+                transaction,
+            }
+            .into(),
+        )
+    }
+
+    pub fn capture(&mut self, transaction: Transaction) -> Result<(), BankAccountError> {
+        if self.locked {
+            return Err(BankAccountError::LockedAccount {
+                id: transaction.client_id,
+                tx: transaction.tx_id,
+            });
         }
+        let Some(hold) = self.holds.get(&transaction.tx_id) else {
+            return Err(BankAccountError::UnknownAuthorization(transaction.tx_id));
+        };
+        let amount = transaction.amount.unwrap_or(hold.remaining);
+        if amount < Decimal::ZERO {
+            return Err(BankAccountError::NegativeTransactionAttempted(
+                transaction.tx_id,
+            ));
+        }
+        if amount > hold.remaining {
+            return Err(BankAccountError::CaptureExceedsAuthorization(
+                transaction.tx_id,
+            ));
+        }
+
+        self.record_that(
+            TransactionEvent::CaptureWasRecorded {
+                tx_id: transaction.tx_id,
+                amount,
+            }
+            .into(),
+        )
+    }
+
+    pub fn void(&mut self, transaction: Transaction) -> Result<(), BankAccountError> {
+        if self.locked {
+            return Err(BankAccountError::LockedAccount {
+                id: transaction.client_id,
+                tx: transaction.tx_id,
+            });
+        }
+        let Some(amount) = self
+            .holds
+            .get(&transaction.tx_id)
+            .map(|hold| hold.remaining)
+        else {
+            return Err(BankAccountError::UnknownAuthorization(transaction.tx_id));
+        };
+
+        self.record_that(
+            TransactionEvent::VoidWasRecorded {
+                tx_id: transaction.tx_id,
+                amount,
+            }
+            .into(),
+        )
+    }
+
+    /// Releases every authorization hold that has been open for at least `after` later client
+    /// transactions.
+    ///
+    /// Like the dispute window, fees, declined disputes and other bookkeeping events aren't
+    /// counted. Holds never expire when `after` is [`None`], and are left untouched on locked accounts.
+    pub fn expire_holds(&mut self, after: Option<Version>) -> Result<(), BankAccountError> {
+        let Some(after) = after else {
+            return Ok(());
+        };
+        if self.locked {
+            return Ok(());
+        }
+
+        let mut expired: Vec<_> = self
+            .holds
+            .iter()
+            .filter(|(_, hold)| self.transactions - hold.authorized_at >= after)
+            .map(|(tx_id, hold)| (*tx_id, hold.remaining))
+            .collect();
+        expired.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+
+        for (tx_id, amount) in expired {
+            self.record_that(TransactionEvent::AuthorizationWasExpired { tx_id, amount }.into())?;
+        }
+        Ok(())
+    }
+
+    /// The number of transactions held in the account state, open to disputes or duplicate checks.
+    pub fn retained_transactions(&self) -> usize {
+        self.pending_transactions.len()
+    }
+
+    /// Whether the account state holds the given client transaction.
+    pub fn has_transaction(&self, tx_id: u32) -> bool {
+        self.pending_transactions
+            .contains_key(&TxKey::Client(tx_id))
+    }
+
+    /// Archives the transactions that entered the account state at least `after` client
+    /// transactions ago, returning them for the archive index.
+    ///
+    /// Like the dispute window, fees, declined disputes and other bookkeeping events aren't
+    /// counted.
+    pub fn archive_transactions(
+        &mut self,
+        after: Option<Version>,
+    ) -> Result<Vec<ArchivedTransaction>, BankAccountError> {
+        let Some(after) = after else {
+            return Ok(Vec::new());
+        };
+        if self.locked {
+            return Ok(Vec::new());
+        }
+
+        let keys: Vec<_> = self
+            .retention
+            .iter()
+            .take_while(|(retained_at, _)| self.transactions - retained_at >= after)
+            .map(|(_, key)| *key)
+            .collect();
+        let archived = self.archived_keys(&keys);
+        if archived.is_empty() {
+            return Ok(archived);
+        }
+
+        let tx_ids = archived.iter().map(|a| a.transaction.tx_id).collect();
+        self.record_that(TransactionEvent::TransactionsWereArchived { tx_ids }.into())?;
+        Ok(archived)
+    }
+
+    /// Brings an archived transaction back into the account state, e.g. for a late dispute.
+    ///
+    /// Nothing is recorded when the account state already holds the transaction.
+    pub fn restore(&mut self, archived: ArchivedTransaction) -> Result<(), BankAccountError> {
+        if self
+            .pending_transactions
+            .contains_key(&TxKey::of(&archived.transaction))
+        {
+            return Ok(());
+        }
+        self.record_that(TransactionEvent::TransactionWasRestored { archived }.into())
+    }
+
+    /// Sets how far below zero the available funds of the account are allowed to go.
+    ///
+    /// Nothing is recorded when the account already has the given limit.
+    pub fn set_credit_limit(&mut self, limit: Decimal) -> Result<(), BankAccountError> {
+        if limit < Decimal::ZERO {
+            return Err(BankAccountError::NegativeCreditLimit);
+        }
+        if self.credit_limit == limit {
+            return Ok(());
+        }
+        self.record_that(TransactionEvent::CreditLimitWasSet { limit }.into())
+    }
+
+    /// Sets the risk controls evaluated on every withdrawal of the account.
+    ///
+    /// Nothing is recorded when the account already has the given limits.
+    pub fn set_withdrawal_limits(
+        &mut self,
+        limits: WithdrawalLimits,
+    ) -> Result<(), BankAccountError> {
+        if self.withdrawal_limits == limits {
+            return Ok(());
+        }
+        self.record_that(TransactionEvent::WithdrawalLimitsWereSet { limits }.into())
+    }
+
+    /// Charges the fee due for an accepted `transaction` under the given [`FeeSchedule`].
+    ///
+    /// The fee is based on the amount the transaction actually moved, e.g. the disputed part of a
+    /// deposit or the captured part of an authorization, as recorded by the last event for the
+    /// transaction. It is recorded as a disputable transaction of its own, under an id counting
+    /// down from [`u32::MAX`] in an id space apart from the client transactions. Returns the id
+    /// and amount of the fee charged, if any.
+    pub fn charge_fee(
+        &mut self,
+        schedule: &FeeSchedule,
+        transaction: &Transaction,
+    ) -> Result<Option<(u32, Decimal)>, BankAccountError> {
+        let amount = self
+            .uncommitted_events()
+            .iter()
+            .rev()
+            .find_map(|event| event.message.recorded_amount(transaction.tx_id))
+            .unwrap_or_default();
+        let fee = schedule.fee(&transaction.transaction_type, amount);
+        if fee <= Decimal::ZERO {
+            return Ok(None);
+        }
+
+        // chargebacks are forced on the client, so the penalty may overdraw the account
+        if transaction.transaction_type != TransactionType::Chargeback && self.spendable() < fee {
+            return Err(BankAccountError::InsufficientFunds);
+        }
+
+        let mut fee_id = self.next_fee_id;
+        while self.pending_transactions.contains_key(&TxKey::Fee(fee_id)) {
+            fee_id -= 1;
+        }
+        self.record_that(
+            TransactionEvent::FeeWasCharged {
+                tx_id: transaction.tx_id,
+                fee_id,
+                amount: fee,
+            }
+            .into(),
+        )?;
+        Ok(Some((fee_id, fee)))
+    }
+
+    /// Opens the house revenue account with the first fee collected from a client.
+    pub fn open_house(
+        house_id: u16,
+        client_id: u16,
+        fee_id: u32,
+        amount: Decimal,
+    ) -> Result<Self, BankAccountError> {
+        Root::<Account>::record_new(
+            TransactionEvent::FeeWasCollected {
+                account_holder_id: house_id,
+                client_id,
+                fee_id,
+                amount,
+            }
+            .into(),
+        )
+        .map(Self)
+    }
+
+    /// Credits a fee charged to a client account to this house revenue account.
+    pub fn collect_fee(
+        &mut self,
+        client_id: u16,
+        fee_id: u32,
+        amount: Decimal,
+    ) -> Result<(), BankAccountError> {
+        let account_holder_id = self.id;
+        self.record_that(
+            TransactionEvent::FeeWasCollected {
+                account_holder_id,
+                client_id,
+                fee_id,
+                amount,
+            }
+            .into(),
+        )
     }
 }
 
@@ -482,7 +1576,7 @@ mod tests {
     use rust_decimal_macros::dec;
 
     use crate::core::repository::{Getter, Saver};
-    use crate::core::{EventSourced, GetError, InMemory};
+    use crate::core::{Appender, Check, Envelope, EventSourced, GetError, InMemory};
 
     use super::*;
 
@@ -523,4 +1617,411 @@ mod tests {
             .expect("account record should have saved");
         assert_eq!(1, root.id);
     }
+
+    #[test]
+    fn partial_disputes_keep_totals_consistent() {
+        let row = |transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 1,
+            transaction_type,
+            amount,
+        };
+        let rules = DisputeRules::default();
+        let mut root =
+            BankAccountRoot::open(row(TransactionType::Deposit, Some(dec!(100)))).expect("opened");
+
+        root.dispute(row(TransactionType::Dispute, Some(dec!(30))), &rules)
+            .expect("partial dispute");
+        root.dispute(row(TransactionType::Dispute, Some(dec!(20))), &rules)
+            .expect("second partial dispute");
+        assert_eq!(
+            Err(BankAccountError::DisputeExceedsTransaction(1)),
+            root.dispute(row(TransactionType::Dispute, Some(dec!(60))), &rules)
+        );
+        root.resolve(row(TransactionType::Resolve, Some(dec!(10))))
+            .expect("partial resolve");
+        assert_eq!(
+            Err(BankAccountError::ChargebackExceedsDispute(1)),
+            root.chargeback(row(TransactionType::Chargeback, Some(dec!(50))))
+        );
+
+        let snapshot = root.snapshot();
+        assert_eq!(dec!(60), snapshot.available);
+        assert_eq!(dec!(40), snapshot.held);
+        assert_eq!(dec!(100), snapshot.total);
+
+        root.chargeback(row(TransactionType::Chargeback, Some(dec!(25))))
+            .expect("partial chargeback");
+        let snapshot = root.snapshot();
+        assert_eq!(dec!(75), snapshot.available);
+        assert_eq!(Decimal::ZERO, snapshot.held);
+        assert_eq!(dec!(75), snapshot.total);
+        assert!(snapshot.locked);
+    }
+
+    #[test]
+    fn declined_dispute_leaves_the_open_dispute_completable() {
+        let row = |transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 1,
+            transaction_type,
+            amount,
+        };
+        let rules = DisputeRules {
+            max_age: None,
+            max_disputes: Some(1),
+        };
+        let mut root =
+            BankAccountRoot::open(row(TransactionType::Deposit, Some(dec!(100)))).expect("opened");
+        root.dispute(row(TransactionType::Dispute, Some(dec!(30))), &rules)
+            .expect("partial dispute");
+        assert_eq!(
+            Err(BankAccountError::DisputeLimitReached(1)),
+            root.dispute(row(TransactionType::Dispute, Some(dec!(20))), &rules)
+        );
+
+        let mut resolved = root.clone();
+        resolved
+            .resolve(row(TransactionType::Resolve, None))
+            .expect("resolve of the held amount");
+        assert_eq!(dec!(100), resolved.snapshot().available);
+        assert_eq!(Decimal::ZERO, resolved.snapshot().held);
+
+        root.chargeback(row(TransactionType::Chargeback, None))
+            .expect("chargeback of the held amount");
+        assert_eq!(dec!(70), root.snapshot().total);
+        assert_eq!(Decimal::ZERO, root.snapshot().held);
+    }
+
+    #[test]
+    fn dispute_window_only_counts_client_transactions() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let rules = DisputeRules {
+            max_age: Some(1),
+            max_disputes: None,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10))))
+            .expect("opened");
+        for limit in [dec!(1), dec!(2), dec!(3)] {
+            root.set_credit_limit(limit).expect("credit limit set");
+        }
+        root.deposit(row(2, TransactionType::Deposit, Some(dec!(5))))
+            .expect("deposit");
+        root.dispute(row(1, TransactionType::Dispute, None), &rules)
+            .expect("dispute within the window");
+
+        root.deposit(row(3, TransactionType::Deposit, Some(dec!(5))))
+            .expect("deposit");
+        assert_eq!(
+            Err(BankAccountError::DisputeWindowExpired(2)),
+            root.dispute(row(2, TransactionType::Dispute, None), &rules)
+        );
+    }
+
+    #[test]
+    fn withdrawal_window_only_counts_client_transactions() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(100))))
+            .expect("opened");
+        root.set_withdrawal_limits(WithdrawalLimits {
+            window: Some(3),
+            max_window_count: Some(2),
+            ..Default::default()
+        })
+        .expect("limits set");
+        root.withdrawal(row(2, TransactionType::Withdrawal, Some(dec!(1))))
+            .expect("withdrawal");
+        root.withdrawal(row(3, TransactionType::Withdrawal, Some(dec!(1))))
+            .expect("second withdrawal");
+        for limit in [dec!(1), dec!(2), dec!(3)] {
+            root.set_credit_limit(limit).expect("credit limit set");
+        }
+        assert_eq!(
+            Err(BankAccountError::WithdrawalCountExceeded(4)),
+            root.withdrawal(row(4, TransactionType::Withdrawal, Some(dec!(1))))
+        );
+
+        root.deposit(row(5, TransactionType::Deposit, Some(dec!(1))))
+            .expect("deposit");
+        root.withdrawal(row(6, TransactionType::Withdrawal, Some(dec!(1))))
+            .expect("withdrawal once the window moved on");
+    }
+
+    #[tokio::test]
+    async fn repository_reports_corrupt_stream_version() {
+        let event_store = InMemory::<u16, TransactionEvent>::default();
+        event_store
+            .append(
+                7,
+                Check::MustBe(0),
+                vec![
+                    Envelope::from(TransactionEvent::WasOpened {
+                        tx_id: 1,
+                        account_holder_id: 7,
+                        transaction: Transaction {
+                            status: Default::default(),
+                            client_id: 7,
+                            tx_id: 1,
+                            transaction_type: TransactionType::Deposit,
+                            amount: Some(dec!(5)),
+                        },
+                    }),
+                    Envelope::from(TransactionEvent::ChargebackWasRecorded {
+                        tx_id: 2,
+                        amount: dec!(5),
+                    }),
+                ],
+            )
+            .await
+            .expect("events appended");
+
+        let account_repository = EventSourced::<Account, _>::from(event_store);
+        assert!(matches!(
+            account_repository.get(&7).await,
+            Err(GetError::Rehydrate { stream_id, version: 2, .. }) if stream_id == "7"
+        ));
+    }
+
+    #[test]
+    fn archiving_bounds_retained_transactions() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root =
+            BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(1)))).expect("opened");
+        let mut archive = Vec::new();
+        for tx_id in 2..=1_000 {
+            archive.extend(root.archive_transactions(Some(10)).expect("archived"));
+            root.deposit(row(tx_id, TransactionType::Deposit, Some(dec!(1))))
+                .expect("deposit");
+            assert!(root.pending_transactions.len() <= 11);
+            assert_eq!(root.pending_transactions.len(), root.retention.len());
+            assert_eq!(root.pending_transactions.len(), root.lifecycles.len());
+        }
+        assert_eq!(1_000, archive.len() + root.retained_transactions());
+
+        let first = archive.remove(0);
+        assert_eq!(1, first.transaction.tx_id);
+        assert!(!root.has_transaction(1));
+        root.restore(first).expect("restored");
+        root.dispute(
+            row(1, TransactionType::Dispute, None),
+            &DisputeRules::default(),
+        )
+        .expect("late dispute");
+        assert_eq!(dec!(1), root.snapshot().held);
+        assert_eq!(dec!(1000), root.snapshot().total);
+    }
+
+    #[test]
+    fn archiving_only_counts_client_transactions() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10))))
+            .expect("opened");
+        for limit in [dec!(1), dec!(2), dec!(3)] {
+            root.set_credit_limit(limit).expect("credit limit set");
+        }
+        assert!(root
+            .archive_transactions(Some(1))
+            .expect("archived")
+            .is_empty());
+
+        root.deposit(row(2, TransactionType::Deposit, Some(dec!(5))))
+            .expect("deposit");
+        let archived = root.archive_transactions(Some(1)).expect("archived");
+        assert_eq!(
+            vec![1],
+            archived
+                .iter()
+                .map(|a| a.transaction.tx_id)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn archiving_bounds_retained_authorizations() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10_000))))
+            .expect("opened");
+        for tx_id in (2..2_000).step_by(3) {
+            archive_and(&mut root, |root| {
+                root.authorize(row(tx_id, TransactionType::Authorize, Some(dec!(2))))
+            });
+            archive_and(&mut root, |root| {
+                root.capture(row(tx_id, TransactionType::Capture, None))
+            });
+            archive_and(&mut root, |root| {
+                root.authorize(row(tx_id + 1, TransactionType::Authorize, Some(dec!(1))))
+            });
+            archive_and(&mut root, |root| {
+                root.void(row(tx_id + 1, TransactionType::Void, None))
+            });
+            archive_and(&mut root, |root| {
+                root.authorize(row(tx_id + 2, TransactionType::Authorize, Some(dec!(1))))
+            });
+            archive_and(&mut root, |root| root.expire_holds(Some(1)));
+        }
+        assert!(root.retained_transactions() <= 11);
+        root.expire_holds(Some(0)).expect("holds expired");
+        assert_eq!(dec!(10_000) - dec!(2) * dec!(666), root.snapshot().total);
+        assert_eq!(Decimal::ZERO, root.snapshot().reserved);
+    }
+
+    /// Archives with a window of 10 before running `transaction`, like the service does.
+    fn archive_and(
+        root: &mut BankAccountRoot,
+        transaction: impl FnOnce(&mut BankAccountRoot) -> Result<(), BankAccountError>,
+    ) {
+        root.archive_transactions(Some(10)).expect("archived");
+        transaction(root).expect("transaction recorded");
+    }
+
+    #[test]
+    fn apply_mut_leaves_account_untouched_on_error() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10))))
+            .expect("opened");
+        root.deposit(row(2, TransactionType::Deposit, Some(dec!(5))))
+            .expect("deposit");
+        root.dispute(
+            row(1, TransactionType::Dispute, Some(dec!(4))),
+            &DisputeRules::default(),
+        )
+        .expect("dispute");
+        let mut account = Account::clone(&root);
+
+        for event in [
+            TransactionEvent::ChargebackWasRecorded {
+                tx_id: 1,
+                amount: dec!(5),
+            },
+            TransactionEvent::ResolveWasRecorded {
+                tx_id: 7,
+                amount: dec!(1),
+            },
+            TransactionEvent::VoidWasRecorded {
+                tx_id: 2,
+                amount: dec!(5),
+            },
+            TransactionEvent::TransactionsWereArchived { tx_ids: vec![1, 3] },
+        ] {
+            let before = account.clone();
+            assert!(account.apply_mut(event).is_err());
+            assert_eq!(before, account);
+        }
+
+        let event = TransactionEvent::ResolveWasRecorded {
+            tx_id: 1,
+            amount: dec!(4),
+        };
+        let applied = Account::apply(Some(account.clone()), event.clone()).expect("applied");
+        account.apply_mut(event).expect("applied in place");
+        assert_eq!(applied, account);
+    }
+
+    #[test]
+    fn it_rejects_invalid_resolve_events() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let rules = DisputeRules::default();
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10))))
+            .expect("opened");
+        root.deposit(row(2, TransactionType::Deposit, Some(dec!(3))))
+            .expect("deposit");
+        root.authorize(row(3, TransactionType::Authorize, Some(dec!(2))))
+            .expect("authorization");
+        root.dispute(row(1, TransactionType::Dispute, Some(dec!(4))), &rules)
+            .expect("dispute");
+        root.dispute(row(2, TransactionType::Dispute, None), &rules)
+            .expect("dispute");
+        let mut account = Account::clone(&root);
+
+        for (event, err) in [
+            (
+                TransactionEvent::ResolveWasRecorded {
+                    tx_id: 3,
+                    amount: dec!(1),
+                },
+                BankAccountError::WrongTransactionRecipient(3),
+            ),
+            (
+                // within the held funds, but over the amount disputed on the transaction
+                TransactionEvent::ResolveWasRecorded {
+                    tx_id: 1,
+                    amount: dec!(5),
+                },
+                BankAccountError::ResolveExceedsDispute(1),
+            ),
+        ] {
+            assert_eq!(Err(err), account.apply_mut(event));
+        }
+    }
+
+    #[test]
+    fn holds_only_expire_after_client_transactions() {
+        let row = |tx_id, transaction_type, amount| Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id,
+            transaction_type,
+            amount,
+        };
+        let mut root = BankAccountRoot::open(row(1, TransactionType::Deposit, Some(dec!(10))))
+            .expect("opened");
+        root.authorize(row(2, TransactionType::Authorize, Some(dec!(2))))
+            .expect("authorization");
+
+        // limit changes are bookkeeping and don't age the hold
+        for limit in [dec!(1), dec!(2), dec!(3)] {
+            root.set_credit_limit(limit).expect("credit limit");
+        }
+        root.expire_holds(Some(1)).expect("expired");
+        assert_eq!(dec!(2), root.snapshot().reserved());
+
+        root.deposit(row(3, TransactionType::Deposit, Some(dec!(1))))
+            .expect("deposit");
+        root.expire_holds(Some(1)).expect("expired");
+        assert_eq!(dec!(0), root.snapshot().reserved());
+    }
 }
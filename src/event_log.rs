@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use futures::TryStreamExt;
+
+use crate::archive::{Archive, InMemoryArchive};
+use crate::core::repository::Getter;
+use crate::core::Aggregate;
+use crate::core::{
+    AppendError, Chained, Importer, InMemory, Persisted, Streamer, Version, VersionSelect,
+};
+use crate::core::{EventSourced, GetError};
+use crate::domain::{Account, AccountSnapShot, BankAccountRoot, TransactionEvent};
+
+/// Account Event Streams persisted as JSON Lines, one [Persisted] Domain Event per line.
+pub type AccountEvent = Persisted<u16, TransactionEvent>;
+
+/// The account Event Streams, linked into the hash chains as they are appended.
+pub type EventStore = Chained<InMemory<u16, TransactionEvent>, u16>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EventLogError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("invalid event on line {line}: {source}")]
+    Parse {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("failed to encode event: {0}")]
+    Encode(#[from] serde_json::Error),
+    #[error("event on line {line} is out of order for account {client}, expected version {expected}, found: {found}")]
+    OutOfOrder {
+        line: usize,
+        client: u16,
+        expected: Version,
+        found: Version,
+    },
+    #[error(transparent)]
+    Append(#[from] AppendError),
+    #[error("event on line {line} has no link into the hash chains, new events can't be chained after it")]
+    Unchained { line: usize },
+}
+
+/// Writes the Event Streams of the given accounts to `writer`, in account order.
+pub async fn export<W: Write>(
+    event_store: &EventStore,
+    account_ids: impl IntoIterator<Item = u16>,
+    mut writer: W,
+) -> Result<(), EventLogError> {
+    for id in account_ids {
+        let events: Vec<AccountEvent> = event_store
+            .stream(&id, VersionSelect::All)
+            .try_collect()
+            .await
+            .unwrap_or_else(|never| match never {});
+        for event in events {
+            serde_json::to_writer(&mut writer, &event)?;
+            writer.write_all(b"\n")?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Reads the events of a JSON Lines event log as they are, along with their line numbers.
+pub fn read_events(
+    reader: impl BufRead,
+) -> impl Iterator<Item = Result<(usize, AccountEvent), EventLogError>> {
+    reader.lines().enumerate().filter_map(|(index, line)| {
+        let line_number = index + 1;
+        match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(
+                serde_json::from_str(&line)
+                    .map(|event| (line_number, event))
+                    .map_err(|source| EventLogError::Parse {
+                        line: line_number,
+                        source,
+                    }),
+            ),
+            Err(err) => Some(Err(err.into())),
+        }
+    })
+}
+
+/// An [InMemory] Event Store loaded from a persisted event log.
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    pub event_store: EventStore,
+    pub account_ids: BTreeSet<u16>,
+    /// The line of the first event without a link, e.g. one recorded before chaining.
+    pub unchained: Option<usize>,
+}
+
+impl EventLog {
+    /// Loads an event log from a JSON Lines file.
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self, EventLogError> {
+        let file = std::fs::File::open(path)?;
+        Self::from_reader(io::BufReader::new(file)).await
+    }
+
+    /// Loads an event log, checking every Event Stream is contiguous from its first version.
+    pub async fn from_reader(reader: impl BufRead) -> Result<Self, EventLogError> {
+        let event_log = Self::default();
+        let mut account_ids = BTreeSet::new();
+        let mut unchained = None;
+
+        for event in read_events(reader) {
+            let (line_number, event) = event?;
+            let client = event.stream_id;
+            let current = event_log
+                .event_store
+                .stream_version(&client)
+                .await
+                .unwrap_or_else(|never| match never {})
+                .unwrap_or_default();
+            if event.version != current + 1 {
+                return Err(EventLogError::OutOfOrder {
+                    line: line_number,
+                    client,
+                    expected: current + 1,
+                    found: event.version,
+                });
+            }
+            if event.link.is_none() {
+                unchained.get_or_insert(line_number);
+            }
+            // keeps the links of the event, so that the log is still verifiable once written back
+            event_log.event_store.import(vec![event]).await?;
+            account_ids.insert(client);
+        }
+
+        Ok(Self {
+            account_ids,
+            unchained,
+            ..event_log
+        })
+    }
+
+    /// Checks that every event is linked into the hash chains, before new events extend them.
+    ///
+    /// The events without a link are left out of the chains, so the new events of a log
+    /// recorded before chaining would start the global chain over from the first sequence.
+    pub fn check_chained(&self) -> Result<(), EventLogError> {
+        match self.unchained {
+            Some(line) => Err(EventLogError::Unchained { line }),
+            None => Ok(()),
+        }
+    }
+
+    /// Rebuilds the archive index from the transactions archived by the Event Streams, so that
+    /// late disputes can still restore them.
+    ///
+    /// A stream stops being replayed at its first event that fails to apply, which the run
+    /// reports once it loads the account.
+    pub async fn archive(&self) -> InMemoryArchive {
+        let archive = InMemoryArchive::default();
+        for id in &self.account_ids {
+            let events: Vec<AccountEvent> = self
+                .event_store
+                .stream(id, VersionSelect::All)
+                .try_collect()
+                .await
+                .unwrap_or_else(|never| match never {});
+            let mut account = None;
+            for persisted in events {
+                let event = persisted.event.message;
+                if let (Some(account), TransactionEvent::TransactionsWereArchived { tx_ids }) =
+                    (&account, &event)
+                {
+                    archive.store(*id, Account::archived(account, tx_ids));
+                }
+                match Account::apply(account, event) {
+                    Ok(applied) => account = Some(applied),
+                    Err(_) => break,
+                }
+            }
+        }
+
+        archive
+    }
+
+    /// Replays the Event Stream of every account to its snapshot.
+    pub async fn snapshots(&self) -> Result<BTreeMap<u16, AccountSnapShot>, GetError> {
+        let account_repository = EventSourced::<Account, _>::from(self.event_store.clone());
+
+        let mut snapshots = BTreeMap::new();
+        for id in &self.account_ids {
+            let root = BankAccountRoot::from(account_repository.get(id).await?);
+            snapshots.insert(*id, root.snapshot());
+        }
+
+        Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_rejects_gaps_in_an_event_stream() {
+        let log = [
+            r#"{"stream_id":1,"version":1,"event":{"message":{"CreditLimitWasSet":{"limit":"1"}}}}"#,
+            r#"{"stream_id":2,"version":1,"event":{"message":{"CreditLimitWasSet":{"limit":"1"}}}}"#,
+            r#"{"stream_id":1,"version":3,"event":{"message":{"CreditLimitWasSet":{"limit":"2"}}}}"#,
+        ]
+        .join("\n");
+
+        let err = EventLog::from_reader(log.as_bytes())
+            .await
+            .expect_err("gap in the stream of account 1");
+        assert!(matches!(
+            err,
+            EventLogError::OutOfOrder {
+                line: 3,
+                client: 1,
+                expected: 2,
+                found: 3,
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn it_reports_events_without_links() {
+        let log = [
+            r#"{"stream_id":1,"version":1,"event":{"message":{"CreditLimitWasSet":{"limit":"1"}}}}"#,
+            r#"{"stream_id":1,"version":2,"event":{"message":{"CreditLimitWasSet":{"limit":"2"}}}}"#,
+        ]
+        .join("\n");
+
+        let event_log = EventLog::from_reader(log.as_bytes())
+            .await
+            .expect("events recorded before chaining load");
+        assert!(matches!(
+            event_log.check_chained(),
+            Err(EventLogError::Unchained { line: 1 })
+        ));
+    }
+}
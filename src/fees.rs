@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::Deserialize;
+
+use crate::domain::TransactionType;
+
+/// The fee charged for a single [`TransactionType`].
+///
+/// The fee is `flat + amount * percentage / 100`, bounded by the optional `minimum` and `maximum`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeRule {
+    pub flat: Decimal,
+    pub percentage: Decimal,
+    pub minimum: Option<Decimal>,
+    pub maximum: Option<Decimal>,
+}
+
+impl FeeRule {
+    pub fn fee(&self, amount: Decimal) -> Decimal {
+        let mut fee = self.flat + amount * self.percentage / dec!(100);
+        if let Some(minimum) = self.minimum {
+            fee = fee.max(minimum);
+        }
+        if let Some(maximum) = self.maximum {
+            fee = fee.min(maximum);
+        }
+        fee.round_dp(4)
+    }
+}
+
+/// Fees charged to client accounts and credited to the house revenue account.
+///
+/// ```toml
+/// house_account = 65535
+/// chargeback_penalty = 15.0
+///
+/// [rates.withdrawal]
+/// flat = 0.25
+/// percentage = 1.0
+/// minimum = 0.5
+/// maximum = 10.0
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeSchedule {
+    /// The client id of the account fee revenue is credited to, transactions of that client are
+    /// rejected.
+    pub house_account: u16,
+    /// A flat amount charged on top of the chargeback rate.
+    pub chargeback_penalty: Decimal,
+    /// The [`FeeRule`] for each [`TransactionType`], transactions without a rule are free.
+    pub rates: HashMap<TransactionType, FeeRule>,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self {
+            house_account: u16::MAX,
+            chargeback_penalty: Decimal::ZERO,
+            rates: HashMap::default(),
+        }
+    }
+}
+
+impl FeeSchedule {
+    /// Loads a [`FeeSchedule`] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ScheduleError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Returns the fee for a transaction of the given type and amount.
+    pub fn fee(&self, transaction_type: &TransactionType, amount: Decimal) -> Decimal {
+        let fee = self
+            .rates
+            .get(transaction_type)
+            .map(|rule| rule.fee(amount))
+            .unwrap_or_default();
+
+        match transaction_type {
+            TransactionType::Chargeback => fee + self.chargeback_penalty,
+            _ => fee,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    #[error("invalid fee schedule: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_bounds_percentage_fees() {
+        let schedule: FeeSchedule = toml::from_str(
+            r#"
+            chargeback_penalty = 15
+
+            [rates.withdrawal]
+            flat = 0.25
+            percentage = 1
+            minimum = 0.5
+            maximum = 10
+
+            [rates.chargeback]
+            percentage = 2
+            "#,
+        )
+        .expect("valid fee schedule");
+
+        assert_eq!(u16::MAX, schedule.house_account);
+        assert_eq!(
+            dec!(0.5),
+            schedule.fee(&TransactionType::Withdrawal, dec!(10))
+        );
+        assert_eq!(
+            dec!(1.25),
+            schedule.fee(&TransactionType::Withdrawal, dec!(100))
+        );
+        assert_eq!(
+            dec!(10),
+            schedule.fee(&TransactionType::Withdrawal, dec!(5000))
+        );
+        assert_eq!(
+            dec!(17),
+            schedule.fee(&TransactionType::Chargeback, dec!(100))
+        );
+        assert_eq!(
+            Decimal::ZERO,
+            schedule.fee(&TransactionType::Deposit, dec!(100))
+        );
+    }
+}
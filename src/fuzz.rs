@@ -0,0 +1,263 @@
+//! Entry points of the `fuzz/` targets, driving untrusted input through the engine internals.
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use arbitrary::{Arbitrary, Unstructured};
+use rust_decimal::Decimal;
+
+use crate::core::repository::Getter;
+use crate::core::{Envelope, EventSourced, InMemory, Root};
+use crate::domain::{
+    Account, ArchivedTransaction, BankAccountRoot, DeclineReason, Lifecycle, Status, Transaction,
+    TransactionEvent, TransactionType, WithdrawalLimits,
+};
+use crate::mapping::ColumnMapping;
+use crate::runtime::{Runtime, Service};
+use crate::summary::RowCounts;
+use crate::InputProcessor;
+
+/// Reads `data` as a transactions CSV and processes it against an in-memory event store,
+/// then takes the snapshot of every account.
+///
+/// Malformed records and rejected transactions are skipped like by the CLI, so only panics
+/// and corrupt account streams are reported.
+pub async fn ingest(data: &[u8]) -> anyhow::Result<()> {
+    let (tx, rx) = flume::unbounded();
+    let rows = Arc::new(RowCounts::default());
+    if InputProcessor::read_csv(data, ColumnMapping::default(), tx, &rows).is_err() {
+        return Ok(());
+    }
+
+    let repository = EventSourced::<Account, _>::from(InMemory::default());
+    let engine = Runtime::new(Service::from(repository.clone()))
+        .with_connector("fuzz", InputProcessor { rx, rows })?
+        .run()
+        .await?;
+
+    for id in engine.account_ids() {
+        if let Ok(root) = repository.get(id).await {
+            BankAccountRoot::from(root).snapshot();
+        }
+    }
+
+    Ok(())
+}
+
+/// Rehydrates an account from `events`, as if they were its Event Stream, then takes its
+/// snapshot.
+///
+/// Events the account can't apply fail the rehydration like a corrupt stream, so only panics
+/// are reported.
+pub async fn rehydrate(events: Vec<TransactionEvent>) {
+    let stream = futures::stream::iter(
+        events
+            .into_iter()
+            .map(|event| Ok::<_, Infallible>(Envelope::from(event))),
+    );
+    if let Ok(Some(root)) = Root::<Account>::rehydrate_async(stream).await {
+        BankAccountRoot::from(root).snapshot();
+    }
+}
+
+/// Few ids, so that events find the transactions and accounts of earlier events often enough.
+fn tx_id(u: &mut Unstructured) -> arbitrary::Result<u32> {
+    u.int_in_range(1..=8)
+}
+
+fn client_id(u: &mut Unstructured) -> arbitrary::Result<u16> {
+    u.int_in_range(0..=3)
+}
+
+/// Amounts with up to four decimal places, including a few negative ones.
+fn amount(u: &mut Unstructured) -> arbitrary::Result<Decimal> {
+    Ok(Decimal::new(
+        u.int_in_range(-1_000..=1_000_000)?,
+        u.int_in_range(0..=4)?,
+    ))
+}
+
+fn optional<T>(
+    u: &mut Unstructured,
+    value: impl FnOnce(&mut Unstructured) -> arbitrary::Result<T>,
+) -> arbitrary::Result<Option<T>> {
+    Ok(if u.arbitrary()? {
+        Some(value(u)?)
+    } else {
+        None
+    })
+}
+
+impl<'a> Arbitrary<'a> for TransactionType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&[
+            TransactionType::Deposit,
+            TransactionType::Withdrawal,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+            TransactionType::Authorize,
+            TransactionType::Capture,
+            TransactionType::Void,
+            TransactionType::Fee,
+        ])
+        .cloned()
+    }
+}
+
+impl<'a> Arbitrary<'a> for Status {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&[
+            Status::Ok,
+            Status::Disputed,
+            Status::Resolved,
+            Status::ChargedBack,
+            Status::Declined,
+        ])
+        .cloned()
+    }
+}
+
+impl<'a> Arbitrary<'a> for DeclineReason {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        u.choose(&[
+            DeclineReason::Expired,
+            DeclineReason::LimitReached,
+            DeclineReason::Finalized,
+        ])
+        .copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for Transaction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Transaction {
+            status: u.arbitrary()?,
+            client_id: client_id(u)?,
+            tx_id: tx_id(u)?,
+            transaction_type: u.arbitrary()?,
+            amount: optional(u, amount)?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for WithdrawalLimits {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(WithdrawalLimits {
+            max_amount: optional(u, amount)?,
+            window: optional(u, |u| u.int_in_range(0..=16))?,
+            max_window_total: optional(u, amount)?,
+            max_window_count: optional(u, |u| u.int_in_range(0..=8))?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for ArchivedTransaction {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(ArchivedTransaction {
+            transaction: u.arbitrary()?,
+            lifecycle: Lifecycle {
+                recorded_at: u.int_in_range(0..=64)?,
+                disputes: u.int_in_range(0..=4)?,
+                disputed: amount(u)?,
+            },
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for TransactionEvent {
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(match u.int_in_range(0..=16)? {
+            0 => TransactionEvent::WasOpened {
+                tx_id: tx_id(u)?,
+                account_holder_id: client_id(u)?,
+                transaction: u.arbitrary()?,
+            },
+            1 => TransactionEvent::DepositWasRecorded {
+                amount: amount(u)?,
+                transaction: u.arbitrary()?,
+            },
+            2 => TransactionEvent::WithdrawalWasRecorded {
+                amount: amount(u)?,
+                transaction: u.arbitrary()?,
+            },
+            3 => TransactionEvent::DisputeWasRecorded {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            4 => TransactionEvent::ResolveWasRecorded {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            5 => TransactionEvent::ChargebackWasRecorded {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            6 => TransactionEvent::AuthorizationWasRecorded {
+                amount: amount(u)?,
+                transaction: u.arbitrary()?,
+            },
+            7 => TransactionEvent::CaptureWasRecorded {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            8 => TransactionEvent::VoidWasRecorded {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            9 => TransactionEvent::AuthorizationWasExpired {
+                tx_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            10 => TransactionEvent::FeeWasCharged {
+                tx_id: tx_id(u)?,
+                fee_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            11 => TransactionEvent::FeeWasCollected {
+                account_holder_id: client_id(u)?,
+                client_id: client_id(u)?,
+                fee_id: tx_id(u)?,
+                amount: amount(u)?,
+            },
+            12 => TransactionEvent::CreditLimitWasSet { limit: amount(u)? },
+            13 => TransactionEvent::WithdrawalLimitsWereSet {
+                limits: u.arbitrary()?,
+            },
+            14 => TransactionEvent::DisputeWasDeclined {
+                tx_id: tx_id(u)?,
+                reason: u.arbitrary()?,
+            },
+            15 => TransactionEvent::TransactionsWereArchived {
+                tx_ids: (0..u.int_in_range(0..=4)?)
+                    .map(|_| tx_id(u))
+                    .collect::<arbitrary::Result<_>>()?,
+            },
+            _ => TransactionEvent::TransactionWasRestored {
+                archived: u.arbitrary()?,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn it_rehydrates_arbitrary_events() {
+        // deterministic bytes, enough for a few hundred event sequences
+        let data: Vec<u8> = (0..65_536u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut u = Unstructured::new(&data);
+        let mut sequences = 0;
+        while let Ok(events) = Vec::<TransactionEvent>::arbitrary(&mut u) {
+            if u.is_empty() {
+                break;
+            }
+            rehydrate(events).await;
+            sequences += 1;
+        }
+        assert!(sequences > 0);
+    }
+}
@@ -0,0 +1,570 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rand::distr::weighted::WeightedIndex;
+use rand::distr::Distribution;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::domain::{AccountSnapShot, TransactionType};
+
+/// Type literal of the deliberately invalid rows, unknown to the engine.
+const UNKNOWN_TYPE: &str = "refund";
+
+/// Transaction id no generated transaction ever gets, for disputes of unknown transactions.
+const UNKNOWN_TX: u32 = u32::MAX;
+
+/// Largest generated deposit, in ten-thousandths.
+const MAX_DEPOSIT: i64 = 10_000_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum WorkloadError {
+    #[error("invalid workload: {0}")]
+    Invalid(String),
+}
+
+/// Shape of a synthetic workload, generated the same way for the same seed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub seed: u64,
+    /// Client ids are drawn from `1..=clients`.
+    pub clients: u16,
+    pub transactions: usize,
+    pub mix: Mix,
+    /// The share of rows disputing an earlier deposit or withdrawal, or settling such a dispute.
+    pub dispute_rate: f64,
+    /// The share of settled disputes ending in a chargeback rather than a resolve.
+    pub chargeback_rate: f64,
+    /// The share of rows the engine rejects, e.g. overdrafts, duplicates or unknown types.
+    pub invalid_rate: f64,
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            clients: 100,
+            transactions: 10_000,
+            mix: Mix::default(),
+            dispute_rate: 0.05,
+            chargeback_rate: 0.2,
+            invalid_rate: 0.01,
+        }
+    }
+}
+
+/// Relative weights of the transaction types outside of the dispute flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub authorize: u32,
+    pub capture: u32,
+    pub void: u32,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Self {
+            deposit: 50,
+            withdrawal: 30,
+            authorize: 10,
+            capture: 6,
+            void: 4,
+        }
+    }
+}
+
+impl Mix {
+    /// Overrides the weight of a transaction type.
+    pub fn with(
+        mut self,
+        transaction_type: TransactionType,
+        weight: u32,
+    ) -> Result<Self, WorkloadError> {
+        match transaction_type {
+            TransactionType::Deposit => self.deposit = weight,
+            TransactionType::Withdrawal => self.withdrawal = weight,
+            TransactionType::Authorize => self.authorize = weight,
+            TransactionType::Capture => self.capture = weight,
+            TransactionType::Void => self.void = weight,
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                return Err(WorkloadError::Invalid(format!(
+                    "{transaction_type:?} rows follow the dispute and chargeback rates"
+                )))
+            }
+            TransactionType::Fee => {
+                return Err(WorkloadError::Invalid(
+                    "Fee rows are only charged by the engine".to_owned(),
+                ))
+            }
+        }
+        Ok(self)
+    }
+
+    fn weights(&self) -> [(TransactionType, u32); 5] {
+        [
+            (TransactionType::Deposit, self.deposit),
+            (TransactionType::Withdrawal, self.withdrawal),
+            (TransactionType::Authorize, self.authorize),
+            (TransactionType::Capture, self.capture),
+            (TransactionType::Void, self.void),
+        ]
+    }
+}
+
+/// A generated transaction row, with the same fields as the transactions CSV.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Row {
+    #[serde(rename = "type")]
+    pub transaction_type: &'static str,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Decimal>,
+}
+
+impl Row {
+    fn new(
+        transaction_type: TransactionType,
+        client: u16,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> Self {
+        Self {
+            transaction_type: transaction_type.as_str(),
+            client,
+            tx,
+            amount,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug, Clone)]
+struct Recorded {
+    transaction_type: TransactionType,
+    amount: Decimal,
+    status: Status,
+}
+
+/// Reference model of a client account, independent from the engine, for the default engine
+/// settings: no fees, limits, hold expiry, dispute rules or archiving.
+#[derive(Debug, Clone)]
+struct Model {
+    available: Decimal,
+    held: Decimal,
+    reserved: Decimal,
+    locked: bool,
+    /// The transaction opening the account.
+    opened_by: u32,
+    transactions: HashMap<u32, Recorded>,
+    holds: HashMap<u32, Decimal>,
+}
+
+impl Model {
+    fn open(tx: u32, amount: Decimal) -> Self {
+        Self {
+            available: amount,
+            held: Decimal::ZERO,
+            reserved: Decimal::ZERO,
+            locked: false,
+            opened_by: tx,
+            transactions: HashMap::from([(tx, Self::recorded(TransactionType::Deposit, amount))]),
+            holds: HashMap::new(),
+        }
+    }
+
+    fn recorded(transaction_type: TransactionType, amount: Decimal) -> Recorded {
+        Recorded {
+            transaction_type,
+            amount,
+            status: Status::Ok,
+        }
+    }
+
+    /// Applies a row like the engine would, returning whether it is accepted.
+    fn apply(
+        &mut self,
+        transaction_type: TransactionType,
+        tx: u32,
+        amount: Option<Decimal>,
+    ) -> bool {
+        if self.locked {
+            return false;
+        }
+        match transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize => {
+                let Some(amount) = amount.filter(|amount| *amount >= Decimal::ZERO) else {
+                    return false;
+                };
+                if transaction_type != TransactionType::Deposit && self.available < amount {
+                    return false;
+                }
+                if self.transactions.contains_key(&tx) {
+                    return false;
+                }
+                match transaction_type {
+                    TransactionType::Deposit => self.available += amount,
+                    TransactionType::Withdrawal => self.available -= amount,
+                    _ => {
+                        self.available -= amount;
+                        self.reserved += amount;
+                        self.holds.insert(tx, amount);
+                    }
+                }
+                self.transactions
+                    .insert(tx, Self::recorded(transaction_type, amount));
+            }
+            TransactionType::Capture | TransactionType::Void => {
+                let Some(remaining) = self.holds.remove(&tx) else {
+                    return false;
+                };
+                self.reserved -= remaining;
+                if transaction_type == TransactionType::Void {
+                    self.available += remaining;
+                }
+            }
+            TransactionType::Dispute => match self.transactions.get_mut(&tx) {
+                Some(recorded)
+                    if matches!(
+                        recorded.transaction_type,
+                        TransactionType::Deposit | TransactionType::Withdrawal
+                    ) && recorded.status == Status::Ok =>
+                {
+                    recorded.status = Status::Disputed;
+                    self.available -= recorded.amount;
+                    self.held += recorded.amount;
+                }
+                _ => return false,
+            },
+            TransactionType::Fee => return false,
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                match self.transactions.get_mut(&tx) {
+                    Some(recorded) if recorded.status == Status::Disputed => {
+                        self.held -= recorded.amount;
+                        if transaction_type == TransactionType::Resolve {
+                            recorded.status = Status::Resolved;
+                            self.available += recorded.amount;
+                        } else {
+                            recorded.status = Status::ChargedBack;
+                            self.locked = true;
+                        }
+                    }
+                    _ => return false,
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Generates the rows of a [`Workload`], tracking the account balances they should result in.
+///
+/// The same workload, seed included, always generates the same rows.
+pub struct Generator {
+    workload: Workload,
+    rng: ChaCha8Rng,
+    mix: WeightedIndex<u32>,
+    generated: usize,
+    next_tx: u32,
+    accounts: BTreeMap<u16, Model>,
+    /// Accepted deposits and withdrawals that can be disputed.
+    disputable: Vec<(u16, u32)>,
+    disputes: Vec<(u16, u32)>,
+    holds: Vec<(u16, u32)>,
+}
+
+impl Generator {
+    pub fn new(workload: Workload) -> Result<Self, WorkloadError> {
+        if workload.clients == 0 {
+            return Err(WorkloadError::Invalid(
+                "at least one client is needed".to_owned(),
+            ));
+        }
+        for (name, rate) in [
+            ("dispute", workload.dispute_rate),
+            ("chargeback", workload.chargeback_rate),
+            ("invalid", workload.invalid_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(WorkloadError::Invalid(format!(
+                    "the {name} rate {rate} is not between 0 and 1"
+                )));
+            }
+        }
+        let mix = WeightedIndex::new(workload.mix.weights().map(|(_, weight)| weight))
+            .map_err(|err| WorkloadError::Invalid(format!("transaction mix: {err}")))?;
+
+        Ok(Self {
+            rng: ChaCha8Rng::seed_from_u64(workload.seed),
+            workload,
+            mix,
+            generated: 0,
+            next_tx: 1,
+            accounts: BTreeMap::new(),
+            disputable: Vec::new(),
+            disputes: Vec::new(),
+            holds: Vec::new(),
+        })
+    }
+
+    /// The account balances `process` should print once the rows generated so far are processed.
+    pub fn expected(&self) -> Vec<AccountSnapShot> {
+        self.accounts
+            .iter()
+            .map(|(client, account)| {
+                AccountSnapShot::new(
+                    *client,
+                    account.available,
+                    account.held,
+                    account.reserved,
+                    account.locked,
+                )
+            })
+            .collect()
+    }
+
+    fn tx(&mut self) -> u32 {
+        let tx = self.next_tx;
+        self.next_tx += 1;
+        tx
+    }
+
+    /// An amount between `0.0001` and `max`, falling back on a deposit sized amount.
+    fn amount(&mut self, max: Decimal) -> Decimal {
+        let max = (max * Decimal::from(10_000))
+            .trunc()
+            .try_into()
+            .unwrap_or(MAX_DEPOSIT);
+        Decimal::new(self.rng.random_range(1..=max.max(1)), 4)
+    }
+
+    fn deposit(&mut self, client: u16) -> Row {
+        let tx = self.tx();
+        let amount = self.amount(Decimal::new(MAX_DEPOSIT, 4));
+        Row::new(TransactionType::Deposit, client, tx, Some(amount))
+    }
+
+    fn next_row(&mut self) -> Row {
+        let client = self.rng.random_range(1..=self.workload.clients);
+        // a row of a client without an account is only rejected as unknown, so every account
+        // is opened by a valid deposit rather than spending the row on a rejection
+        let Some(available) = self.accounts.get(&client).map(|account| account.available) else {
+            return self.deposit(client);
+        };
+        if self.rng.random_bool(self.workload.invalid_rate) {
+            return self.invalid(client, available);
+        }
+
+        if self.rng.random_bool(self.workload.dispute_rate) {
+            let settle = !self.disputes.is_empty()
+                && (self.disputable.is_empty() || self.rng.random_bool(0.5));
+            if settle {
+                let (client, tx) = take(&mut self.rng, &mut self.disputes);
+                let transaction_type = match self.rng.random_bool(self.workload.chargeback_rate) {
+                    true => TransactionType::Chargeback,
+                    false => TransactionType::Resolve,
+                };
+                return Row::new(transaction_type, client, tx, None);
+            }
+            if !self.disputable.is_empty() {
+                let (client, tx) = take(&mut self.rng, &mut self.disputable);
+                return Row::new(TransactionType::Dispute, client, tx, None);
+            }
+        }
+
+        let transaction_type = self.workload.mix.weights()[self.mix.sample(&mut self.rng)]
+            .0
+            .clone();
+        match transaction_type {
+            TransactionType::Withdrawal | TransactionType::Authorize
+                if available > Decimal::ZERO =>
+            {
+                let tx = self.tx();
+                let amount = self.amount(available);
+                Row::new(transaction_type, client, tx, Some(amount))
+            }
+            TransactionType::Capture | TransactionType::Void if !self.holds.is_empty() => {
+                let (client, tx) = take(&mut self.rng, &mut self.holds);
+                Row::new(transaction_type, client, tx, None)
+            }
+            _ => self.deposit(client),
+        }
+    }
+
+    /// A row the engine rejects, for a client with an account.
+    fn invalid(&mut self, client: u16, available: Decimal) -> Row {
+        let excess = self.amount(Decimal::ONE);
+        match self.rng.random_range(0..6) {
+            0 => {
+                let tx = self.tx();
+                Row {
+                    transaction_type: UNKNOWN_TYPE,
+                    client,
+                    tx,
+                    amount: Some(excess),
+                }
+            }
+            1 => {
+                let tx = self.tx();
+                Row::new(TransactionType::Deposit, client, tx, None)
+            }
+            2 => {
+                let tx = self.tx();
+                Row::new(TransactionType::Withdrawal, client, tx, Some(-excess))
+            }
+            3 => Row::new(TransactionType::Dispute, client, UNKNOWN_TX, None),
+            4 => {
+                let tx = self.tx();
+                let amount = available.max(Decimal::ZERO) + excess;
+                Row::new(TransactionType::Withdrawal, client, tx, Some(amount))
+            }
+            _ => {
+                let tx = self.accounts[&client].opened_by;
+                let amount = self.amount(Decimal::ONE);
+                Row::new(TransactionType::Deposit, client, tx, Some(amount))
+            }
+        }
+    }
+
+    /// Applies a generated row to the reference model.
+    fn record(&mut self, row: &Row) {
+        let Ok(transaction_type) = row.transaction_type.parse::<TransactionType>() else {
+            return;
+        };
+        let Some(account) = self.accounts.get_mut(&row.client) else {
+            if let (TransactionType::Deposit, Some(amount)) = (&transaction_type, row.amount) {
+                self.accounts
+                    .insert(row.client, Model::open(row.tx, amount));
+                self.disputable.push((row.client, row.tx));
+            }
+            return;
+        };
+        if !account.apply(transaction_type.clone(), row.tx, row.amount) {
+            return;
+        }
+
+        let entry = (row.client, row.tx);
+        match transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal => self.disputable.push(entry),
+            TransactionType::Authorize => self.holds.push(entry),
+            TransactionType::Dispute => self.disputes.push(entry),
+            // a resolved transaction can't be disputed again under the default dispute rules
+            TransactionType::Resolve => {}
+            TransactionType::Chargeback => {
+                // every later row of a locked account is rejected
+                let client = row.client;
+                self.disputable.retain(|(id, _)| *id != client);
+                self.disputes.retain(|(id, _)| *id != client);
+                self.holds.retain(|(id, _)| *id != client);
+            }
+            TransactionType::Capture | TransactionType::Void | TransactionType::Fee => {}
+        }
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Row;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated == self.workload.transactions {
+            return None;
+        }
+        self.generated += 1;
+
+        let row = self.next_row();
+        self.record(&row);
+        Some(row)
+    }
+}
+
+/// Removes a random entry.
+fn take(rng: &mut ChaCha8Rng, entries: &mut Vec<(u16, u32)>) -> (u16, u32) {
+    let index = rng.random_range(0..entries.len());
+    entries.swap_remove(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workload(seed: u64) -> Workload {
+        Workload {
+            seed,
+            clients: 10,
+            transactions: 2_000,
+            dispute_rate: 0.2,
+            chargeback_rate: 0.3,
+            invalid_rate: 0.1,
+            ..Workload::default()
+        }
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_rows() {
+        let rows: Vec<_> = Generator::new(workload(7))
+            .expect("valid workload")
+            .collect();
+        assert_eq!(2_000, rows.len());
+        assert_eq!(
+            rows,
+            Generator::new(workload(7))
+                .expect("valid workload")
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(
+            rows,
+            Generator::new(workload(8))
+                .expect("valid workload")
+                .collect::<Vec<_>>()
+        );
+        for transaction_type in [
+            "dispute",
+            "resolve",
+            "chargeback",
+            "capture",
+            "void",
+            UNKNOWN_TYPE,
+        ] {
+            assert!(rows
+                .iter()
+                .any(|row| row.transaction_type == transaction_type));
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_workloads() {
+        let invalid = [
+            Workload {
+                clients: 0,
+                ..Workload::default()
+            },
+            Workload {
+                invalid_rate: 1.5,
+                ..Workload::default()
+            },
+            Workload {
+                mix: Mix {
+                    deposit: 0,
+                    withdrawal: 0,
+                    authorize: 0,
+                    capture: 0,
+                    void: 0,
+                },
+                ..Workload::default()
+            },
+        ];
+        for workload in invalid {
+            assert!(Generator::new(workload).is_err());
+        }
+        assert!(Mix::default().with(TransactionType::Dispute, 1).is_err());
+    }
+}
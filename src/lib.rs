@@ -1,60 +1,151 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 use std::future::Future;
-use std::io;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Instant;
 
-use clap::Parser;
 use csv::Trim;
 use either::Either;
-use futures::TryFutureExt;
-use tap::{Pipe, TapFallible as _};
+use futures::{TryFutureExt, TryStreamExt};
+use tap::TapFallible as _;
 
-use crate::cli::{Args, InputType, ProcessingError};
-use crate::core::repository::Getter;
-use crate::core::{EventSourced, InMemory};
-use crate::domain::{Account, BankAccountRoot, Transaction, TransactionEvent};
+use crate::archive::InMemoryArchive;
+use crate::cli::{
+    Args, Cli, Command, ConfigCommand, GenerateArgs, InputType, Instrumentation, ProcessingError,
+};
+use crate::config::{Config, CorruptStreamPolicy, InputFormat, OutputConfig, OutputFormat};
+use crate::core::repository::{Getter, Repository};
+use crate::core::{
+    Cached, Checkpoint, EventSourced, Fork, GetError, Message, Streamer, Verified, Version,
+    VersionSelect,
+};
+use crate::diff::Tolerance;
+use crate::domain::{
+    Account, AccountSnapShot, BalanceChange, BankAccountRoot, Transaction, TransactionEvent,
+};
+use crate::event_log::{AccountEvent, EventLog};
+use crate::fees::FeeSchedule;
+use crate::generate::Generator;
+use crate::limits::{CreditLimits, DisputeRules, WithdrawalPolicy};
+use crate::mapping::ColumnMapping;
 use crate::runtime::{ConnectorError, Read, Runtime, Service};
+use crate::summary::{RowCounts, Summary};
+use crate::telemetry::Metrics;
 
+pub mod archive;
 mod cli;
+pub mod config;
 pub mod core;
+pub mod diff;
 pub mod domain;
+pub mod event_log;
+pub mod fees;
+#[cfg(feature = "fuzz")]
+#[doc(hidden)]
+pub mod fuzz;
+pub mod generate;
+pub mod limits;
+pub mod mapping;
 pub mod runtime;
+pub mod summary;
+pub mod telemetry;
 
 struct InputProcessor {
     rx: flume::Receiver<Transaction>,
+    rows: Arc<RowCounts>,
 }
 
-impl From<InputType> for InputProcessor {
-    fn from(value: InputType) -> Self {
-        let (tx, rx) = flume::bounded(128 * 1024);
+impl InputProcessor {
+    fn new(
+        input: InputType,
+        format: InputFormat,
+        mapping: ColumnMapping,
+        channel_size: usize,
+    ) -> Self {
+        let (tx, rx) = flume::bounded(channel_size);
+        let rows = Arc::new(RowCounts::default());
 
+        let counts = rows.clone();
         std::thread::spawn(move || -> Result<(), ProcessingError> {
-            let rdr = match value {
+            let reader = match input {
                 InputType::File(path) => Either::Left(std::fs::File::open(path)?),
                 InputType::Stdin => Either::Right(io::stdin()),
+            };
+            match format {
+                InputFormat::Csv => Self::read_csv(reader, mapping, tx, &counts),
+                InputFormat::Jsonl => Self::read_jsonl(reader, tx, &counts),
             }
-            .pipe(|either_reader| {
-                csv::ReaderBuilder::new()
-                    .trim(Trim::All)
-                    .flexible(true)
-                    .from_reader(either_reader)
-            });
+        });
+        Self { rx, rows }
+    }
+
+    fn read_csv(
+        reader: impl io::Read,
+        mapping: ColumnMapping,
+        tx: flume::Sender<Transaction>,
+        rows: &RowCounts,
+    ) -> Result<(), ProcessingError> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .has_headers(mapping.has_headers)
+            .from_reader(reader);
 
-            for record in rdr
-                .into_deserialize::<Transaction>()
-                .map(|record| {
-                    record
-                        .tap_err(|err| tracing::error!(error=?err, "Error parsing CSV records"))
-                        .map_err(ProcessingError::from)
-                })
-                .map_while(Result::ok)
-            {
-                tx.send(record)?
+        let headers = match mapping.has_headers {
+            true => Some(rdr.headers()?.clone()),
+            false => None,
+        };
+        let layout = mapping
+            .layout(headers.as_ref())
+            .tap_err(|err| tracing::error!(error=%err, "Error mapping CSV columns"))?;
+
+        for record in rdr
+            .into_records()
+            .inspect(|_| rows.read())
+            .map(|record| {
+                record
+                    .tap_err(|err| tracing::error!(error=?err, "Error parsing CSV records"))
+                    .map_err(ProcessingError::from)
+            })
+            .map_while(Result::ok)
+        {
+            match layout.transaction(&record) {
+                Ok(transaction) => {
+                    rows.parsed();
+                    tx.send(transaction)?
+                }
+                Err(err) => tracing::error!(error=%err, "Error mapping CSV record"),
             }
+        }
 
-            Ok(())
-        });
-        Self { rx }
+        Ok(())
+    }
+
+    fn read_jsonl(
+        reader: impl io::Read,
+        tx: flume::Sender<Transaction>,
+        rows: &RowCounts,
+    ) -> Result<(), ProcessingError> {
+        for line in io::BufReader::new(reader).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            rows.read();
+            match serde_json::from_str::<Transaction>(&line) {
+                Ok(transaction) => {
+                    rows.parsed();
+                    tx.send(transaction)?
+                }
+                Err(err) => tracing::error!(error=%err, "Error parsing JSON transaction"),
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -71,6 +162,7 @@ impl Read for InputProcessor {
                 + '_,
         >,
     > {
+        metrics::gauge!(telemetry::QUEUE_DEPTH, "queue" => "ingest").set(self.rx.len() as f64);
         let fut = self
             .rx
             .recv_async()
@@ -82,26 +174,517 @@ impl Read for InputProcessor {
     }
 }
 
-pub async fn run() -> anyhow::Result<()> {
-    let args = Args::parse();
+pub async fn run() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse_checked();
+    let config = cli.config()?;
+    // flushes the spans not exported yet once the command is done
+    let _tracer = Instrumentation::from_config(&config.logging)?.setup()?;
+
+    match cli.command() {
+        Command::Process(args) => process(*args, config).await,
+        Command::Replay { event_log } => replay(event_log, &config).await,
+        Command::Inspect { event_log, client } => inspect(event_log, client).await,
+        Command::Verify {
+            event_log,
+            accounts,
+        } => verify(event_log, accounts).await,
+        Command::VerifyChain {
+            event_log,
+            checkpoint,
+            key_file,
+        } => verify_chain(event_log, checkpoint, key_file),
+        Command::Checkpoint {
+            event_log,
+            key_file,
+        } => checkpoint(event_log, key_file),
+        Command::Generate(args) => generate(args),
+        Command::Config(ConfigCommand::Show) => {
+            print!("{}", toml::to_string_pretty(&config)?);
+            Ok(())
+        }
+        Command::Diff {
+            left,
+            right,
+            tolerance,
+            decimal_places,
+        } => {
+            let tolerance = Tolerance {
+                absolute: tolerance,
+                decimal_places,
+            };
+            // like diff(1), exit with 1 when the accounts differ and 2 when they can't be compared
+            return Ok(match accounts_diff(left, right, tolerance).await {
+                Ok(true) => ExitCode::SUCCESS,
+                Ok(false) => ExitCode::from(1),
+                Err(err) => {
+                    eprintln!("Error: {err:?}");
+                    ExitCode::from(2)
+                }
+            });
+        }
+    }
+    .map(|()| ExitCode::SUCCESS)
+}
+
+/// Processes the transactions, exporting the metrics of the run if configured.
+async fn process(args: Args, config: Config) -> anyhow::Result<()> {
+    let metrics = Metrics::install(&config.metrics)?;
+    let processed = process_transactions(args, config).await;
+    // the metrics of a failed run are still written, they may tell why it failed
+    if let Some(metrics) = metrics {
+        metrics.dump()?;
+    }
+    processed
+}
+
+async fn process_transactions(args: Args, config: Config) -> anyhow::Result<()> {
+    let started = Instant::now();
+    let mapping = args
+        .mapping
+        .column_mapping(config.input.mapping.as_deref())?;
+    let input = InputProcessor::new(
+        args.input.unwrap_or_default(),
+        config.input.format,
+        mapping,
+        config.runtime.ingest_channel_size,
+    );
+    let event_log_path = config.event_store.event_log()?;
+    // a dry run forks the events already recorded and never writes them back, while a resumed
+    // run appends its own events to them
+    let event_log = match event_log_path
+        .filter(|path| (args.dry_run || config.event_store.resume) && path.exists())
+    {
+        Some(path) => EventLog::from_path(path).await?,
+        None => EventLog::default(),
+    };
+    if args.dry_run {
+        return dry_run(input, &config, event_log, started).await;
+    }
+    event_log.check_chained()?;
+    let rows = input.rows.clone();
+    let mut versions = BTreeMap::new();
+    for id in &event_log.account_ids {
+        if let Ok(Some(version)) = event_log.event_store.stream_version(id).await {
+            versions.insert(*id, version);
+        }
+    }
 
-    args.instrumentation.setup()?;
+    let archive = event_log.archive().await;
 
-    let event_store = InMemory::<u16, TransactionEvent>::default();
-    let account_repository = EventSourced::<Account, _>::from(event_store);
-    let application_service = Service::from(account_repository.clone());
+    let quarantine = config.errors.corrupt_stream == CorruptStreamPolicy::Quarantine;
+    let event_store = event_log.event_store;
+    let account_repository = EventSourced::<Account, _>::from(event_store.clone());
+    let cache = config
+        .engine
+        .cache_capacity
+        .map(|capacity| Cached::new(account_repository.clone(), capacity));
+    let (application_service, house_account) = match cache.clone() {
+        Some(cache) => service(&config, cache, archive)?,
+        None => service(&config, account_repository.clone(), archive)?,
+    };
 
     let engine = Runtime::new(application_service)
-        .with_connector("stdin_or_file", InputProcessor::from(args.input))?;
+        .with_channel_size(config.runtime.dispatch_channel_size)
+        .with_rejection_policy(config.errors.rejected_transaction)
+        .with_connector("stdin_or_file", input)?;
+
+    let engine = engine.run().await?;
+    let cache_metrics = cache.map(|cache| cache.metrics());
+    if let Some(metrics) = cache_metrics {
+        tracing::info!(
+            hits = metrics.hits,
+            misses = metrics.misses,
+            stale = metrics.stale,
+            "aggregate cache"
+        );
+    }
+
+    let account_ids: BTreeSet<u16> = engine
+        .account_ids()
+        .union(&event_log.account_ids)
+        .copied()
+        .collect();
+    let mut snapshots = Vec::with_capacity(account_ids.len());
+    for id in &account_ids {
+        let root: BankAccountRoot = match account_repository.get(id).await {
+            Ok(root) => root.into(),
+            Err(GetError::Rehydrate { .. }) if quarantine => continue,
+            Err(err) => return Err(err.into()),
+        };
+        snapshots.push(root.snapshot());
+    }
+    let house_account = house_account.filter(|id| !account_ids.contains(id));
+    if let Some(id) = house_account {
+        if let Ok(root) = account_repository.get(&id).await {
+            snapshots.push(BankAccountRoot::from(root).snapshot());
+        }
+    }
+    write_accounts(config.output.format, &snapshots)?;
+
+    if config.output.summary || config.output.summary_file.is_some() {
+        let mut summary = Summary::new(&rows, engine.outcomes());
+        summary.cache = cache_metrics;
+        let ids = account_ids.iter().copied().chain(house_account);
+        record_events(&mut summary, &event_store, ids, &versions).await?;
+        snapshots
+            .iter()
+            .for_each(|snapshot| summary.account(snapshot));
+        summary.finish(started.elapsed());
+        report(&config.output, &summary)?;
+    }
+
+    if let Some(path) = event_log_path {
+        event_log::export(
+            &event_store,
+            account_ids.into_iter().chain(house_account),
+            io::BufWriter::new(std::fs::File::create(path)?),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Prints the account snapshots to stdout in the output format.
+fn write_accounts(format: OutputFormat, snapshots: &[AccountSnapShot]) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(io::stdout());
+            for snapshot in snapshots {
+                wtr.serialize(snapshot)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(io::stdout(), snapshots)?;
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the events recorded during the run to the summary, those of every account after its
+/// version in `versions`.
+async fn record_events<S>(
+    summary: &mut Summary,
+    event_store: &S,
+    account_ids: impl IntoIterator<Item = u16>,
+    versions: &BTreeMap<u16, Version>,
+) -> anyhow::Result<()>
+where
+    S: Streamer<u16, TransactionEvent>,
+    S::Error: std::error::Error + 'static,
+{
+    for id in account_ids {
+        let from = versions.get(&id).map_or(0, |version| version + 1);
+        let events: Vec<AccountEvent> = event_store
+            .stream(&id, VersionSelect::From(from))
+            .try_collect()
+            .await?;
+        for persisted in &events {
+            summary.record(&persisted.event.message);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the summary to stderr and writes it to the summary file, as configured.
+fn report(output: &OutputConfig, summary: &Summary) -> anyhow::Result<()> {
+    if output.summary {
+        eprintln!("{summary}");
+    }
+    if let Some(path) = &output.summary_file {
+        summary.write(path)?;
+    }
+
+    Ok(())
+}
 
+/// Builds the application [Service] with the engine settings on top of `repository` and
+/// `archive`, along with the house account collecting fees, if any.
+fn service(
+    config: &Config,
+    repository: impl Repository<Account> + 'static,
+    archive: InMemoryArchive,
+) -> anyhow::Result<(Service, Option<u16>)> {
+    let engine = &config.engine;
+    let fee_schedule = engine
+        .fee_schedule
+        .as_ref()
+        .map(FeeSchedule::from_path)
+        .transpose()?;
+    let credit_limits = engine
+        .credit_limits
+        .as_ref()
+        .map(CreditLimits::from_path)
+        .transpose()?;
+    let withdrawal_policy = engine
+        .withdrawal_policy
+        .as_ref()
+        .map(WithdrawalPolicy::from_path)
+        .transpose()?;
+    let dispute_rules = engine
+        .dispute_rules
+        .as_ref()
+        .map(DisputeRules::from_path)
+        .transpose()?;
+    let house_account = fee_schedule.as_ref().map(|schedule| schedule.house_account);
+
+    let service = Service::from(repository)
+        .with_hold_expiry(engine.hold_expiry)
+        .with_fee_schedule(fee_schedule)
+        .with_credit_limits(credit_limits)
+        .with_withdrawal_policy(withdrawal_policy)
+        .with_dispute_rules(dispute_rules)
+        .with_quarantine(config.errors.corrupt_stream == CorruptStreamPolicy::Quarantine)
+        .with_archive_after(engine.archive_after)
+        .with_archive(archive);
+
+    Ok((service, house_account))
+}
+
+/// Runs the batch against a scratch [Fork] of the event log, then prints the balance changes
+/// of every account involved and the rejected transactions.
+async fn dry_run(
+    input: InputProcessor,
+    config: &Config,
+    event_log: EventLog,
+    started: Instant,
+) -> anyhow::Result<()> {
+    let quarantine = config.errors.corrupt_stream == CorruptStreamPolicy::Quarantine;
+    let rows = input.rows.clone();
+    let fork = Fork::new(event_log.event_store.clone());
+    let account_repository = EventSourced::<Account, _>::from(event_log.event_store.clone());
+    let forked_repository = EventSourced::<Account, _>::from(fork.clone());
+    let archive = event_log.archive().await;
+    let (application_service, house_account) = service(config, forked_repository.clone(), archive)?;
+
+    let engine = Runtime::new(application_service)
+        .with_channel_size(config.runtime.dispatch_channel_size)
+        .with_rejections()
+        .with_connector("stdin_or_file", input)?;
     let engine = engine.run().await?;
 
+    let mut summary = Summary::new(&rows, engine.outcomes());
+    let mut versions = BTreeMap::new();
     let mut wtr = csv::Writer::from_writer(io::stdout());
-    for id in engine.account_ids() {
-        let root: BankAccountRoot = account_repository.get(id).await?.into();
-        wtr.serialize(&root.snapshot())?;
+    let account_ids: Vec<u16> = engine
+        .account_ids()
+        .iter()
+        .copied()
+        .chain(house_account.filter(|id| !engine.account_ids().contains(id)))
+        .collect();
+    for &id in &account_ids {
+        let before = snapshot(&account_repository, id, quarantine).await?;
+        let after = snapshot(&forked_repository, id, quarantine).await?;
+        if let Ok(Some(version)) = event_log.event_store.stream_version(&id).await {
+            versions.insert(id, version);
+        }
+        if let Some(after) = &after {
+            summary.account(after);
+        }
+        if before.is_none() && after.is_none() {
+            continue;
+        }
+        wtr.serialize(BalanceChange::new(id, before.as_ref(), after.as_ref()))?;
     }
     wtr.flush()?;
 
+    if !engine.rejections().is_empty() {
+        println!();
+        let mut wtr = csv::Writer::from_writer(io::stdout());
+        for rejection in engine.rejections() {
+            wtr.serialize(rejection)?;
+        }
+        wtr.flush()?;
+    }
+
+    if config.output.summary || config.output.summary_file.is_some() {
+        record_events(&mut summary, &fork, account_ids, &versions).await?;
+        summary.finish(started.elapsed());
+        report(&config.output, &summary)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the snapshot of an account, or `None` if it has no events or is quarantined.
+async fn snapshot(
+    repository: &impl Getter<Account>,
+    id: u16,
+    quarantine: bool,
+) -> anyhow::Result<Option<AccountSnapShot>> {
+    match repository.get(&id).await {
+        Ok(root) => Ok(Some(BankAccountRoot::from(root).snapshot())),
+        Err(GetError::NotFound) => Ok(None),
+        Err(GetError::Rehydrate { .. }) if quarantine => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Writes a synthetic workload, then the account balances it should result in.
+fn generate(args: GenerateArgs) -> anyhow::Result<()> {
+    let mut generator = Generator::new(args.workload()?)?;
+    let mut writer: Box<dyn io::Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    match args.format {
+        InputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for row in generator.by_ref() {
+                wtr.serialize(row)?;
+            }
+            wtr.flush()?;
+        }
+        InputFormat::Jsonl => {
+            writer = Box::new(io::BufWriter::new(writer));
+            for row in generator.by_ref() {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    if let Some(path) = args.expected {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for snapshot in generator.expected() {
+            wtr.serialize(snapshot)?;
+        }
+        wtr.flush()?;
+    }
+
     Ok(())
 }
+
+async fn replay(path: PathBuf, config: &Config) -> anyhow::Result<()> {
+    let snapshots = EventLog::from_path(path).await?.snapshots().await?;
+    let snapshots: Vec<_> = snapshots.into_values().collect();
+
+    write_accounts(config.output.format, &snapshots)
+}
+
+async fn inspect(path: PathBuf, client: u16) -> anyhow::Result<()> {
+    let event_log = EventLog::from_path(path).await?;
+    let events: Vec<AccountEvent> = event_log
+        .event_store
+        .stream(&client, VersionSelect::All)
+        .try_collect()
+        .await?;
+    if events.is_empty() {
+        anyhow::bail!("no events recorded for account {client}");
+    }
+
+    let mut wtr = csv::Writer::from_writer(io::stdout());
+    wtr.write_record(["version", "event", "payload"])?;
+    for persisted in events {
+        wtr.write_record([
+            persisted.version.to_string(),
+            persisted.event.message.name().to_string(),
+            serde_json::to_string(&persisted.event.message)?,
+        ])?;
+    }
+    wtr.flush()?;
+
+    Ok(())
+}
+
+/// Prints the differences between two sets of accounts, returning whether they match.
+async fn accounts_diff(
+    left: PathBuf,
+    right: PathBuf,
+    tolerance: Tolerance,
+) -> anyhow::Result<bool> {
+    let differences = diff::diff(
+        &diff::load(left).await?,
+        &diff::load(right).await?,
+        &tolerance,
+    );
+
+    let mut wtr = csv::Writer::from_writer(io::stdout());
+    for difference in &differences {
+        wtr.serialize(difference)?;
+    }
+    wtr.flush()?;
+
+    Ok(differences.is_empty())
+}
+
+async fn verify(event_log: PathBuf, accounts: PathBuf) -> anyhow::Result<()> {
+    let derived = EventLog::from_path(event_log).await?.snapshots().await?;
+    let expected = diff::read_accounts(accounts)?;
+
+    let mut mismatches = 0;
+    for id in derived
+        .keys()
+        .chain(expected.keys())
+        .collect::<BTreeSet<_>>()
+    {
+        match (expected.get(id), derived.get(id)) {
+            (Some(expected), Some(derived)) if expected == derived => continue,
+            (Some(expected), Some(derived)) => {
+                println!("account {id}: expected {expected:?}, derived {derived:?}")
+            }
+            (Some(_), None) => println!("account {id}: no events recorded"),
+            (None, Some(_)) => println!("account {id}: missing from the accounts CSV"),
+            (None, None) => unreachable!("account ids come from either side"),
+        }
+        mismatches += 1;
+    }
+
+    if mismatches > 0 {
+        anyhow::bail!("{mismatches} account(s) don't match their event streams");
+    }
+    println!("{} account(s) verified", expected.len());
+
+    Ok(())
+}
+
+/// Checks the hash chains of an event log, against a signed checkpoint if given.
+fn verify_chain(
+    event_log: PathBuf,
+    checkpoint: Option<PathBuf>,
+    key_file: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let verified = verified_chain(&event_log)?;
+    if let (Some(path), Some(key_file)) = (checkpoint, key_file) {
+        let checkpoint: Checkpoint = serde_json::from_reader(std::fs::File::open(path)?)?;
+        verified.check(&checkpoint, &signing_key(&key_file)?)?;
+        println!("checkpoint at event {} verified", checkpoint.head.sequence);
+    }
+
+    let head = verified.head();
+    println!("{} event(s) verified, head {}", head.sequence, head.hash);
+
+    Ok(())
+}
+
+/// Prints a checkpoint of the head of the hash chains of an event log, once they are verified.
+fn checkpoint(event_log: PathBuf, key_file: PathBuf) -> anyhow::Result<()> {
+    let head = verified_chain(&event_log)?.head();
+    let checkpoint = Checkpoint::sign(head, &signing_key(&key_file)?);
+    serde_json::to_writer_pretty(io::stdout(), &checkpoint)?;
+    println!();
+
+    Ok(())
+}
+
+fn verified_chain(event_log: &Path) -> anyhow::Result<Verified> {
+    let reader = io::BufReader::new(std::fs::File::open(event_log)?);
+    let events = event_log::read_events(reader)
+        .map(|event| event.map(|(_, event)| event))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(core::verify(events)?)
+}
+
+fn signing_key(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let key = std::fs::read(path)?;
+    if key.is_empty() {
+        anyhow::bail!("the key file {} is empty", path.display());
+    }
+    Ok(key)
+}
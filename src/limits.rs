@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+
+use crate::core::Version;
+use crate::domain::WithdrawalLimits;
+
+/// Per-client credit limits, allowing the available funds to go negative up to the limit.
+///
+/// ```toml
+/// default = 0.0
+///
+/// [clients]
+/// 1 = 100.0
+/// 42 = 2500.0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CreditLimits {
+    /// The credit limit of clients without their own entry.
+    pub default: Decimal,
+    /// The credit limit of each client, by client id.
+    #[serde(deserialize_with = "client_keys")]
+    pub clients: HashMap<u16, Decimal>,
+}
+
+impl CreditLimits {
+    /// Loads the [`CreditLimits`] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, LimitsError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Returns the credit limit of the given client.
+    pub fn limit(&self, client_id: u16) -> Decimal {
+        self.clients
+            .get(&client_id)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// Per-client [`WithdrawalLimits`], falling back field by field on the default limits.
+///
+/// ```toml
+/// [default]
+/// max_amount = 1000.0
+/// window = 10
+/// max_window_total = 2500.0
+/// max_window_count = 5
+///
+/// [clients.1]
+/// max_amount = 50.0
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WithdrawalPolicy {
+    /// The limits of clients without their own entry.
+    pub default: WithdrawalLimits,
+    /// The limits of each client, by client id.
+    #[serde(deserialize_with = "client_keys")]
+    pub clients: HashMap<u16, WithdrawalLimits>,
+}
+
+impl WithdrawalPolicy {
+    /// Loads the [`WithdrawalPolicy`] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, LimitsError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Returns the withdrawal limits of the given client.
+    pub fn limits(&self, client_id: u16) -> WithdrawalLimits {
+        let Some(limits) = self.clients.get(&client_id) else {
+            return self.default.clone();
+        };
+
+        WithdrawalLimits {
+            max_amount: limits.max_amount.or(self.default.max_amount),
+            window: limits.window.or(self.default.window),
+            max_window_total: limits.max_window_total.or(self.default.max_window_total),
+            max_window_count: limits.max_window_count.or(self.default.max_window_count),
+        }
+    }
+}
+
+/// Rules bounding when and how often a transaction can be disputed.
+///
+/// The dispute window is measured in transactions of the account holder, since transactions
+/// carry no timestamp: deposits, withdrawals, disputes, resolves, chargebacks, authorizations,
+/// captures and voids all count, while fees, declined disputes and other bookkeeping events don't.
+///
+/// ```toml
+/// max_age = 100
+/// max_disputes = 2
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisputeRules {
+    /// How many later transactions of the account holder a transaction can still be disputed after.
+    pub max_age: Option<Version>,
+    /// How many times a single transaction can be disputed. A resolved transaction can't be
+    /// disputed again without a limit.
+    pub max_disputes: Option<u32>,
+}
+
+impl DisputeRules {
+    /// Loads the [`DisputeRules`] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, LimitsError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+}
+
+/// Deserializes a table keyed by client id, since TOML keys are always strings.
+pub(crate) fn client_keys<'de, D, V>(deserializer: D) -> Result<HashMap<u16, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    V: Deserialize<'de>,
+{
+    HashMap::<String, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(client, value)| {
+            client
+                .parse()
+                .map(|client| (client, value))
+                .map_err(|_| serde::de::Error::custom(format!("invalid client id `{client}`")))
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LimitsError {
+    #[error("invalid limits configuration: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn it_falls_back_to_default_limit() {
+        let limits: CreditLimits = toml::from_str(
+            r#"
+            default = 10
+
+            [clients]
+            1 = 100.5
+            "#,
+        )
+        .expect("valid credit limits");
+
+        assert_eq!(dec!(100.5), limits.limit(1));
+        assert_eq!(dec!(10), limits.limit(2));
+        assert!(toml::from_str::<CreditLimits>("[clients]\nabc = 1").is_err());
+    }
+
+    #[test]
+    fn it_merges_client_withdrawal_limits_over_default() {
+        let policy: WithdrawalPolicy = toml::from_str(
+            r#"
+            [default]
+            max_amount = 1000
+            window = 10
+            max_window_count = 5
+
+            [clients.1]
+            max_amount = 50
+            "#,
+        )
+        .expect("valid withdrawal policy");
+
+        let limits = policy.limits(1);
+        assert_eq!(Some(dec!(50)), limits.max_amount);
+        assert_eq!(Some(10), limits.window);
+        assert_eq!(Some(5), limits.max_window_count);
+        assert_eq!(None, limits.max_window_total);
+        assert_eq!(policy.default, policy.limits(2));
+    }
+}
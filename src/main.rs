@@ -1,6 +1,8 @@
+use std::process::ExitCode;
+
 use payments_engine_rs::run;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     run().await
 }
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::domain::{Transaction, TransactionType};
+
+/// A source column, referenced either by its header name or by its zero-based position.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for Column {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<usize>()
+            .map(Column::Index)
+            .unwrap_or_else(|_| Column::Name(s.to_owned())))
+    }
+}
+
+/// The [`Transaction`] fields that can be mapped onto a source [`Column`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+}
+
+impl FromStr for Field {
+    type Err = MappingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "type" => Ok(Field::Type),
+            "client" => Ok(Field::Client),
+            "tx" => Ok(Field::Tx),
+            "amount" => Ok(Field::Amount),
+            _ => Err(MappingError::UnknownField(s.to_owned())),
+        }
+    }
+}
+
+/// Source columns for each [`Transaction`] field.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Columns {
+    #[serde(rename = "type")]
+    pub transaction_type: Column,
+    pub client: Column,
+    pub tx: Column,
+    /// The amount column, which has to be present once mapped. Left unmapped, the `amount`
+    /// header is used if the input has one, as rows like disputes don't need an amount.
+    pub amount: Option<Column>,
+}
+
+impl Default for Columns {
+    fn default() -> Self {
+        Self {
+            transaction_type: Column::Name("type".to_owned()),
+            client: Column::Name("client".to_owned()),
+            tx: Column::Name("tx".to_owned()),
+            amount: None,
+        }
+    }
+}
+
+impl Columns {
+    pub fn set(&mut self, field: Field, column: Column) {
+        match field {
+            Field::Type => self.transaction_type = column,
+            Field::Client => self.client = column,
+            Field::Tx => self.tx = column,
+            Field::Amount => self.amount = Some(column),
+        }
+    }
+}
+
+/// Describes how rows of a partner CSV file map onto [`Transaction`]s.
+///
+/// The default mapping expects the `type,client,tx,amount` headers, in any order.
+///
+/// ```toml
+/// has_headers = false
+///
+/// [columns]
+/// type = 2
+/// client = 0
+/// tx = 1
+/// amount = 3
+///
+/// [types]
+/// DEP = "deposit"
+/// WD = "withdrawal"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnMapping {
+    /// Whether the first row of the input holds column names.
+    pub has_headers: bool,
+    /// The source column of each [`Transaction`] field.
+    pub columns: Columns,
+    /// Source literals for each [`TransactionType`], checked before the canonical names.
+    pub types: HashMap<String, TransactionType>,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        Self {
+            has_headers: true,
+            columns: Columns::default(),
+            types: HashMap::default(),
+        }
+    }
+}
+
+impl ColumnMapping {
+    /// Loads a [`ColumnMapping`] from a TOML file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, MappingError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Resolves every mapped [`Column`] to a position in the input records.
+    ///
+    /// Named columns can only be resolved when `headers` are available.
+    pub fn layout(&self, headers: Option<&StringRecord>) -> Result<Layout<'_>, MappingError> {
+        let position = |column: &Column| match (column, headers) {
+            (Column::Index(index), _) => Ok(*index),
+            (Column::Name(name), Some(headers)) => headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| MappingError::MissingColumn(name.clone())),
+            (Column::Name(name), None) => Err(MappingError::HeadersRequired(name.clone())),
+        };
+
+        Ok(Layout {
+            mapping: self,
+            transaction_type: position(&self.columns.transaction_type)?,
+            client: position(&self.columns.client)?,
+            tx: position(&self.columns.tx)?,
+            amount: match &self.columns.amount {
+                Some(column) => Some(position(column)?),
+                None => headers.and_then(|headers| headers.iter().position(|h| h == "amount")),
+            },
+        })
+    }
+
+    fn transaction_type(&self, literal: &str) -> Option<TransactionType> {
+        self.types
+            .get(literal)
+            .cloned()
+            .or_else(|| literal.parse().ok())
+    }
+}
+
+/// A [`ColumnMapping`] resolved against the headers of a specific input.
+#[derive(Debug)]
+pub struct Layout<'a> {
+    mapping: &'a ColumnMapping,
+    transaction_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+}
+
+impl Layout<'_> {
+    /// Builds a [`Transaction`] out of a single input record.
+    pub fn transaction(&self, record: &StringRecord) -> Result<Transaction, MappingError> {
+        let row = record.position().map(|pos| pos.line()).unwrap_or_default();
+        let value = |index: usize, field: &'static str| {
+            record
+                .get(index)
+                .filter(|value| !value.is_empty())
+                .ok_or(MappingError::MissingValue { row, field })
+        };
+        let invalid = |field: &'static str, value: &str| MappingError::InvalidValue {
+            row,
+            field,
+            value: value.to_owned(),
+        };
+
+        let literal = value(self.transaction_type, "type")?;
+        let transaction_type =
+            self.mapping
+                .transaction_type(literal)
+                .ok_or_else(|| MappingError::UnknownType {
+                    row,
+                    literal: literal.to_owned(),
+                })?;
+
+        let client = value(self.client, "client")?;
+        let tx = value(self.tx, "tx")?;
+        let amount = self
+            .amount
+            .and_then(|index| record.get(index))
+            .filter(|amount| !amount.is_empty())
+            .map(|amount| {
+                amount
+                    .parse::<Decimal>()
+                    .map(|amount| amount.normalize())
+                    .map_err(|_| invalid("amount", amount))
+            })
+            .transpose()?;
+
+        Ok(Transaction {
+            status: Default::default(),
+            client_id: client.parse().map_err(|_| invalid("client", client))?,
+            tx_id: tx.parse().map_err(|_| invalid("tx", tx))?,
+            transaction_type,
+            amount,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MappingError {
+    #[error("unknown transaction field `{0}`, expected one of: type, client, tx, amount")]
+    UnknownField(String),
+    #[error("column `{0}` not found in CSV headers")]
+    MissingColumn(String),
+    #[error("column `{0}` is referenced by name but the input has no headers")]
+    HeadersRequired(String),
+    #[error("row {row}: unknown transaction type `{literal}`")]
+    UnknownType { row: u64, literal: String },
+    #[error("row {row}: missing value for `{field}`")]
+    MissingValue { row: u64, field: &'static str },
+    #[error("row {row}: invalid value `{value}` for `{field}`")]
+    InvalidValue {
+        row: u64,
+        field: &'static str,
+        value: String,
+    },
+    #[error("invalid mapping configuration: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::ReaderBuilder;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    #[test]
+    fn it_maps_headerless_columns_and_type_literals() {
+        let mapping: ColumnMapping = toml::from_str(
+            r#"
+            has_headers = false
+
+            [columns]
+            type = 2
+            client = 0
+            tx = 1
+            amount = 3
+
+            [types]
+            DEP = "deposit"
+            "#,
+        )
+        .expect("valid mapping");
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(mapping.has_headers)
+            .from_reader("7,42,DEP,1.5\n7,43,XX,1.0\n".as_bytes());
+        let layout = mapping.layout(None).expect("positional layout");
+        let records: Vec<_> = rdr.records().map(Result::unwrap).collect();
+
+        let transaction = layout.transaction(&records[0]).expect("mapped row");
+        assert_eq!(transaction.client_id, 7);
+        assert_eq!(transaction.tx_id, 42);
+        assert_eq!(transaction.transaction_type, TransactionType::Deposit);
+        assert_eq!(transaction.amount, Some(dec!(1.5)));
+
+        assert!(matches!(
+            layout.transaction(&records[1]),
+            Err(MappingError::UnknownType { row: 2, literal }) if literal == "XX"
+        ));
+    }
+
+    #[test]
+    fn it_reports_a_missing_mapped_amount_column() {
+        let mut mapping = ColumnMapping::default();
+        mapping
+            .columns
+            .set(Field::Amount, Column::Name("amuont".to_owned()));
+        let headers = StringRecord::from(vec!["type", "client", "tx", "amount"]);
+
+        assert!(matches!(
+            mapping.layout(Some(&headers)),
+            Err(MappingError::MissingColumn(name)) if name == "amuont"
+        ));
+    }
+
+    #[test]
+    fn it_reads_rows_without_the_default_amount_column() {
+        let headers = StringRecord::from(vec!["type", "client", "tx"]);
+        let record = StringRecord::from(vec!["dispute", "1", "2"]);
+
+        let transaction = ColumnMapping::default()
+            .layout(Some(&headers))
+            .expect("layout without amounts")
+            .transaction(&record)
+            .expect("mapped row");
+        assert_eq!(transaction.amount, None);
+    }
+
+    #[test]
+    fn it_requires_headers_for_named_columns() {
+        let mapping = ColumnMapping {
+            has_headers: false,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            mapping.layout(None),
+            Err(MappingError::HeadersRequired(name)) if name == "type"
+        ));
+    }
+}
@@ -1,18 +1,26 @@
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::error::Error as StdError;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use tracing::Instrument;
 
+use crate::archive::{Archive, InMemoryArchive};
 use crate::core::repository::Repository;
-use crate::core::{Envelope, GetError, Handler};
-use crate::domain::{Account, BankAccountRoot, Transaction, TransactionType};
+use crate::core::{Envelope, GetError, Handler, Version};
+use crate::domain::{Account, BankAccountError, BankAccountRoot, Transaction, TransactionType};
+use crate::fees::FeeSchedule;
+use crate::limits::{CreditLimits, DisputeRules, WithdrawalPolicy};
 use crate::runtime::sealed::State;
+use crate::telemetry;
 
 pub trait Read {
     type Request;
@@ -31,6 +39,14 @@ pub trait Read {
 #[derive(Clone)]
 pub struct Service {
     repository: Arc<dyn Repository<Account>>,
+    hold_expiry: Option<Version>,
+    fee_schedule: Option<Arc<FeeSchedule>>,
+    credit_limits: Option<Arc<CreditLimits>>,
+    withdrawal_policy: Option<Arc<WithdrawalPolicy>>,
+    dispute_rules: DisputeRules,
+    quarantine: bool,
+    archive_after: Option<Version>,
+    archive: Arc<dyn Archive>,
 }
 
 impl<R> From<R> for Service
@@ -40,60 +56,264 @@ where
     fn from(repository: R) -> Self {
         Self {
             repository: Arc::new(repository),
+            hold_expiry: None,
+            fee_schedule: None,
+            credit_limits: None,
+            withdrawal_policy: None,
+            dispute_rules: DisputeRules::default(),
+            quarantine: false,
+            archive_after: None,
+            archive: Arc::new(InMemoryArchive::default()),
         }
     }
 }
 
-#[async_trait]
-impl Handler<Transaction> for Service {
-    type Error = anyhow::Error;
+impl Service {
+    /// Expires authorization holds once they have been open for `transactions` later client
+    /// transactions.
+    pub fn with_hold_expiry(mut self, transactions: Option<Version>) -> Self {
+        self.hold_expiry = transactions;
+        self
+    }
 
-    async fn handle(&self, command: Envelope<Transaction>) -> Result<(), Self::Error> {
-        let command = command.message;
-
-        match command.transaction_type {
-            TransactionType::Deposit => match self.repository.get(&command.client_id).await {
-                Ok(account) => {
-                    let mut root = BankAccountRoot::from(account);
-                    root.deposit(command)?;
-                    self.repository.save(&mut root).await?
-                }
-                Err(_err) if matches!(GetError::NotFound, _err) => {
-                    tracing::debug!("creating new account: {:?}", &command.client_id);
-                    let mut root = BankAccountRoot::open(command.clone())?;
-                    self.repository.save(&mut root).await?
-                }
-                Err(err) => return Err(anyhow::Error::from(err)),
-            },
-            TransactionType::Withdrawal => {
-                let mut root: BankAccountRoot =
-                    self.repository.get(&command.client_id).await?.into();
-                root.withdrawal(command)?;
-                self.repository.save(&mut root).await?
+    /// Charges fees on accepted transactions and credits them to the house revenue account.
+    pub fn with_fee_schedule(mut self, schedule: Option<FeeSchedule>) -> Self {
+        self.fee_schedule = schedule.map(Arc::new);
+        self
+    }
+
+    /// Lets client accounts overdraw their available funds up to their credit limit.
+    pub fn with_credit_limits(mut self, limits: Option<CreditLimits>) -> Self {
+        self.credit_limits = limits.map(Arc::new);
+        self
+    }
+
+    /// Evaluates per-client risk controls on every withdrawal.
+    pub fn with_withdrawal_policy(mut self, policy: Option<WithdrawalPolicy>) -> Self {
+        self.withdrawal_policy = policy.map(Arc::new);
+        self
+    }
+
+    /// Declines disputes falling outside the dispute window or over the dispute limit.
+    pub fn with_dispute_rules(mut self, rules: Option<DisputeRules>) -> Self {
+        self.dispute_rules = rules.unwrap_or_default();
+        self
+    }
+
+    /// Skips the transactions of accounts with a corrupt event stream, instead of failing on them.
+    pub fn with_quarantine(mut self, quarantine: bool) -> Self {
+        self.quarantine = quarantine;
+        self
+    }
+
+    /// Archives transactions out of the account state once they have been retained for
+    /// `transactions` later client transactions.
+    pub fn with_archive_after(mut self, transactions: Option<Version>) -> Self {
+        self.archive_after = transactions;
+        self
+    }
+
+    /// Sets the index archived transactions are stored in and restored from.
+    pub fn with_archive(mut self, archive: impl Archive + 'static) -> Self {
+        self.archive = Arc::new(archive);
+        self
+    }
+
+    /// Moves the transactions past the retention window to the archive index, and brings back
+    /// the archived transaction referenced by `transaction`, if any.
+    fn apply_retention(
+        &self,
+        root: &mut BankAccountRoot,
+        transaction: &Transaction,
+    ) -> Result<(), BankAccountError> {
+        let client_id = *root.aggregate_id();
+        let archived = root.archive_transactions(self.archive_after)?;
+        if !archived.is_empty() {
+            self.archive.store(client_id, archived);
+        }
+        if !root.has_transaction(transaction.tx_id) {
+            if let Some(archived) = self.archive.get(client_id, transaction.tx_id) {
+                root.restore(archived)?;
             }
-            TransactionType::Dispute => {
-                let mut root: BankAccountRoot =
-                    self.repository.get(&command.client_id).await?.into();
-                root.dispute(command)?;
-                self.repository.save(&mut root).await?
+        }
+        Ok(())
+    }
+
+    fn apply_limits(&self, root: &mut BankAccountRoot) -> Result<(), BankAccountError> {
+        let client_id = *root.aggregate_id();
+        if let Some(limits) = &self.credit_limits {
+            root.set_credit_limit(limits.limit(client_id))?;
+        }
+        if let Some(policy) = &self.withdrawal_policy {
+            root.set_withdrawal_limits(policy.limits(client_id))?;
+        }
+        Ok(())
+    }
+
+    async fn save_with_fees(
+        &self,
+        mut root: BankAccountRoot,
+        transaction: &Transaction,
+    ) -> anyhow::Result<()> {
+        let Some(schedule) = &self.fee_schedule else {
+            return Ok(self.repository.save_owned(root.into()).await?);
+        };
+
+        let fee = root.charge_fee(schedule, transaction)?;
+        self.repository.save_owned(root.into()).await?;
+
+        if let Some((fee_id, amount)) = fee {
+            let client_id = transaction.client_id;
+            self.collect_fee(schedule, client_id, fee_id, amount)
+                .await
+                .map_err(|source| FeeCollectionError {
+                    client_id,
+                    fee_id,
+                    amount,
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Credits a fee charged to a client account to the house revenue account.
+    async fn collect_fee(
+        &self,
+        schedule: &FeeSchedule,
+        client_id: u16,
+        fee_id: u32,
+        amount: Decimal,
+    ) -> anyhow::Result<()> {
+        let mut house = match self.repository.get(&schedule.house_account).await {
+            Ok(house) => BankAccountRoot::from(house),
+            Err(GetError::NotFound) => {
+                let house =
+                    BankAccountRoot::open_house(schedule.house_account, client_id, fee_id, amount)?;
+                return Ok(self.repository.save_owned(house.into()).await?);
             }
-            TransactionType::Resolve => {
-                let mut root: BankAccountRoot =
-                    self.repository.get(&command.client_id).await?.into();
-                root.resolve(command)?;
-                self.repository.save(&mut root).await?
+            Err(err) => return Err(anyhow::Error::from(err)),
+        };
+        house.collect_fee(client_id, fee_id, amount)?;
+        Ok(self.repository.save_owned(house.into()).await?)
+    }
+
+    /// Loads the account, runs the transaction against it and saves the recorded events, within
+    /// nested `load`, `decide` and `save` spans.
+    async fn handle_transaction(&self, transaction: Transaction) -> anyhow::Result<()> {
+        // fee revenue is credited to the house account, which no client can transact on
+        if let Some(schedule) = self
+            .fee_schedule
+            .as_ref()
+            .filter(|schedule| schedule.house_account == transaction.client_id)
+        {
+            return Err(BankAccountError::HouseAccount(schedule.house_account).into());
+        }
+        let command = transaction.clone();
+
+        let loaded = self
+            .repository
+            .get(&command.client_id)
+            .instrument(tracing::info_span!("load"))
+            .await;
+        let mut root: BankAccountRoot = match loaded {
+            Ok(account) => account.into(),
+            Err(GetError::NotFound) if command.transaction_type == TransactionType::Deposit => {
+                tracing::debug!("creating new account: {:?}", &command.client_id);
+                let root = tracing::info_span!("decide").in_scope(|| {
+                    let mut root = BankAccountRoot::open(command)?;
+                    self.apply_limits(&mut root)?;
+                    Ok::<_, BankAccountError>(root)
+                })?;
+                return self
+                    .save_with_fees(root, &transaction)
+                    .instrument(tracing::info_span!("save"))
+                    .await;
             }
-            TransactionType::Chargeback => {
-                let mut root: BankAccountRoot =
-                    self.repository.get(&command.client_id).await?.into();
-                root.chargeback(command)?;
-                self.repository.save(&mut root).await?
+            Err(source @ GetError::Rehydrate { .. }) if self.quarantine => {
+                return Err(QuarantinedError {
+                    client_id: command.client_id,
+                    tx_id: command.tx_id,
+                    source,
+                }
+                .into());
             }
+            Err(err) => return Err(anyhow::Error::from(err)),
+        };
+
+        let result = tracing::info_span!("decide").in_scope(|| {
+            root.expire_holds(self.hold_expiry)?;
+            self.apply_retention(&mut root, &command)?;
+            self.apply_limits(&mut root)?;
+            Ok::<_, BankAccountError>(match command.transaction_type {
+                TransactionType::Deposit => root.deposit(command),
+                TransactionType::Withdrawal => root.withdrawal(command),
+                TransactionType::Dispute => root.dispute(command, &self.dispute_rules),
+                TransactionType::Resolve => root.resolve(command),
+                TransactionType::Chargeback => root.chargeback(command),
+                TransactionType::Authorize => root.authorize(command),
+                TransactionType::Capture => root.capture(command),
+                TransactionType::Void => root.void(command),
+                TransactionType::Fee => Err(BankAccountError::UnexpectedFee(command.tx_id)),
+            })
+        })?;
+
+        async {
+            if let Err(err) = result {
+                // keep the events recorded before the rejection, e.g. expired holds or declined disputes
+                self.repository.save_owned(root.into()).await?;
+                return Err(err.into());
+            }
+            self.save_with_fees(root, &transaction).await
         }
-        Ok(())
+        .instrument(tracing::info_span!("save"))
+        .await
+    }
+}
+
+#[async_trait]
+impl Handler<Transaction> for Service {
+    type Error = anyhow::Error;
+
+    async fn handle(&self, command: Envelope<Transaction>) -> Result<(), Self::Error> {
+        let transaction = command.message;
+        let span = tracing::info_span!(
+            "transaction",
+            client = transaction.client_id,
+            tx = transaction.tx_id,
+            "type" = transaction.transaction_type.as_str(),
+        );
+        self.handle_transaction(transaction).instrument(span).await
     }
 }
 
+/// A transaction skipped because the event stream of its account can't be replayed and the
+/// [Service] quarantines such accounts.
+///
+/// The [Runtime] counts it apart from the rejected transactions and carries on whatever its
+/// [RejectionPolicy].
+#[derive(Debug, Error)]
+#[error("skipped transaction {tx_id} of quarantined account {client_id}: {source}")]
+pub struct QuarantinedError {
+    pub client_id: u16,
+    pub tx_id: u32,
+    #[source]
+    pub source: GetError,
+}
+
+/// A fee charged to a client account that could not be credited to the house revenue account.
+///
+/// The client transaction and its fee are saved by then, so the fee has to be collected by hand
+/// and the run is aborted rather than leaving the books unbalanced.
+#[derive(Debug, Error)]
+#[error("fee {fee_id} of {amount} charged to client {client_id} was not collected: {source}")]
+pub struct FeeCollectionError {
+    pub client_id: u16,
+    pub fee_id: u32,
+    pub amount: Decimal,
+    #[source]
+    pub source: anyhow::Error,
+}
+
 #[derive(Debug, Error)]
 pub enum ConnectorError {
     #[error("already exists")]
@@ -126,11 +346,51 @@ impl Executor for TokioExecutor {
     }
 }
 
+/// A transaction rejected by the [Service], along with the reason.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rejection {
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    #[serde(rename = "type")]
+    pub transaction_type: TransactionType,
+    pub reason: String,
+}
+
+/// What the [Runtime] does when the [Service] rejects a transaction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectionPolicy {
+    /// Log the rejection and carry on with the next transaction.
+    #[default]
+    Skip,
+    /// Abort the run.
+    Abort,
+}
+
+/// Counts of the transactions handled by the [Runtime], by outcome.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcomes {
+    /// Accepted transactions, by type.
+    pub accepted: BTreeMap<&'static str, u64>,
+    /// Rejected transactions, by type.
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// Rejected transactions, by error variant.
+    pub errors: BTreeMap<&'static str, u64>,
+    /// Transactions of quarantined accounts, skipped without being handled, by type.
+    pub quarantined: BTreeMap<&'static str, u64>,
+}
+
 pub struct Runtime<E, S: State> {
     svc: Service,
     connector: HashMap<String, Box<dyn Read<Request = Transaction> + Send>>,
     executor: E,
     account_ids: BTreeSet<u16>,
+    outcomes: Outcomes,
+    rejections: Option<Vec<Rejection>>,
+    rejection_policy: RejectionPolicy,
+    channel_size: usize,
     _state: PhantomData<S>,
 }
 
@@ -141,6 +401,10 @@ impl Runtime<TokioExecutor, Idle> {
             connector: Default::default(),
             executor: TokioExecutor,
             account_ids: BTreeSet::new(),
+            outcomes: Outcomes::default(),
+            rejections: None,
+            rejection_policy: RejectionPolicy::default(),
+            channel_size: 8192,
             _state: PhantomData,
         }
     }
@@ -151,9 +415,36 @@ impl<E, S: State> Runtime<E, S> {
     pub const fn account_ids(&self) -> &BTreeSet<u16> {
         &self.account_ids
     }
+
+    /// The transactions accepted, rejected and quarantined so far.
+    pub fn outcomes(&self) -> &Outcomes {
+        &self.outcomes
+    }
+
+    /// The transactions rejected so far, empty unless recorded [with_rejections][Runtime::with_rejections].
+    pub fn rejections(&self) -> &[Rejection] {
+        self.rejections.as_deref().unwrap_or_default()
+    }
 }
 
 impl<E> Runtime<E, Idle> {
+    /// Records the rejected transactions and their reasons while running.
+    pub fn with_rejections(mut self) -> Self {
+        self.rejections = Some(Vec::new());
+        self
+    }
+
+    pub fn with_rejection_policy(mut self, rejection_policy: RejectionPolicy) -> Self {
+        self.rejection_policy = rejection_policy;
+        self
+    }
+
+    /// Sets the number of transactions buffered between the connectors and the [Service].
+    pub fn with_channel_size(mut self, channel_size: usize) -> Self {
+        self.channel_size = channel_size;
+        self
+    }
+
     pub fn with_connector(
         mut self,
         connector_id: impl Into<String>,
@@ -171,7 +462,7 @@ impl<E> Runtime<E, Idle> {
     where
         E: Executor,
     {
-        let (tx, rx) = flume::bounded(8192);
+        let (tx, rx) = flume::bounded(self.channel_size);
 
         for (connector_name, mut connector) in self.connector.drain() {
             let tx = tx.clone();
@@ -188,9 +479,67 @@ impl<E> Runtime<E, Idle> {
         drop(tx);
 
         while let Ok(request) = rx.recv_async().await {
+            metrics::gauge!(telemetry::QUEUE_DEPTH, "queue" => "dispatch").set(rx.len() as f64);
             self.account_ids.insert(request.client_id);
-            if let Err(err) = self.svc.handle(request.into()).await {
-                tracing::warn!(error=?err, "Error processing transaction:");
+            let (client_id, tx_id) = (request.client_id, request.tx_id);
+            let transaction_type = request.transaction_type.as_str();
+            let rejected = self.rejections.is_some().then(|| {
+                (
+                    request.client_id,
+                    request.tx_id,
+                    request.transaction_type.clone(),
+                )
+            });
+            let started = Instant::now();
+            let handled = self.svc.handle(request.into()).await;
+            metrics::histogram!(telemetry::HANDLE_DURATION, "type" => transaction_type)
+                .record(started.elapsed());
+            if let Err(err) = handled {
+                if err.is::<QuarantinedError>() {
+                    tracing::error!(error = %err, "skipping transaction of quarantined account");
+                    metrics::counter!(telemetry::TRANSACTIONS_QUARANTINED, "type" => transaction_type)
+                        .increment(1);
+                    *self
+                        .outcomes
+                        .quarantined
+                        .entry(transaction_type)
+                        .or_default() += 1;
+                    continue;
+                }
+                let error = telemetry::error_label(&err);
+                metrics::counter!(
+                    telemetry::TRANSACTIONS_REJECTED,
+                    "type" => transaction_type,
+                    "error" => error
+                )
+                .increment(1);
+                // a corrupt account stream can't be recovered from, unless the service quarantines it
+                if let Some(GetError::Rehydrate { .. }) = err.downcast_ref::<GetError>() {
+                    return Err(err);
+                }
+                if err.is::<FeeCollectionError>() {
+                    return Err(err);
+                }
+                if self.rejection_policy == RejectionPolicy::Abort {
+                    return Err(err);
+                }
+                tracing::warn!(error=?err, client = client_id, tx = tx_id, "Error processing transaction:");
+                *self.outcomes.rejected.entry(transaction_type).or_default() += 1;
+                *self.outcomes.errors.entry(error).or_default() += 1;
+                if let (Some(rejections), Some((client_id, tx_id, transaction_type))) =
+                    (self.rejections.as_mut(), rejected)
+                {
+                    rejections.push(Rejection {
+                        client_id,
+                        tx_id,
+                        transaction_type,
+                        reason: err.to_string(),
+                    });
+                }
+            } else {
+                metrics::counter!(telemetry::TRANSACTIONS_PROCESSED, "type" => transaction_type)
+                    .increment(1);
+                *self.outcomes.accepted.entry(transaction_type).or_default() += 1;
             }
         }
 
@@ -199,6 +548,10 @@ impl<E> Runtime<E, Idle> {
             connector: self.connector,
             executor: self.executor,
             account_ids: self.account_ids,
+            outcomes: self.outcomes,
+            rejections: self.rejections,
+            rejection_policy: self.rejection_policy,
+            channel_size: self.channel_size,
             _state: PhantomData,
         };
 
@@ -214,3 +567,72 @@ mod sealed {
     impl State for super::Dead {}
     impl State for super::Idle {}
 }
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::core::{Appender, Check, EventSourced, InMemory};
+    use crate::domain::TransactionEvent;
+    use crate::mapping::ColumnMapping;
+    use crate::summary::RowCounts;
+    use crate::InputProcessor;
+
+    #[tokio::test]
+    async fn it_counts_quarantined_transactions_apart_from_rejected_ones() {
+        let store = InMemory::default();
+        // a dispute of an unknown transaction, which account 1 can't be rehydrated from
+        store
+            .append(
+                1,
+                Check::MustBe(0),
+                vec![
+                    Envelope::from(TransactionEvent::WasOpened {
+                        tx_id: 1,
+                        account_holder_id: 1,
+                        transaction: Transaction {
+                            status: Default::default(),
+                            client_id: 1,
+                            tx_id: 1,
+                            transaction_type: TransactionType::Deposit,
+                            amount: Some(dec!(10)),
+                        },
+                    }),
+                    Envelope::from(TransactionEvent::DisputeWasRecorded {
+                        tx_id: 42,
+                        amount: dec!(10),
+                    }),
+                ],
+            )
+            .await
+            .unwrap();
+
+        let csv = "type,client,tx,amount\n\
+                   deposit,1,2,1.0\n\
+                   withdrawal,1,3,1.0\n\
+                   deposit,2,4,1.0\n\
+                   withdrawal,2,5,2.0\n";
+        let (tx, rx) = flume::unbounded();
+        let rows = Arc::new(RowCounts::default());
+        InputProcessor::read_csv(csv.as_bytes(), ColumnMapping::default(), tx, &rows).unwrap();
+
+        let service = Service::from(EventSourced::<Account, _>::from(store)).with_quarantine(true);
+        let engine = Runtime::new(service)
+            .with_connector("csv", InputProcessor { rx, rows })
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            &Outcomes {
+                accepted: [("deposit", 1)].into(),
+                rejected: [("withdrawal", 1)].into(),
+                errors: [("InsufficientFunds", 1)].into(),
+                quarantined: [("deposit", 1), ("withdrawal", 1)].into(),
+            },
+            engine.outcomes()
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! End-of-run summary of a batch, for operators to sanity-check a run at a glance.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::core::CacheMetrics;
+use crate::domain::{AccountSnapShot, TransactionEvent};
+use crate::runtime::Outcomes;
+
+#[derive(Debug, Error)]
+pub enum SummaryError {
+    #[error("failed to encode the run summary: {0}")]
+    Encode(#[from] serde_json::Error),
+    #[error("failed to write the run summary to {path}: {source}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// Input rows counted by the reader of a connector, shared with the thread reading them.
+#[derive(Debug, Default)]
+pub struct RowCounts {
+    read: AtomicU64,
+    parsed: AtomicU64,
+}
+
+impl RowCounts {
+    /// Counts a row read from the input, whether or not it parses.
+    pub fn read(&self) {
+        self.read.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a row parsed into a transaction.
+    pub fn parsed(&self) {
+        self.parsed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts and totals of a run.
+///
+/// Amounts add up the events recorded during the run, while `held` and `accounts_locked` are
+/// the state of the accounts once it ends.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub rows_read: u64,
+    pub rows_parsed: u64,
+    /// Accepted transactions, by type.
+    pub accepted: BTreeMap<&'static str, u64>,
+    /// Rejected transactions, by type.
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// Rejected transactions, by error variant.
+    pub rejections: BTreeMap<&'static str, u64>,
+    /// Transactions of quarantined accounts, skipped without being handled, by type.
+    pub quarantined: BTreeMap<&'static str, u64>,
+    pub accounts_opened: u64,
+    pub accounts_locked: u64,
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    pub captured: Decimal,
+    pub held: Decimal,
+    pub charged_back: Decimal,
+    /// Hits and misses of the aggregate cache, when one is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<CacheMetrics>,
+    pub wall_time_seconds: f64,
+    /// Rows read per second of wall time.
+    pub throughput: f64,
+}
+
+impl Summary {
+    pub fn new(rows: &RowCounts, outcomes: &Outcomes) -> Self {
+        Self {
+            rows_read: rows.read.load(Ordering::Relaxed),
+            rows_parsed: rows.parsed.load(Ordering::Relaxed),
+            accepted: outcomes.accepted.clone(),
+            rejected: outcomes.rejected.clone(),
+            rejections: outcomes.errors.clone(),
+            quarantined: outcomes.quarantined.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds the amount moved by an event recorded during the run to the totals.
+    pub fn record(&mut self, event: &TransactionEvent) {
+        match event {
+            TransactionEvent::WasOpened { transaction, .. } => {
+                self.accounts_opened += 1;
+                self.deposited += transaction.amount.unwrap_or_default();
+            }
+            TransactionEvent::DepositWasRecorded { amount, .. } => self.deposited += amount,
+            TransactionEvent::WithdrawalWasRecorded { amount, .. } => self.withdrawn += amount,
+            TransactionEvent::CaptureWasRecorded { amount, .. } => self.captured += amount,
+            TransactionEvent::ChargebackWasRecorded { amount, .. } => self.charged_back += amount,
+            _ => {}
+        }
+    }
+
+    /// Adds the balances of an account at the end of the run.
+    pub fn account(&mut self, snapshot: &AccountSnapShot) {
+        self.held += snapshot.held();
+        if snapshot.locked() {
+            self.accounts_locked += 1;
+        }
+    }
+
+    pub fn finish(&mut self, elapsed: Duration) {
+        self.wall_time_seconds = elapsed.as_secs_f64();
+        if !elapsed.is_zero() {
+            self.throughput = self.rows_read as f64 / elapsed.as_secs_f64();
+        }
+    }
+
+    /// Writes the summary as JSON to `path`.
+    pub fn write(&self, path: &Path) -> Result<(), SummaryError> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").map_err(|source| SummaryError::Write {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+/// Writes `name=count` pairs, or `-` when there are none.
+struct Counts<'a>(&'a BTreeMap<&'static str, u64>);
+
+impl fmt::Display for Counts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("-");
+        }
+        for (i, (name, count)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{name}={count}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run summary")?;
+        writeln!(f, "  rows read:        {}", self.rows_read)?;
+        writeln!(f, "  rows parsed:      {}", self.rows_parsed)?;
+        writeln!(f, "  accepted:         {}", Counts(&self.accepted))?;
+        writeln!(f, "  rejected:         {}", Counts(&self.rejected))?;
+        writeln!(f, "  rejections:       {}", Counts(&self.rejections))?;
+        writeln!(f, "  quarantined:      {}", Counts(&self.quarantined))?;
+        writeln!(f, "  accounts opened:  {}", self.accounts_opened)?;
+        writeln!(f, "  accounts locked:  {}", self.accounts_locked)?;
+        writeln!(f, "  deposited:        {}", self.deposited)?;
+        writeln!(f, "  withdrawn:        {}", self.withdrawn)?;
+        writeln!(f, "  captured:         {}", self.captured)?;
+        writeln!(f, "  held:             {}", self.held)?;
+        writeln!(f, "  charged back:     {}", self.charged_back)?;
+        if let Some(cache) = &self.cache {
+            writeln!(
+                f,
+                "  aggregate cache:  hits={} misses={} stale={}",
+                cache.hits, cache.misses, cache.stale
+            )?;
+        }
+        writeln!(f, "  wall time:        {:.3}s", self.wall_time_seconds)?;
+        write!(f, "  throughput:       {:.0} rows/s", self.throughput)
+    }
+}
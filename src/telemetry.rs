@@ -0,0 +1,202 @@
+//! Prometheus metrics of the runtime and the event-sourced repository, and OpenTelemetry export
+//! of the tracing spans.
+//!
+//! Metrics are recorded through the [`metrics`] facade, and are only kept once a recorder is
+//! [installed][Metrics::install].
+use std::io;
+use std::path::PathBuf;
+
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+use opentelemetry_otlp::{ExporterBuildError, Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use thiserror::Error;
+
+use crate::config::MetricsConfig;
+use crate::core::GetError;
+use crate::domain::BankAccountError;
+
+/// Transactions accepted by the service, by `type`.
+pub const TRANSACTIONS_PROCESSED: &str = "payments_transactions_processed_total";
+/// Transactions rejected by the service, by `type` and `error`.
+pub const TRANSACTIONS_REJECTED: &str = "payments_transactions_rejected_total";
+/// Transactions of quarantined accounts, skipped by the service, by `type`.
+pub const TRANSACTIONS_QUARANTINED: &str = "payments_transactions_quarantined_total";
+/// Transactions waiting in the `ingest` queue of the input connector or the `dispatch` queue of
+/// the runtime.
+pub const QUEUE_DEPTH: &str = "payments_queue_depth";
+/// Time the service takes to handle a transaction, by `type`.
+pub const HANDLE_DURATION: &str = "payments_handle_duration_seconds";
+/// Events applied to rehydrate an aggregate from the event store, by `aggregate`.
+pub const REHYDRATED_EVENTS: &str = "payments_rehydrated_events";
+/// Time the event store takes to append the events of an aggregate, by `aggregate`.
+pub const APPEND_DURATION: &str = "payments_store_append_duration_seconds";
+
+const DURATION_BUCKETS: &[f64] = &[
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5,
+    1.0,
+];
+const EVENT_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0];
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("failed to set up the metrics exporter: {0}")]
+    Build(#[from] BuildError),
+    #[error("failed to write the metrics to {path}: {source}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// The installed Prometheus recorder.
+pub struct Metrics {
+    handle: PrometheusHandle,
+    file: Option<PathBuf>,
+}
+
+impl Metrics {
+    /// Installs the global Prometheus recorder, serving the metrics on the configured address,
+    /// if any. Returns `None` without recording anything when no export is configured.
+    ///
+    /// Serving the metrics must be set up from within a Tokio runtime.
+    pub fn install(config: &MetricsConfig) -> Result<Option<Self>, MetricsError> {
+        if !config.is_enabled() {
+            return Ok(None);
+        }
+
+        let builder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".to_owned()), DURATION_BUCKETS)?
+            .set_buckets_for_metric(Matcher::Full(REHYDRATED_EVENTS.to_owned()), EVENT_BUCKETS)?;
+        let handle = match config.listen {
+            Some(address) => {
+                let (recorder, exporter) = builder.with_http_listener(address).build()?;
+                let handle = recorder.handle();
+                tokio::spawn(exporter);
+                metrics::set_global_recorder(recorder).map_err(BuildError::from)?;
+                handle
+            }
+            None => builder.install_recorder()?,
+        };
+        tracing::info!(address = ?config.listen, file = ?config.file, "exporting metrics");
+
+        describe_counter!(
+            TRANSACTIONS_PROCESSED,
+            "Transactions accepted by the service."
+        );
+        describe_counter!(
+            TRANSACTIONS_REJECTED,
+            "Transactions rejected by the service."
+        );
+        describe_gauge!(QUEUE_DEPTH, "Transactions waiting in a queue.");
+        describe_histogram!(
+            HANDLE_DURATION,
+            Unit::Seconds,
+            "Time the service takes to handle a transaction."
+        );
+        describe_histogram!(
+            REHYDRATED_EVENTS,
+            "Events applied to rehydrate an aggregate from the event store."
+        );
+        describe_histogram!(
+            APPEND_DURATION,
+            Unit::Seconds,
+            "Time the event store takes to append the events of an aggregate."
+        );
+
+        Ok(Some(Self {
+            handle,
+            file: config.file.clone(),
+        }))
+    }
+
+    /// Renders the metrics recorded so far in the Prometheus text format.
+    pub fn render(&self) -> String {
+        self.handle.run_upkeep();
+        self.handle.render()
+    }
+
+    /// Writes the metrics recorded so far to the configured file, if any.
+    pub fn dump(&self) -> Result<(), MetricsError> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        std::fs::write(path, self.render()).map_err(|source| MetricsError::Write {
+            path: path.clone(),
+            source,
+        })
+    }
+}
+
+/// Exports the spans it traces as OTLP/HTTP JSON to the collector at `endpoint`, e.g.
+/// `http://localhost:4318`, until it is dropped.
+pub struct Tracer {
+    provider: SdkTracerProvider,
+}
+
+impl Tracer {
+    pub fn otlp(endpoint: &str) -> Result<Self, ExporterBuildError> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpJson)
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(env!("CARGO_PKG_NAME"))
+                    .build(),
+            )
+            .build();
+        Ok(Self { provider })
+    }
+
+    pub fn provider(&self) -> &SdkTracerProvider {
+        &self.provider
+    }
+}
+
+impl Drop for Tracer {
+    /// Flushes the spans not exported yet.
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            eprintln!("Error exporting spans: {err}");
+        }
+    }
+}
+
+/// The `error` label of a transaction rejected with `err`: the [`BankAccountError`] or
+/// [`GetError`] variant, or `Other`.
+pub(crate) fn error_label(err: &anyhow::Error) -> &'static str {
+    if let Some(err) = err.downcast_ref::<BankAccountError>() {
+        return err.name();
+    }
+    match err.downcast_ref::<GetError>() {
+        Some(GetError::NotFound) => "NotFound",
+        Some(GetError::Rehydrate { .. }) => "Rehydrate",
+        Some(GetError::Internal(_)) | None => "Other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejections_are_labelled_by_error_variant() {
+        let locked = anyhow::Error::from(BankAccountError::LockedAccount { id: 1, tx: 2 });
+        let not_found = anyhow::Error::from(GetError::NotFound);
+        let insufficient = anyhow::Error::from(BankAccountError::InsufficientFunds);
+        let house = anyhow::Error::from(BankAccountError::HouseAccount(99));
+        let other = anyhow::anyhow!("connector closed");
+
+        assert_eq!("LockedAccount", error_label(&locked));
+        assert_eq!("InsufficientFunds", error_label(&insufficient));
+        assert_eq!("HouseAccount", error_label(&house));
+        assert_eq!("NotFound", error_label(&not_found));
+        assert_eq!("Other", error_label(&other));
+    }
+}
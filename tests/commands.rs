@@ -1,6 +1,10 @@
-use payments_engine_rs::core::{Envelope, EventSourced, Persisted, Scenario};
-use payments_engine_rs::domain::{Transaction, TransactionEvent, TransactionType};
-use payments_engine_rs::runtime::Service;
+use payments_engine_rs::core::{Envelope, EventSourced, GetError, Persisted, Root, Scenario};
+use payments_engine_rs::domain::{
+    Account, BankAccountError, DeclineReason, Transaction, TransactionEvent, TransactionType,
+};
+use payments_engine_rs::fees::{FeeRule, FeeSchedule};
+use payments_engine_rs::limits::DisputeRules;
+use payments_engine_rs::runtime::{FeeCollectionError, QuarantinedError, Service};
 use rust_decimal_macros::dec;
 
 #[tokio::test]
@@ -27,6 +31,7 @@ async fn it_records_new_account_deposit() {
                     amount: Some(dec!(10.123)),
                 },
             }),
+            link: None,
         }])
         .assert_on(|even_store| Service::from(EventSourced::from(even_store)))
         .await;
@@ -49,6 +54,7 @@ async fn it_updates_deposit_of_existing_account() {
                     amount: Some(dec!(10.123)),
                 },
             }),
+            link: None,
         }])
         .when(Envelope::from(Transaction {
             status: Default::default(),
@@ -70,7 +76,603 @@ async fn it_updates_deposit_of_existing_account() {
                     amount: Some(dec!(10.123)),
                 },
             }),
+            link: None,
         }])
         .assert_on(|even_store| Service::from(EventSourced::from(even_store)))
         .await;
 }
+
+#[tokio::test]
+async fn it_reserves_funds_for_authorization() {
+    Scenario
+        .given(vec![Persisted {
+            stream_id: 1,
+            version: 1,
+            event: Envelope::from(TransactionEvent::WasOpened {
+                tx_id: 1,
+                account_holder_id: 1,
+                transaction: Transaction {
+                    status: Default::default(),
+                    client_id: 1,
+                    tx_id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    amount: Some(dec!(10.123)),
+                },
+            }),
+            link: None,
+        }])
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 2,
+            transaction_type: TransactionType::Authorize,
+            amount: Some(dec!(5)),
+        }))
+        .then(vec![Persisted {
+            stream_id: 1,
+            version: 2,
+            event: Envelope::from(TransactionEvent::AuthorizationWasRecorded {
+                amount: dec!(5),
+                transaction: Transaction {
+                    status: Default::default(),
+                    client_id: 1,
+                    tx_id: 2,
+                    transaction_type: TransactionType::Authorize,
+                    amount: Some(dec!(5)),
+                },
+            }),
+            link: None,
+        }])
+        .assert_on(|even_store| Service::from(EventSourced::from(even_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_rejects_capture_exceeding_authorization() {
+    Scenario
+        .given(vec![
+            Persisted {
+                stream_id: 1,
+                version: 1,
+                event: Envelope::from(TransactionEvent::WasOpened {
+                    tx_id: 1,
+                    account_holder_id: 1,
+                    transaction: Transaction {
+                        status: Default::default(),
+                        client_id: 1,
+                        tx_id: 1,
+                        transaction_type: TransactionType::Deposit,
+                        amount: Some(dec!(10.123)),
+                    },
+                }),
+                link: None,
+            },
+            Persisted {
+                stream_id: 1,
+                version: 2,
+                event: Envelope::from(TransactionEvent::AuthorizationWasRecorded {
+                    amount: dec!(5),
+                    transaction: Transaction {
+                        status: Default::default(),
+                        client_id: 1,
+                        tx_id: 2,
+                        transaction_type: TransactionType::Authorize,
+                        amount: Some(dec!(5)),
+                    },
+                }),
+                link: None,
+            },
+        ])
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 2,
+            transaction_type: TransactionType::Capture,
+            amount: Some(dec!(6)),
+        }))
+        .then_fails()
+        .assert_on(|even_store| Service::from(EventSourced::from(even_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_charges_withdrawal_fee_to_house_account() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        rates: [(
+            TransactionType::Withdrawal,
+            FeeRule {
+                flat: dec!(0.5),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    Scenario
+        .given(vec![Persisted {
+            stream_id: 1,
+            version: 1,
+            event: Envelope::from(TransactionEvent::WasOpened {
+                tx_id: 1,
+                account_holder_id: 1,
+                transaction: Transaction {
+                    status: Default::default(),
+                    client_id: 1,
+                    tx_id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    amount: Some(dec!(10.123)),
+                },
+            }),
+            link: None,
+        }])
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 2,
+            transaction_type: TransactionType::Withdrawal,
+            amount: Some(dec!(5)),
+        }))
+        .then(vec![
+            Persisted {
+                stream_id: 1,
+                version: 2,
+                event: Envelope::from(TransactionEvent::WithdrawalWasRecorded {
+                    amount: dec!(5),
+                    transaction: Transaction {
+                        status: Default::default(),
+                        client_id: 1,
+                        tx_id: 2,
+                        transaction_type: TransactionType::Withdrawal,
+                        amount: Some(dec!(5)),
+                    },
+                }),
+                link: None,
+            },
+            Persisted {
+                stream_id: 1,
+                version: 3,
+                event: Envelope::from(TransactionEvent::FeeWasCharged {
+                    tx_id: 2,
+                    fee_id: u32::MAX,
+                    amount: dec!(0.5),
+                }),
+                link: None,
+            },
+            Persisted {
+                stream_id: 99,
+                version: 1,
+                event: Envelope::from(TransactionEvent::FeeWasCollected {
+                    account_holder_id: 99,
+                    client_id: 1,
+                    fee_id: u32::MAX,
+                    amount: dec!(0.5),
+                }),
+                link: None,
+            },
+        ])
+        .assert_on(|even_store| {
+            Service::from(EventSourced::from(even_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_rejects_transactions_of_the_house_account() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        ..Default::default()
+    };
+
+    Scenario
+        .when(transaction(99, 1, TransactionType::Deposit, Some(dec!(10))))
+        .then_fails_with_error(BankAccountError::HouseAccount(99))
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_reports_a_fee_the_house_account_did_not_collect() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        rates: [(
+            TransactionType::Deposit,
+            FeeRule {
+                flat: dec!(0.5),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    Scenario
+        .given(vec![Persisted {
+            stream_id: 99,
+            version: 1,
+            // a house account stream that doesn't rehydrate
+            event: Envelope::from(TransactionEvent::CreditLimitWasSet { limit: dec!(1) }),
+            link: None,
+        }])
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .then_fails_with(|err: &anyhow::Error| {
+            matches!(
+                err.downcast_ref::<FeeCollectionError>(),
+                Some(FeeCollectionError {
+                    client_id: 1,
+                    fee_id: u32::MAX,
+                    ..
+                })
+            )
+        })
+        .then_state(1, |account: &Root<Account>| {
+            // the client transaction and its fee are saved regardless
+            assert_eq!(dec!(9.5), account.snapshot().available());
+        })
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_disputes_a_fee_on_its_own() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        rates: [(
+            TransactionType::Withdrawal,
+            FeeRule {
+                flat: dec!(0.5),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when(transaction(
+            1,
+            2,
+            TransactionType::Withdrawal,
+            Some(dec!(5)),
+        ))
+        .when(transaction(1, u32::MAX, TransactionType::Dispute, None))
+        .then_state(1, |account: &Root<Account>| {
+            // only the fee is held, the withdrawal it was charged for stays undisputed
+            let snapshot = account.snapshot();
+            assert_eq!(dec!(4), snapshot.available());
+            assert_eq!(dec!(0.5), snapshot.held());
+        })
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_accepts_client_transactions_under_fee_ids() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        rates: [(
+            TransactionType::Deposit,
+            FeeRule {
+                flat: dec!(0.5),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when(transaction(
+            1,
+            u32::MAX,
+            TransactionType::Deposit,
+            Some(dec!(1)),
+        ))
+        .when(transaction(1, u32::MAX, TransactionType::Dispute, None))
+        .then_state(1, |account: &Root<Account>| {
+            // the dispute references the client deposit, not the fee charged under the same id
+            let snapshot = account.snapshot();
+            assert_eq!(dec!(9), snapshot.available());
+            assert_eq!(dec!(1), snapshot.held());
+        })
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_charges_chargeback_fees_on_the_charged_back_amount() {
+    let schedule = FeeSchedule {
+        house_account: 99,
+        rates: [(
+            TransactionType::Chargeback,
+            FeeRule {
+                percentage: dec!(10),
+                ..Default::default()
+            },
+        )]
+        .into(),
+        ..Default::default()
+    };
+
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(100))))
+        .when(transaction(1, 1, TransactionType::Dispute, Some(dec!(40))))
+        .when(transaction(1, 1, TransactionType::Chargeback, None))
+        .then_state(1, |account: &Root<Account>| {
+            // the fee is based on the 40 charged back, not on the deposit
+            let snapshot = account.snapshot();
+            assert_eq!(dec!(56), snapshot.available());
+            assert_eq!(dec!(0), snapshot.held());
+        })
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_fee_schedule(Some(schedule.clone()))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_declines_dispute_over_the_dispute_limit() {
+    Scenario
+        .given(vec![
+            Persisted {
+                stream_id: 1,
+                version: 1,
+                event: Envelope::from(TransactionEvent::WasOpened {
+                    tx_id: 1,
+                    account_holder_id: 1,
+                    transaction: Transaction {
+                        status: Default::default(),
+                        client_id: 1,
+                        tx_id: 1,
+                        transaction_type: TransactionType::Deposit,
+                        amount: Some(dec!(10)),
+                    },
+                }),
+                link: None,
+            },
+            Persisted {
+                stream_id: 1,
+                version: 2,
+                event: Envelope::from(TransactionEvent::DisputeWasRecorded {
+                    tx_id: 1,
+                    amount: dec!(10),
+                }),
+                link: None,
+            },
+            Persisted {
+                stream_id: 1,
+                version: 3,
+                event: Envelope::from(TransactionEvent::ResolveWasRecorded {
+                    tx_id: 1,
+                    amount: dec!(10),
+                }),
+                link: None,
+            },
+        ])
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 1,
+            transaction_type: TransactionType::Dispute,
+            amount: None,
+        }))
+        .then(vec![Persisted {
+            stream_id: 1,
+            version: 4,
+            event: Envelope::from(TransactionEvent::DisputeWasDeclined {
+                tx_id: 1,
+                reason: DeclineReason::LimitReached,
+            }),
+            link: None,
+        }])
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_dispute_rules(Some(DisputeRules {
+                max_age: None,
+                max_disputes: Some(1),
+            }))
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn it_declines_dispute_of_resolved_transaction_by_default() {
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when(transaction(1, 1, TransactionType::Dispute, None))
+        .when(transaction(1, 1, TransactionType::Resolve, None))
+        .when(transaction(1, 1, TransactionType::Dispute, None))
+        .then_fails_with_error(BankAccountError::DisputeFinalized(1))
+        .then_state(1, |account: &Root<Account>| {
+            // the declined dispute is recorded, but holds nothing
+            assert_eq!(4, account.version());
+            assert_eq!(dec!(10), account.snapshot().available());
+            assert_eq!(dec!(0), account.snapshot().held());
+        })
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+fn corrupt_account_stream() -> Vec<Persisted<u16, TransactionEvent>> {
+    vec![
+        Persisted {
+            stream_id: 1,
+            version: 1,
+            event: Envelope::from(TransactionEvent::WasOpened {
+                tx_id: 1,
+                account_holder_id: 1,
+                transaction: Transaction {
+                    status: Default::default(),
+                    client_id: 1,
+                    tx_id: 1,
+                    transaction_type: TransactionType::Deposit,
+                    amount: Some(dec!(10)),
+                },
+            }),
+            link: None,
+        },
+        Persisted {
+            stream_id: 1,
+            version: 2,
+            event: Envelope::from(TransactionEvent::DisputeWasRecorded {
+                tx_id: 42,
+                amount: dec!(10),
+            }),
+            link: None,
+        },
+    ]
+}
+
+#[tokio::test]
+async fn it_fails_on_corrupt_account_stream() {
+    Scenario
+        .given(corrupt_account_stream())
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 2,
+            transaction_type: TransactionType::Deposit,
+            amount: Some(dec!(1)),
+        }))
+        .then_fails()
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_skips_quarantined_account_stream() {
+    Scenario
+        .given(corrupt_account_stream())
+        .when(Envelope::from(Transaction {
+            status: Default::default(),
+            client_id: 1,
+            tx_id: 2,
+            transaction_type: TransactionType::Deposit,
+            amount: Some(dec!(1)),
+        }))
+        .then_fails_with(|err: &anyhow::Error| {
+            matches!(
+                err.downcast_ref::<QuarantinedError>(),
+                Some(QuarantinedError {
+                    client_id: 1,
+                    tx_id: 2,
+                    source: GetError::Rehydrate { .. },
+                })
+            )
+        })
+        .assert_on(|event_store| {
+            Service::from(EventSourced::from(event_store)).with_quarantine(true)
+        })
+        .await;
+}
+
+fn transaction(
+    client_id: u16,
+    tx_id: u32,
+    transaction_type: TransactionType,
+    amount: Option<rust_decimal::Decimal>,
+) -> Envelope<Transaction> {
+    Envelope::from(Transaction {
+        status: Default::default(),
+        client_id,
+        tx_id,
+        transaction_type,
+        amount,
+    })
+}
+
+#[tokio::test]
+async fn it_rejects_withdrawal_exceeding_available_funds() {
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when(transaction(
+            1,
+            2,
+            TransactionType::Withdrawal,
+            Some(dec!(15)),
+        ))
+        .then_fails_with_error(BankAccountError::InsufficientFunds)
+        .then_state(1, |account: &Root<Account>| {
+            // the rejected withdrawal records no event
+            assert_eq!(1, account.version());
+            assert_eq!(dec!(10), account.snapshot().available());
+        })
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+#[tokio::test]
+#[should_panic(expected = "command 2 should have succeeded")]
+async fn it_fails_on_an_earlier_command_error() {
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when(transaction(
+            1,
+            2,
+            TransactionType::Withdrawal,
+            Some(dec!(15)),
+        ))
+        .when(transaction(1, 3, TransactionType::Deposit, Some(dec!(5))))
+        .then_state(1, |_: &Root<Account>| {})
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_continues_after_a_command_allowed_to_fail() {
+    Scenario
+        .when(transaction(1, 1, TransactionType::Deposit, Some(dec!(10))))
+        .when_may_fail(transaction(
+            1,
+            2,
+            TransactionType::Withdrawal,
+            Some(dec!(15)),
+        ))
+        .when(transaction(1, 3, TransactionType::Deposit, Some(dec!(5))))
+        .then_state(1, |account: &Root<Account>| {
+            assert_eq!(2, account.version());
+            assert_eq!(dec!(15), account.snapshot().available());
+        })
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_rejects_transactions_of_locked_account() {
+    Scenario
+        .given_csv("etc/locked.csv")
+        .when(transaction(1, 4, TransactionType::Deposit, Some(dec!(10))))
+        .then_fails_with_error(BankAccountError::LockedAccount { id: 1, tx: 4 })
+        .then_state(1, |account: &Root<Account>| {
+            let snapshot = account.snapshot();
+            assert!(snapshot.locked());
+            assert_eq!(dec!(-10), snapshot.total());
+        })
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
+
+#[tokio::test]
+async fn it_reports_the_corrupt_event_version() {
+    Scenario
+        .given(corrupt_account_stream())
+        .when(transaction(1, 2, TransactionType::Deposit, Some(dec!(1))))
+        .then_fails_with(|err: &anyhow::Error| {
+            matches!(
+                err.downcast_ref::<GetError>(),
+                Some(GetError::Rehydrate { version: 2, .. })
+            )
+        })
+        .assert_on(|event_store| Service::from(EventSourced::from(event_store)))
+        .await;
+}
@@ -0,0 +1,95 @@
+//! Proptest strategies and failure fixtures shared by the property-based tests.
+use std::path::PathBuf;
+
+use payments_engine_rs::domain::{Transaction, TransactionType};
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestError, TestRunner};
+use rust_decimal::Decimal;
+
+/// Few transaction ids, so that disputes, resolves, chargebacks, captures and voids find their
+/// transaction often enough.
+pub const TX_IDS: u32 = 8;
+pub const MAX_TRANSACTIONS: usize = 64;
+
+fn transaction_type() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        3 => Just(TransactionType::Deposit),
+        2 => Just(TransactionType::Withdrawal),
+        2 => Just(TransactionType::Dispute),
+        1 => Just(TransactionType::Resolve),
+        1 => Just(TransactionType::Chargeback),
+        1 => Just(TransactionType::Authorize),
+        1 => Just(TransactionType::Capture),
+        1 => Just(TransactionType::Void),
+    ]
+}
+
+/// Amounts with up to four decimal places, including a few negative ones.
+fn amount() -> impl Strategy<Value = Decimal> {
+    (-1_000i64..1_000_000).prop_map(|units| Decimal::new(units, 4))
+}
+
+/// Transactions of `clients` accounts, carrying an amount with the given probability.
+pub fn transaction(clients: u16, with_amount: f64) -> impl Strategy<Value = Transaction> {
+    (
+        transaction_type(),
+        1..=clients,
+        1..=TX_IDS,
+        option::weighted(with_amount, amount()),
+    )
+        .prop_map(|(transaction_type, client_id, tx_id, amount)| Transaction {
+            status: Default::default(),
+            client_id,
+            tx_id,
+            transaction_type,
+            amount,
+        })
+}
+
+/// Writes the transactions as a CSV fixture named `name` into `etc/`, returning its path.
+fn write_fixture(name: &str, transactions: &[Transaction]) -> PathBuf {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("etc")
+        .join(name);
+    let mut writer = csv::Writer::from_path(&path).expect("create fixture");
+    writer
+        .write_record(["type", "client", "tx", "amount"])
+        .expect("write fixture header");
+    for transaction in transactions {
+        writer
+            .serialize((
+                &transaction.transaction_type,
+                transaction.client_id,
+                transaction.tx_id,
+                transaction.amount,
+            ))
+            .expect("write fixture row");
+    }
+    writer.flush().expect("flush fixture");
+    path
+}
+
+/// Runs `test` against sequences of `transactions`, writing the minimal failing sequence as
+/// the `fixture` CSV for the CLI to replay.
+pub fn run(
+    transactions: impl Strategy<Value = Transaction>,
+    fixture: &str,
+    test: impl Fn(Vec<Transaction>) -> Result<(), TestCaseError>,
+) {
+    let mut runner = TestRunner::new(Config {
+        // failures are persisted as CSV fixtures instead
+        failure_persistence: None,
+        ..Config::default()
+    });
+
+    match runner.run(&vec(transactions, 1..MAX_TRANSACTIONS), test) {
+        Ok(()) => {}
+        Err(TestError::Fail(reason, minimal)) => panic!(
+            "{reason}\nminimal failing transactions written to {}",
+            write_fixture(fixture, &minimal).display()
+        ),
+        Err(error) => panic!("{error}"),
+    }
+}
@@ -0,0 +1,331 @@
+//! Differential testing of the engine against a reference model of the payment rules.
+//!
+//! The reference model keeps plain balances in hashmaps, without events, roots or repositories.
+//! The same transaction streams are processed by it and by the full [`Runtime`], [`Service`] and
+//! [`EventSourced`] stack, and the resulting account snapshots and rejected transactions must
+//! match. A diverging proptest sequence is shrunk and written as a transactions CSV into `etc/`,
+//! so it can be replayed with `cargo run -- etc/differential_failure.csv`.
+mod common;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error as StdError;
+use std::future::Future;
+use std::pin::Pin;
+
+use payments_engine_rs::core::{EventSourced, GetError, Getter, InMemory};
+use payments_engine_rs::domain::{
+    Account, AccountSnapShot, BankAccountRoot, Transaction, TransactionType,
+};
+use payments_engine_rs::generate::{Generator, Workload};
+use payments_engine_rs::runtime::{Read, Runtime, Service};
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use rust_decimal::Decimal;
+
+const CLIENTS: u16 = 3;
+
+/// A transaction, identified the way rejections are reported.
+type Rejected = (u16, u32, TransactionType);
+
+/// A deposit or withdrawal as the reference model remembers it.
+struct Movement {
+    amount: Decimal,
+    /// The part of the amount currently under dispute.
+    disputed: Decimal,
+    /// Whether a resolve settled the whole dispute, which can't be reopened by default.
+    resolved: bool,
+    charged_back: bool,
+}
+
+#[derive(Default)]
+struct Balances {
+    available: Decimal,
+    held: Decimal,
+    reserved: Decimal,
+    locked: bool,
+    movements: HashMap<u32, Movement>,
+    /// The remaining amount of every open authorization.
+    holds: HashMap<u32, Decimal>,
+    /// Every deposit, withdrawal and authorization id, captured or voided ones included.
+    seen: Vec<u32>,
+}
+
+impl Balances {
+    /// Applies the transaction, returning whether it is accepted.
+    fn apply(&mut self, transaction: &Transaction) -> bool {
+        if self.locked {
+            return false;
+        }
+        let tx = transaction.tx_id;
+        match transaction.transaction_type {
+            TransactionType::Deposit | TransactionType::Withdrawal | TransactionType::Authorize => {
+                let Some(amount) = transaction.amount else {
+                    return false;
+                };
+                let spending = transaction.transaction_type != TransactionType::Deposit;
+                if amount < Decimal::ZERO
+                    || (spending && self.available < amount)
+                    || self.seen.contains(&tx)
+                {
+                    return false;
+                }
+                self.seen.push(tx);
+                match transaction.transaction_type {
+                    TransactionType::Deposit => self.available += amount,
+                    TransactionType::Withdrawal => self.available -= amount,
+                    _ => {
+                        self.available -= amount;
+                        self.reserved += amount;
+                        self.holds.insert(tx, amount);
+                        return true;
+                    }
+                }
+                self.movements.insert(
+                    tx,
+                    Movement {
+                        amount,
+                        disputed: Decimal::ZERO,
+                        resolved: false,
+                        charged_back: false,
+                    },
+                );
+            }
+            TransactionType::Capture => {
+                let Some(remaining) = self.holds.get_mut(&tx) else {
+                    return false;
+                };
+                let amount = transaction.amount.unwrap_or(*remaining);
+                if amount < Decimal::ZERO || amount > *remaining {
+                    return false;
+                }
+                *remaining -= amount;
+                if remaining.is_zero() {
+                    self.holds.remove(&tx);
+                }
+                self.reserved -= amount;
+            }
+            TransactionType::Void => {
+                let Some(remaining) = self.holds.remove(&tx) else {
+                    return false;
+                };
+                self.reserved -= remaining;
+                self.available += remaining;
+            }
+            // fees are only charged by the engine
+            TransactionType::Fee => return false,
+            TransactionType::Dispute => {
+                let Some(movement) = self.movements.get_mut(&tx) else {
+                    return false;
+                };
+                let undisputed = movement.amount - movement.disputed;
+                let amount = transaction.amount.unwrap_or(undisputed);
+                if movement.charged_back
+                    || movement.resolved
+                    || amount <= Decimal::ZERO
+                    || amount > undisputed
+                {
+                    return false;
+                }
+                movement.disputed += amount;
+                self.available -= amount;
+                self.held += amount;
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                let Some(movement) = self.movements.get_mut(&tx) else {
+                    return false;
+                };
+                let disputed = movement.disputed;
+                let amount = transaction.amount.unwrap_or(disputed);
+                if amount <= Decimal::ZERO || amount > disputed {
+                    return false;
+                }
+                if transaction.transaction_type == TransactionType::Resolve {
+                    movement.disputed -= amount;
+                    movement.resolved = movement.disputed.is_zero();
+                    self.held -= amount;
+                    self.available += amount;
+                } else {
+                    // the part of the dispute that isn't charged back is released
+                    movement.disputed = Decimal::ZERO;
+                    movement.charged_back = true;
+                    self.held -= disputed;
+                    self.available += disputed - amount;
+                    self.locked = true;
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Processes the transactions with the reference model.
+fn reference(transactions: &[Transaction]) -> (Vec<AccountSnapShot>, Vec<Rejected>) {
+    let mut accounts: BTreeMap<u16, Balances> = BTreeMap::new();
+    let mut rejected = Vec::new();
+
+    for transaction in transactions {
+        let client = transaction.client_id;
+        let accepted = match accounts.get_mut(&client) {
+            Some(balances) => balances.apply(transaction),
+            // only a deposit opens an account
+            None if transaction.transaction_type == TransactionType::Deposit => {
+                let mut balances = Balances::default();
+                let opened = balances.apply(transaction);
+                if opened {
+                    accounts.insert(client, balances);
+                }
+                opened
+            }
+            None => false,
+        };
+        if !accepted {
+            rejected.push((
+                client,
+                transaction.tx_id,
+                transaction.transaction_type.clone(),
+            ));
+        }
+    }
+
+    let snapshots = accounts
+        .into_iter()
+        .map(|(client, balances)| {
+            AccountSnapShot::new(
+                client,
+                balances.available,
+                balances.held,
+                balances.reserved,
+                balances.locked,
+            )
+        })
+        .collect();
+    (snapshots, rejected)
+}
+
+/// Feeds a fixed stream of transactions to the [`Runtime`].
+struct Stream(VecDeque<Transaction>);
+
+impl Read for Stream {
+    type Request = Transaction;
+
+    fn recv(
+        &mut self,
+    ) -> Pin<
+        Box<
+            dyn Future<Output = Result<Self::Request, Box<dyn StdError + Send + Sync + 'static>>>
+                + Send
+                + '_,
+        >,
+    > {
+        let next = self.0.pop_front();
+        Box::pin(async move { next.ok_or_else(|| "end of stream".into()) })
+    }
+}
+
+/// Processes the transactions with the event-sourced engine.
+fn engine(
+    runtime: &tokio::runtime::Runtime,
+    transactions: &[Transaction],
+) -> (Vec<AccountSnapShot>, Vec<Rejected>) {
+    runtime.block_on(async {
+        let repository = EventSourced::<Account, _>::from(InMemory::default());
+        let engine = Runtime::new(Service::from(repository.clone()))
+            .with_rejections()
+            .with_connector(
+                "differential",
+                Stream(transactions.iter().cloned().collect()),
+            )
+            .expect("single connector")
+            .run()
+            .await
+            .expect("engine run");
+
+        let mut snapshots = Vec::new();
+        for id in engine.account_ids() {
+            match repository.get(id).await {
+                Ok(root) => snapshots.push(BankAccountRoot::from(root).snapshot()),
+                Err(GetError::NotFound) => {}
+                Err(err) => panic!("failed to get account {id}: {err}"),
+            }
+        }
+        let rejected = engine
+            .rejections()
+            .iter()
+            .map(|rejection| {
+                (
+                    rejection.client_id,
+                    rejection.tx_id,
+                    rejection.transaction_type.clone(),
+                )
+            })
+            .collect();
+        (snapshots, rejected)
+    })
+}
+
+fn compare(
+    runtime: &tokio::runtime::Runtime,
+    transactions: &[Transaction],
+) -> Result<(), TestCaseError> {
+    let (expected_snapshots, expected_rejected) = reference(transactions);
+    let (snapshots, rejected) = engine(runtime, transactions);
+    prop_assert_eq!(
+        rejected,
+        expected_rejected,
+        "the engine and the reference model rejected different transactions"
+    );
+    prop_assert_eq!(
+        snapshots,
+        expected_snapshots,
+        "the engine and the reference model ended with different accounts"
+    );
+    Ok(())
+}
+
+fn current_thread() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("tokio runtime")
+}
+
+#[test]
+fn engine_matches_reference_model_for_arbitrary_transactions() {
+    let runtime = current_thread();
+    common::run(
+        common::transaction(CLIENTS, 0.8),
+        "differential_failure.csv",
+        |transactions| compare(&runtime, &transactions),
+    );
+}
+
+#[test]
+fn engine_matches_reference_model_for_generated_workloads() {
+    let runtime = current_thread();
+    for seed in 0..8 {
+        let workload = Workload {
+            seed,
+            clients: 20,
+            transactions: 2_000,
+            invalid_rate: 0.05,
+            ..Workload::default()
+        };
+        // rows of unknown types never reach the engine, like with the CLI
+        let transactions: Vec<_> = Generator::new(workload)
+            .expect("valid workload")
+            .filter_map(|row| {
+                Some(Transaction {
+                    status: Default::default(),
+                    client_id: row.client,
+                    tx_id: row.tx,
+                    transaction_type: row.transaction_type.parse().ok()?,
+                    amount: row.amount,
+                })
+            })
+            .collect();
+
+        if let Err(error) = compare(&runtime, &transactions) {
+            panic!("seed {seed}: {error}");
+        }
+    }
+}
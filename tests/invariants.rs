@@ -0,0 +1,150 @@
+//! Property-based invariants of the [`Account`] aggregate.
+//!
+//! Arbitrary sequences of transactions are driven through [`BankAccountRoot`] the way the
+//! service dispatches them, and the account invariants are asserted after every step. A failing
+//! sequence is shrunk to a minimal one, written as a transactions CSV into `etc/` so it can be
+//! replayed with `cargo run -- etc/invariants_failure.csv`.
+mod common;
+
+use std::collections::HashMap;
+
+use payments_engine_rs::core::{Aggregate, Root};
+use payments_engine_rs::domain::{
+    Account, BankAccountError, BankAccountRoot, Transaction, TransactionEvent, TransactionType,
+};
+use payments_engine_rs::limits::DisputeRules;
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+use rust_decimal::Decimal;
+
+const CLIENTS: u16 = 2;
+
+/// An account along with every event it has recorded so far.
+struct Tracked {
+    root: BankAccountRoot,
+    events: Vec<TransactionEvent>,
+}
+
+fn dispatch(
+    root: &mut BankAccountRoot,
+    transaction: Transaction,
+    rules: &DisputeRules,
+) -> Result<(), BankAccountError> {
+    match transaction.transaction_type {
+        TransactionType::Deposit => root.deposit(transaction),
+        TransactionType::Withdrawal => root.withdrawal(transaction),
+        TransactionType::Dispute => root.dispute(transaction, rules),
+        TransactionType::Resolve => root.resolve(transaction),
+        TransactionType::Chargeback => root.chargeback(transaction),
+        TransactionType::Authorize => root.authorize(transaction),
+        TransactionType::Capture => root.capture(transaction),
+        TransactionType::Void => root.void(transaction),
+        TransactionType::Fee => Err(BankAccountError::UnexpectedFee(transaction.tx_id)),
+    }
+}
+
+fn check(transactions: &[Transaction]) -> Result<(), TestCaseError> {
+    let rules = DisputeRules::default();
+    let mut accounts: HashMap<u16, Tracked> = HashMap::new();
+
+    for (step, transaction) in transactions.iter().enumerate() {
+        let client = transaction.client_id;
+        let tracked = match accounts.get_mut(&client) {
+            Some(tracked) => {
+                let before = (tracked.root.snapshot(), tracked.root.version());
+                let result = dispatch(&mut tracked.root, transaction.clone(), &rules);
+                if before.0.locked() {
+                    prop_assert!(
+                        result.is_err(),
+                        "step {}: locked account {} accepted {:?}",
+                        step,
+                        client,
+                        transaction
+                    );
+                    prop_assert_eq!(
+                        &before,
+                        &(tracked.root.snapshot(), tracked.root.version()),
+                        "step {}: locked account {} changed",
+                        step,
+                        client
+                    );
+                }
+                tracked
+            }
+            // like the service, only a deposit opens an account
+            None if transaction.transaction_type == TransactionType::Deposit => {
+                match BankAccountRoot::open(transaction.clone()) {
+                    Ok(root) => accounts.entry(client).or_insert(Tracked {
+                        root,
+                        events: Vec::new(),
+                    }),
+                    Err(_) => continue,
+                }
+            }
+            None => continue,
+        };
+
+        // rejected transactions may still record events, e.g. declined disputes
+        tracked.events.extend(
+            tracked
+                .root
+                .take_uncommitted_events()
+                .into_iter()
+                .map(|event| event.message),
+        );
+
+        let snapshot = tracked.root.snapshot();
+        // authorized funds are reserved, and count towards the total like held ones
+        prop_assert_eq!(
+            snapshot.total(),
+            snapshot.available() + snapshot.held() + snapshot.reserved(),
+            "step {}: total of account {} is not the sum of its balances",
+            step,
+            client
+        );
+        prop_assert!(
+            snapshot.held() >= Decimal::ZERO,
+            "step {}: account {} holds {}",
+            step,
+            client,
+            snapshot.held()
+        );
+        prop_assert!(
+            snapshot.reserved() >= Decimal::ZERO,
+            "step {}: account {} reserves {}",
+            step,
+            client,
+            snapshot.reserved()
+        );
+
+        let rehydrated = tracked
+            .events
+            .iter()
+            .cloned()
+            .try_fold(None, |state, event| Account::apply(state, event).map(Some))
+            .map_err(|error| {
+                TestCaseError::fail(format!(
+                    "step {step}: rehydrating account {client}: {error}"
+                ))
+            })?
+            .expect("at least the opening event");
+        prop_assert_eq!(
+            &Root::rehydrate_from_state(tracked.events.len() as u64, rehydrated),
+            &*tracked.root,
+            "step {}: rehydrated account {} differs from the in-memory one",
+            step,
+            client
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn account_invariants_hold_for_arbitrary_transactions() {
+    common::run(
+        common::transaction(CLIENTS, 0.9),
+        "invariants_failure.csv",
+        |transactions| check(&transactions),
+    );
+}
@@ -89,3 +89,889 @@ fn same_tx_id_attempt() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn partner_column_mapping() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/partner_mapping.csv")
+        .arg("--mapping")
+        .arg("./etc/partner_mapping.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn authorization_holds() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/authorization.csv");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn authorization_holds_expire() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/authorization.csv")
+        .arg("--hold-expiry")
+        .arg("3");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn fee_schedule() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/fees.csv")
+        .arg("--fee-schedule")
+        .arg("./etc/fee_schedule.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn credit_limits() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/overdraft.csv")
+        .arg("--credit-limits")
+        .arg("./etc/credit_limits.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn withdrawal_policy() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/velocity.csv")
+        .arg("--withdrawal-policy")
+        .arg("./etc/withdrawal_policy.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn dispute_rules() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/dispute_rules.csv")
+        .arg("--dispute-rules")
+        .arg("./etc/dispute_rules.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn partial_disputes() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/partial_disputes.csv");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn archived_transactions() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/archive.csv");
+    let retained = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/archive.csv").arg("--archive-after").arg("2");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    assert_eq!(retained, stdout);
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn cached_accounts() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/transactions.csv");
+    let uncached = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    for capacity in ["1", "1000"] {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg("./etc/transactions.csv")
+            .arg("--cache-capacity")
+            .arg(capacity);
+        let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+        assert_eq!(uncached, stdout);
+    }
+
+    // every transaction loads its account once, from the cache or the event store
+    let summary = std::env::temp_dir().join(format!("cache-{}.json", std::process::id()));
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/transactions.csv")
+        .arg("--cache-capacity")
+        .arg("1000")
+        .arg("--summary-file")
+        .arg(&summary);
+    cmd.assert().success();
+    let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&summary)?)?;
+    std::fs::remove_file(&summary)?;
+    let cache = &written["cache"];
+    assert!(cache["hits"].as_u64() > Some(0), "{cache}");
+    assert_eq!(
+        written["rows_parsed"].as_u64(),
+        Some(
+            cache["hits"].as_u64().unwrap_or_default()
+                + cache["misses"].as_u64().unwrap_or_default()
+        ),
+        "{cache}"
+    );
+    assert_eq!(cache["stale"], 0);
+
+    Ok(())
+}
+
+#[test]
+fn process_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/basic.csv");
+    let default = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("process").arg("./etc/basic.csv");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    assert_eq!(default, stdout);
+
+    Ok(())
+}
+
+#[test]
+fn replay_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("replay").arg("./etc/events.jsonl");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn replay_persisted_event_log() -> Result<(), Box<dyn std::error::Error>> {
+    let event_log = std::env::temp_dir().join(format!("events-{}.jsonl", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/archive.csv")
+        .arg("--archive-after")
+        .arg("2")
+        .arg("--event-log")
+        .arg(&event_log);
+    let processed = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("replay").arg(&event_log);
+    let replayed = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+    std::fs::remove_file(&event_log)?;
+
+    assert_eq!(processed, replayed);
+
+    Ok(())
+}
+
+#[test]
+fn resume_archived_transactions() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir();
+    let event_log = dir.join(format!("archive-{}.jsonl", std::process::id()));
+    let early = dir.join(format!("archive-early-{}.csv", std::process::id()));
+    let late = dir.join(format!("archive-late-{}.csv", std::process::id()));
+    // the late rows dispute and charge back a transaction archived by the early ones
+    let rows = std::fs::read_to_string("./etc/archive.csv")?;
+    let (header, rows) = rows.split_once('\n').expect("header");
+    let (early_rows, late_rows) = rows.split_at(rows.find("dispute,1,2,").expect("late dispute"));
+    std::fs::write(&early, format!("{header}\n{early_rows}"))?;
+    std::fs::write(&late, format!("{header}\n{late_rows}"))?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/archive.csv").arg("--archive-after").arg("2");
+    let processed = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    for (input, resume) in [(&early, false), (&late, true)] {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg(input)
+            .arg("--archive-after")
+            .arg("2")
+            .arg("--event-log")
+            .arg(&event_log);
+        if resume {
+            cmd.arg("--resume");
+        }
+        let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+        if resume {
+            assert_eq!(processed, stdout);
+        }
+    }
+    for path in [&event_log, &early, &late] {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn resume_event_log() -> Result<(), Box<dyn std::error::Error>> {
+    let event_log = std::env::temp_dir().join(format!("resume-{}.jsonl", std::process::id()));
+    let process = |input: &str, resume: bool| -> Result<_, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg(input).arg("--event-log").arg(&event_log);
+        if resume {
+            cmd.arg("--resume");
+        }
+        Ok(String::from_utf8(
+            cmd.assert().success().get_output().stdout.clone(),
+        )?)
+    };
+    let replay = || -> Result<_, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg("replay").arg(&event_log);
+        Ok(String::from_utf8(
+            cmd.assert().success().get_output().stdout.clone(),
+        )?)
+    };
+
+    process("./etc/basic.csv", false)?;
+    let resumed = process("./etc/authorization.csv", true)?;
+    assert_eq!(resumed, replay()?);
+
+    // without --resume the event log only holds the events of the latest run
+    let overwritten = process("./etc/authorization.csv", false)?;
+    assert_eq!(overwritten, replay()?);
+    assert_ne!(resumed, overwritten);
+    std::fs::remove_file(&event_log)?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/basic.csv").arg("--resume");
+    cmd.assert().failure();
+
+    // events recorded before chaining would leave the new ones on a chain of their own
+    std::fs::copy("./etc/events.jsonl", &event_log)?;
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/basic.csv")
+        .arg("--event-log")
+        .arg(&event_log)
+        .arg("--resume");
+    let stderr = String::from_utf8(cmd.assert().failure().get_output().stderr.clone())?;
+    assert!(stderr.contains("event on line 1 has no link"), "{stderr}");
+    assert_eq!(
+        std::fs::read_to_string("./etc/events.jsonl")?,
+        std::fs::read_to_string(&event_log)?
+    );
+    std::fs::remove_file(&event_log)?;
+
+    Ok(())
+}
+
+#[test]
+fn inspect_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("inspect")
+        .arg("./etc/events.jsonl")
+        .arg("--client")
+        .arg("3");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn verify_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("verify")
+        .arg("./etc/events.jsonl")
+        .arg("./etc/accounts.csv");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn verify_command_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("verify")
+        .arg("./etc/events.jsonl")
+        .arg("./etc/accounts_mismatch.csv");
+    let stdout = String::from_utf8(cmd.assert().failure().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn verify_chain_command() -> Result<(), Box<dyn std::error::Error>> {
+    let event_log = std::env::temp_dir().join(format!("chain-{}.jsonl", std::process::id()));
+    let key = std::env::temp_dir().join(format!("chain-{}.key", std::process::id()));
+    let checkpoint = std::env::temp_dir().join(format!("chain-{}.json", std::process::id()));
+    std::fs::write(&key, "end of day")?;
+
+    // the second run resumes from the events of the first one and chains its own after them
+    for (input, resume) in [
+        ("./etc/basic.csv", false),
+        ("./etc/authorization.csv", true),
+    ] {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg(input).arg("--event-log").arg(&event_log);
+        if resume {
+            cmd.arg("--resume");
+        }
+        cmd.assert().success();
+    }
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("checkpoint")
+        .arg(&event_log)
+        .arg("--key-file")
+        .arg(&key);
+    std::fs::write(&checkpoint, &cmd.assert().success().get_output().stdout)?;
+
+    let verify_chain = |event_log: &std::path::Path| -> Result<_, Box<dyn std::error::Error>> {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg("verify-chain")
+            .arg(event_log)
+            .arg("--checkpoint")
+            .arg(&checkpoint)
+            .arg("--key-file")
+            .arg(&key);
+        Ok(cmd.assert())
+    };
+    let stdout = String::from_utf8(
+        verify_chain(&event_log)?
+            .success()
+            .get_output()
+            .stdout
+            .clone(),
+    )?;
+    insta::assert_snapshot!(stdout);
+
+    // a deposit of account 1 raised from 2 to 3
+    let events = std::fs::read_to_string(&event_log)?;
+    let tampered = events.replacen(r#""amount":"2","#, r#""amount":"3","#, 1);
+    assert_ne!(events, tampered);
+    std::fs::write(&event_log, tampered)?;
+    let stderr = String::from_utf8(
+        verify_chain(&event_log)?
+            .failure()
+            .get_output()
+            .stderr
+            .clone(),
+    )?;
+    assert!(
+        stderr.contains("event 2 of stream 1 doesn't match its stream chain"),
+        "{stderr}"
+    );
+
+    // the latest event removed, which leaves the chains intact but short of the checkpoint
+    let mut lines: Vec<_> = events.lines().collect();
+    let latest = lines
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, line)| {
+            serde_json::from_str::<serde_json::Value>(line).expect("event")["link"]["sequence"]
+                .as_u64()
+        })
+        .map(|(index, _)| index)
+        .expect("events");
+    lines.remove(latest);
+    std::fs::write(&event_log, lines.join("\n"))?;
+    let stderr = String::from_utf8(
+        verify_chain(&event_log)?
+            .failure()
+            .get_output()
+            .stderr
+            .clone(),
+    )?;
+    assert!(stderr.contains("before the checkpoint"), "{stderr}");
+
+    for file in [event_log, key, checkpoint] {
+        std::fs::remove_file(file)?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn dry_run() -> Result<(), Box<dyn std::error::Error>> {
+    let event_log = std::fs::read("./etc/events.jsonl")?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/dry_run.csv")
+        .arg("--event-log")
+        .arg("./etc/events.jsonl")
+        .arg("--dry-run");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    assert_eq!(event_log, std::fs::read("./etc/events.jsonl")?);
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn diff_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts.csv")
+        .arg("./etc/accounts_mismatch.csv");
+    let stdout = String::from_utf8(cmd.assert().code(1).get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn diff_command_replays_event_logs() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/events.jsonl")
+        .arg("./etc/accounts.json");
+    cmd.assert().code(0).stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn diff_command_reads_accounts_without_reserved_funds() -> Result<(), Box<dyn std::error::Error>> {
+    // accounts printed by engine versions without authorization holds
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts_v1.csv")
+        .arg("./etc/accounts.csv");
+    cmd.assert().code(0).stdout("");
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts_v1.csv")
+        .arg("./etc/accounts_mismatch.csv");
+    cmd.assert().code(1);
+
+    Ok(())
+}
+
+#[test]
+fn diff_command_tolerance() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts.csv")
+        .arg("./etc/accounts_drift.csv");
+    cmd.assert().code(1);
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts.csv")
+        .arg("./etc/accounts_drift.csv")
+        .arg("--tolerance")
+        .arg("0.001");
+    cmd.assert().code(0);
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts.csv")
+        .arg("./etc/accounts_drift.csv")
+        .arg("--decimal-places")
+        .arg("2");
+    cmd.assert().code(0);
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff")
+        .arg("./etc/accounts.csv")
+        .arg("./etc/basic.toml");
+    cmd.assert().code(2);
+
+    Ok(())
+}
+
+#[test]
+fn metrics_file() -> Result<(), Box<dyn std::error::Error>> {
+    let metrics = std::env::temp_dir().join(format!("metrics-{}.prom", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/locked.csv")
+        .arg("--metrics-file")
+        .arg(&metrics);
+    cmd.assert().success();
+    let exported = std::fs::read_to_string(&metrics)?;
+    std::fs::remove_file(&metrics)?;
+
+    for line in [
+        r#"payments_transactions_processed_total{type="deposit"} 1"#,
+        r#"payments_transactions_processed_total{type="chargeback"} 1"#,
+        r#"payments_transactions_rejected_total{type="deposit",error="LockedAccount"} 1"#,
+        r#"payments_handle_duration_seconds_count{type="deposit"} 2"#,
+        r#"payments_store_append_duration_seconds_count{aggregate="Account"} 4"#,
+        r#"payments_queue_depth{queue="dispatch"} 0"#,
+    ] {
+        assert!(
+            exported.lines().any(|exported| exported == line),
+            "missing `{line}` in\n{exported}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn run_summary() -> Result<(), Box<dyn std::error::Error>> {
+    let summary = std::env::temp_dir().join(format!("summary-{}.json", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/locked.csv")
+        .arg("--summary")
+        .arg("--summary-file")
+        .arg(&summary);
+    let output = cmd.assert().success().get_output().clone();
+    let written: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&summary)?)?;
+    std::fs::remove_file(&summary)?;
+
+    // the accounts CSV on stdout is left as is
+    insta::assert_snapshot!("trigger_locked", String::from_utf8(output.stdout)?);
+    let stderr = String::from_utf8(output.stderr)?;
+    for line in [
+        "  accepted:         chargeback=1 deposit=1 dispute=1 withdrawal=1",
+        "  rejections:       LockedAccount=1",
+        "  charged back:     10",
+    ] {
+        assert!(
+            stderr.lines().any(|printed| printed == line),
+            "missing `{line}` in\n{stderr}"
+        );
+    }
+
+    assert_eq!(written["rows_read"], 5);
+    assert!(written.get("cache").is_none());
+    assert_eq!(written["rows_parsed"], 5);
+    assert_eq!(
+        written["accepted"],
+        serde_json::json!({"chargeback": 1, "deposit": 1, "dispute": 1, "withdrawal": 1})
+    );
+    assert_eq!(written["rejected"], serde_json::json!({"deposit": 1}));
+    assert_eq!(
+        written["rejections"],
+        serde_json::json!({"LockedAccount": 1})
+    );
+    assert_eq!(written["quarantined"], serde_json::json!({}));
+    assert_eq!(written["accounts_opened"], 1);
+    assert_eq!(written["accounts_locked"], 1);
+    assert_eq!(written["deposited"], "10");
+    assert_eq!(written["withdrawn"], "10");
+    assert_eq!(written["captured"], "0");
+    assert_eq!(written["held"], "0");
+    assert_eq!(written["charged_back"], "10");
+    assert!(written["wall_time_seconds"]
+        .as_f64()
+        .is_some_and(|secs| secs > 0.0));
+    assert!(written["throughput"]
+        .as_f64()
+        .is_some_and(|rows| rows > 0.0));
+
+    Ok(())
+}
+
+#[test]
+fn captured_summary() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/authorization.csv").arg("--summary");
+    let output = cmd.assert().success().get_output().clone();
+
+    // captures move reserved funds out of the accounts, apart from the withdrawals
+    let stderr = String::from_utf8(output.stderr)?;
+    for line in [
+        "  accepted:         authorize=4 capture=2 deposit=7 void=1",
+        "  withdrawn:        0",
+        "  captured:         25",
+    ] {
+        assert!(
+            stderr.lines().any(|printed| printed == line),
+            "missing `{line}` in\n{stderr}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn otlp_export() -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    // a stand-in for an OTLP/HTTP collector, recording the path and body of every request
+    let collector = std::net::TcpListener::bind("127.0.0.1:0")?;
+    collector.set_nonblocking(true)?;
+    let endpoint = format!("http://{}", collector.local_addr()?);
+    let done = Arc::new(AtomicBool::new(false));
+    let stop = done.clone();
+    let requests = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        while !stop.load(Ordering::SeqCst) {
+            let Ok((stream, _)) = collector.accept() else {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                continue;
+            };
+            stream.set_nonblocking(false).expect("blocking stream");
+            let mut reader = BufReader::new(stream);
+            // serve the requests of the connection until the exporter closes it
+            loop {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                    break;
+                }
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).expect("request header");
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().expect("content length");
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).expect("request body");
+                reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 2\r\n\r\n{}")
+                    .expect("response");
+                requests.push((request_line, String::from_utf8_lossy(&body).into_owned()));
+            }
+        }
+        requests
+    });
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/basic.csv")
+        .arg("--otlp-endpoint")
+        .arg(&endpoint);
+    let assert = cmd.assert().success();
+    done.store(true, Ordering::SeqCst);
+    let requests = requests.join().expect("collector");
+
+    insta::assert_snapshot!(
+        "basic",
+        String::from_utf8(assert.get_output().stdout.clone())?
+    );
+    assert!(
+        requests
+            .iter()
+            .all(|(request_line, _)| request_line.starts_with("POST /v1/traces ")),
+        "unexpected requests {requests:?}"
+    );
+    let spans: Vec<serde_json::Value> = requests
+        .iter()
+        .map(|(_, body)| serde_json::from_str::<serde_json::Value>(body))
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .flat_map(|body| {
+            body["resourceSpans"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+        })
+        .flat_map(|resource| {
+            resource["scopeSpans"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default()
+        })
+        .flat_map(|scope| scope["spans"].as_array().cloned().unwrap_or_default())
+        .collect();
+    let names: std::collections::BTreeSet<_> = spans
+        .iter()
+        .filter_map(|span| span["name"].as_str())
+        .collect();
+    assert_eq!(
+        std::collections::BTreeSet::from([
+            "append",
+            "decide",
+            "load",
+            "save",
+            "stream",
+            "transaction"
+        ]),
+        names
+    );
+
+    // one transaction span per row, carrying the transaction
+    let transactions: Vec<_> = spans
+        .iter()
+        .filter(|span| span["name"] == "transaction")
+        .collect();
+    assert_eq!(5, transactions.len());
+    for span in transactions {
+        let keys: Vec<_> = span["attributes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|attribute| attribute["key"].as_str())
+            .collect();
+        for key in ["client", "tx", "type"] {
+            assert!(keys.contains(&key), "missing {key} in {span}");
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn config_show() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.env("PAYMENTS_RUNTIME__DISPATCH_CHANNEL_SIZE", "32")
+        .env("PAYMENTS_OUTPUT__FORMAT", "csv")
+        .arg("--config")
+        .arg("./etc/config.toml")
+        .arg("--hold-expiry")
+        .arg("5")
+        .arg("config")
+        .arg("show");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn negated_flags_override_the_configuration() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.env("PAYMENTS_ERRORS__CORRUPT_STREAM", "quarantine")
+        .env("PAYMENTS_HOLD_EXPIRY", "5")
+        .arg("--no-quarantine")
+        .arg("config")
+        .arg("show");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    assert!(stdout.contains(r#"corrupt_stream = "abort""#), "{stdout}");
+    // settings are only read from the environment through their configuration key
+    assert!(!stdout.contains("hold_expiry"), "{stdout}");
+
+    Ok(())
+}
+
+#[test]
+fn process_flags_before_a_command() -> Result<(), Box<dyn std::error::Error>> {
+    for command in [
+        ["process", "./etc/basic.csv"],
+        ["replay", "./etc/events.jsonl"],
+    ] {
+        let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+        cmd.arg("--hold-expiry").arg("5").args(command);
+        let stderr = String::from_utf8(cmd.assert().code(2).get_output().stderr.clone())?;
+        assert!(
+            stderr.contains(&format!(
+                "the argument '--hold-expiry' cannot be used before the '{}' command",
+                command[0]
+            )),
+            "{stderr}"
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn config_file() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("./etc/fees.csv")
+        .arg("--config")
+        .arg("./etc/config.toml");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn generate_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("generate")
+        .arg("--seed")
+        .arg("42")
+        .arg("--clients")
+        .arg("3")
+        .arg("--transactions")
+        .arg("20")
+        .arg("--dispute-rate")
+        .arg("0.3")
+        .arg("--invalid-rate")
+        .arg("0.1");
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone())?;
+
+    insta::assert_snapshot!(stdout);
+
+    Ok(())
+}
+
+#[test]
+fn generate_expected_accounts() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir();
+    let workload = dir.join(format!("workload-{}.csv", std::process::id()));
+    let expected = dir.join(format!("expected-{}.csv", std::process::id()));
+    let accounts = dir.join(format!("accounts-{}.csv", std::process::id()));
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("generate")
+        .arg("--seed")
+        .arg("7")
+        .arg("--clients")
+        .arg("20")
+        .arg("--transactions")
+        .arg("5000")
+        .arg("--dispute-rate")
+        .arg("0.2")
+        .arg("--invalid-rate")
+        .arg("0.05")
+        .arg("--output")
+        .arg(&workload)
+        .arg("--expected")
+        .arg(&expected);
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg(&workload);
+    std::fs::write(&accounts, &cmd.assert().success().get_output().stdout)?;
+
+    let mut cmd = Command::cargo_bin("payments-engine-rs")?;
+    cmd.arg("diff").arg(&expected).arg(&accounts);
+    let assert = cmd.assert();
+    for path in [workload, expected, accounts] {
+        std::fs::remove_file(path)?;
+    }
+    assert.success();
+
+    Ok(())
+}